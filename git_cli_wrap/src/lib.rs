@@ -1,7 +1,28 @@
+use std::fmt;
 use std::process::Command;
+use std::str::FromStr;
 
 #[derive(Debug)]
-pub struct GitError {}
+pub struct GitError {
+	/// What went wrong, for showing to the user
+	pub message: String,
+}
+
+impl GitError {
+	fn new(message: impl Into<String>) -> Self {
+		Self {
+			message: message.into(),
+		}
+	}
+}
+
+impl fmt::Display for GitError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.message)
+	}
+}
+
+impl std::error::Error for GitError {}
 
 #[derive(Debug, Hash, Eq, PartialEq, Clone, Copy)]
 pub struct Sha1Hash([u8; 40]);
@@ -96,9 +117,183 @@ impl ShowEntry {
 	}
 }
 
+/// Name, email and date of an author or committer
+#[derive(Debug, Clone, Default)]
+pub struct Signature {
+	pub name: String,
+	pub email: String,
+	/// As git prints it for `%ai`, like `2024-05-01 12:34:56 +0200`
+	pub date: String,
+}
+
+/// Result of verifying a commit's GPG signature, as reported by `%G?`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureStatus {
+	Good,
+	Bad,
+	/// Good signature with unknown validity
+	Unknown,
+	/// Good signature which has expired
+	Expired,
+	/// Good signature made by an expired key
+	ExpiredKey,
+	/// Good signature made by a revoked key
+	RevokedKey,
+	/// The signature can't be checked, usually because the key is missing
+	CannotCheck,
+}
+
+/// Parses `%G?`. Unsigned commits, `N`, are an error like any other letter.
+impl FromStr for SignatureStatus {
+	type Err = GitError;
+
+	fn from_str(status: &str) -> Result<Self, GitError> {
+		match status {
+			"G" => Ok(SignatureStatus::Good),
+			"B" => Ok(SignatureStatus::Bad),
+			"U" => Ok(SignatureStatus::Unknown),
+			"X" => Ok(SignatureStatus::Expired),
+			"Y" => Ok(SignatureStatus::ExpiredKey),
+			"R" => Ok(SignatureStatus::RevokedKey),
+			"E" => Ok(SignatureStatus::CannotCheck),
+			_ => Err(GitError::new(format!(
+				"not a signature status: {:?}",
+				status
+			))),
+		}
+	}
+}
+
+impl fmt::Display for SignatureStatus {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			SignatureStatus::Good => "Good signature",
+			SignatureStatus::Bad => "Bad signature",
+			SignatureStatus::Unknown => "Good signature, unknown validity",
+			SignatureStatus::Expired => "Good signature, expired",
+			SignatureStatus::ExpiredKey => "Good signature, expired key",
+			SignatureStatus::RevokedKey => "Good signature, revoked key",
+			SignatureStatus::CannotCheck => "Signature can't be checked",
+		})
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct GpgSignature {
+	pub status: SignatureStatus,
+	pub signer: String,
+	pub key: String,
+}
+
+/// A `Key: value` line at the end of a commit message, like `Signed-off-by`
+#[derive(Debug, Clone)]
+pub struct Trailer {
+	pub key: String,
+	pub value: String,
+}
+
+/// Totals of `git diff --stat`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiffStat {
+	pub files_changed: usize,
+	pub insertions: usize,
+	pub deletions: usize,
+}
+
+impl DiffStat {
+	/// Adds one line of `--numstat` output. Binary files count as changed without any lines.
+	fn add_numstat_line(&mut self, line: &str) {
+		let mut parts = line.splitn(3, '\t');
+		let (Some(insertions), Some(deletions), Some(_path)) =
+			(parts.next(), parts.next(), parts.next())
+		else {
+			return;
+		};
+
+		let parse = |count: &str| match count {
+			"-" => Some(0),
+			count => count.parse::<usize>().ok(),
+		};
+		let (Some(insertions), Some(deletions)) = (parse(insertions), parse(deletions)) else {
+			return;
+		};
+
+		self.files_changed += 1;
+		self.insertions += insertions;
+		self.deletions += deletions;
+	}
+}
+
+/// Summary in the words of `git diff --stat`
+impl fmt::Display for DiffStat {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let plural = |count: usize, one: &str, many: &str| {
+			format!("{} {}", count, if count == 1 { one } else { many })
+		};
+		write!(
+			f,
+			"{}, {}, {}",
+			plural(self.files_changed, "file changed", "files changed"),
+			plural(self.insertions, "insertion(+)", "insertions(+)"),
+			plural(self.deletions, "deletion(-)", "deletions(-)")
+		)
+	}
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CommitDetails {
+	pub sha1: String,
+	pub parents: Vec<String>,
+	/// Branches and tags pointing at the commit, like `HEAD -> main` or `tag: v1.0`
+	pub refs: Vec<String>,
+	pub author: Signature,
+	pub committer: Signature,
+	/// The full message, including the subject line and any trailers
+	pub message: String,
+	pub trailers: Vec<Trailer>,
+	pub signature: Option<GpgSignature>,
+	pub diff_stat: DiffStat,
+}
+
+impl CommitDetails {
+	pub fn subject(&self) -> &str {
+		self.message.lines().next().unwrap_or("")
+	}
+
+	/// The message after the subject line, without the trailers which end it
+	pub fn body(&self) -> &str {
+		let body = self
+			.message
+			.split_once('\n')
+			.map_or("", |(_subject, body)| body)
+			.trim_end();
+
+		// Trailers make up the last paragraph of the message
+		let body = match body.rsplit_once("\n\n") {
+			Some((rest, last_paragraph))
+				if !self.trailers.is_empty() && self.is_trailer_block(last_paragraph) =>
+			{
+				rest
+			}
+			_ => body,
+		};
+		body.trim()
+	}
+
+	fn is_trailer_block(&self, paragraph: &str) -> bool {
+		paragraph.lines().all(|line| {
+			line.starts_with(char::is_whitespace)
+				|| self.trailers.iter().any(|trailer| {
+					line.split_once(':')
+						.is_some_and(|(key, _)| key.trim().eq_ignore_ascii_case(&trailer.key))
+				})
+		})
+	}
+}
+
 #[derive(Debug)]
 pub struct GitShow {
-	pub description: String,
+	pub commit: CommitDetails,
 	pub entries: Vec<ShowEntry>,
 }
 
@@ -219,7 +414,8 @@ pub struct DiffOptions {
 	pub merge_base: bool,
 	pub cached: bool,
 	pub commit: Option<String>,
-	// pub path: Option<String>, // TODO
+	/// Limits the diff to these paths, when there are any
+	pub paths: Vec<String>,
 }
 
 pub fn get_diff(options: &DiffOptions) -> Result<GitDiff, GitError> {
@@ -238,6 +434,8 @@ pub fn get_diff(options: &DiffOptions) -> Result<GitDiff, GitError> {
 		command.arg(commit);
 	}
 
+	command.arg("--").args(&options.paths);
+
 	println!("Command: {:?}", command);
 
 	let output = command.output().expect("failed to execute process");
@@ -249,7 +447,7 @@ pub fn get_diff(options: &DiffOptions) -> Result<GitDiff, GitError> {
 			"{}",
 			String::from_utf8(output.stderr).expect("Invalid utf-8")
 		);
-		return Err(GitError {});
+		return Err(GitError::new("git diff failed"));
 	}
 
 	let output_string = String::from_utf8(output.stdout).expect("Invalid utf-8");
@@ -263,9 +461,15 @@ pub fn get_diff(options: &DiffOptions) -> Result<GitDiff, GitError> {
 }
 
 pub fn get_file_contents(path: &std::path::Path, sha1: &Sha1Hash) -> Result<String, GitError> {
+	let bytes = get_file_bytes(path, sha1)?;
+	Ok(String::from_utf8(bytes).expect("Invalid utf-8"))
+}
+
+/// Contents of a blob after the filters for `path`, which may not be text
+pub fn get_file_bytes(path: &std::path::Path, sha1: &Sha1Hash) -> Result<Vec<u8>, GitError> {
 	// Null/Empty file case
 	if sha1 == &Sha1Hash(['0' as u8; 40]) {
-		return Ok("".to_string());
+		return Ok(Vec::new());
 	}
 
 	let output = Command::new("git")
@@ -283,17 +487,25 @@ pub fn get_file_contents(path: &std::path::Path, sha1: &Sha1Hash) -> Result<Stri
 			"{}",
 			String::from_utf8(output.stderr).expect("Invalid utf-8")
 		);
-		return Err(GitError {});
+		return Err(GitError::new("git cat-file failed"));
 	}
 
-	Ok(String::from_utf8(output.stdout).expect("Invalid utf-8"))
+	Ok(output.stdout)
 }
 
+/// Commit fields requested from `git show`, separated by NUL since the message may contain
+/// anything else. The raw and numstat output follows the last one.
+const SHOW_FORMAT: &str = "%H%x00%P%x00%D%x00%an%x00%ae%x00%ai%x00%cn%x00%ce%x00%ci%x00\
+	%G?%x00%GS%x00%GK%x00%B%x00%(trailers:only,unfold)%x00";
+const SHOW_FIELD_COUNT: usize = 14;
+
 pub fn show(commit: &str) -> Result<GitShow, GitError> {
 	let output = Command::new("git")
 		.arg("show")
 		.arg("--abbrev=40")
 		.arg("--raw")
+		.arg("--numstat")
+		.arg(format!("--format={}", SHOW_FORMAT))
 		.arg(commit)
 		.output()
 		.expect("failed to execute process");
@@ -305,28 +517,141 @@ pub fn show(commit: &str) -> Result<GitShow, GitError> {
 			"{}",
 			String::from_utf8(output.stderr).expect("Invalid utf-8")
 		);
-		return Err(GitError {});
+		return Err(GitError::new("git show failed"));
 	}
 
 	let output_string = String::from_utf8(output.stdout).expect("Invalid utf-8");
 
-	let mut description = String::new();
-	let mut entries = Vec::new();
+	parse_show(&output_string)
+}
+
+fn parse_show(output: &str) -> Result<GitShow, GitError> {
+	let mut fields = output.splitn(SHOW_FIELD_COUNT + 1, '\0');
+	let mut next_field = |name: &str| {
+		fields.next().ok_or_else(|| {
+			GitError::new(format!(
+				"git show output ended before the commit's {}",
+				name
+			))
+		})
+	};
+
+	let sha1 = next_field("hash")?.to_string();
+	let parents = next_field("parents")?
+		.split_whitespace()
+		.map(str::to_string)
+		.collect();
+	let refs = next_field("refs")?
+		.split(", ")
+		.filter(|name| !name.is_empty())
+		.map(str::to_string)
+		.collect();
+	let author = Signature {
+		name: next_field("author name")?.to_string(),
+		email: next_field("author email")?.to_string(),
+		date: next_field("author date")?.to_string(),
+	};
+	let committer = Signature {
+		name: next_field("committer name")?.to_string(),
+		email: next_field("committer email")?.to_string(),
+		date: next_field("committer date")?.to_string(),
+	};
+	let signature_status = next_field("signature status")?;
+	let signer = next_field("signer")?;
+	let key = next_field("signing key")?;
+	// Unsigned commits have no status, and neither do ones of a kind git added since
+	let signature = signature_status.parse().ok().map(|status| GpgSignature {
+		status,
+		signer: signer.to_string(),
+		key: key.to_string(),
+	});
+	let message = next_field("message")?.trim_end().to_string();
+	let trailers = next_field("trailers")?
+		.lines()
+		.filter_map(|line| line.split_once(':'))
+		.map(|(key, value)| Trailer {
+			key: key.trim().to_string(),
+			value: value.trim().to_string(),
+		})
+		.collect();
 
-	for line in output_string.lines() {
-		if !line.starts_with(':') {
-			description.push_str(line);
-		} else {
+	let mut entries = Vec::new();
+	let mut diff_stat = DiffStat::default();
+	for line in next_field("changed files")?.lines() {
+		if line.starts_with(':') {
 			entries.push(ShowEntry::from_line(line));
+		} else if !line.is_empty() {
+			diff_stat.add_numstat_line(line);
 		}
 	}
 
 	Ok(GitShow {
-		description,
+		commit: CommitDetails {
+			sha1,
+			parents,
+			refs,
+			author,
+			committer,
+			message,
+			trailers,
+			signature,
+			diff_stat,
+		},
 		entries,
 	})
 }
 
+/// Whether the current directory is inside a repository's working tree
+pub fn is_inside_work_tree() -> bool {
+	Command::new("git")
+		.arg("rev-parse")
+		.arg("--is-inside-work-tree")
+		.output()
+		.is_ok_and(|output| output.status.success() && output.stdout.starts_with(b"true"))
+}
+
+/// Checks that `rev` names a commit, without printing anything when it doesn't
+pub fn verify_commit(rev: &str) -> Result<(), GitError> {
+	let output = Command::new("git")
+		.arg("rev-parse")
+		.arg("--verify")
+		.arg("--quiet")
+		.arg(format!("{}^{{commit}}", rev))
+		.output()
+		.map_err(|error| GitError::new(format!("couldn't run git: {}", error)))?;
+
+	if !output.status.success() {
+		return Err(GitError::new(format!("not a commit: {}", rev)));
+	}
+	Ok(())
+}
+
+/// Value of a gitattributes attribute for `path`, or `None` when it is unspecified, set or unset
+/// rather than given a value
+pub fn check_attr(attribute: &str, path: &std::path::Path) -> Result<Option<String>, GitError> {
+	let output = Command::new("git")
+		.arg("check-attr")
+		.arg("-z")
+		.arg(attribute)
+		.arg("--")
+		.arg(path)
+		.output()
+		.map_err(|error| GitError::new(format!("couldn't run git: {}", error)))?;
+
+	// Outside a repository there are no attributes, which isn't worth reporting
+	if !output.status.success() {
+		return Err(GitError::new("git check-attr failed"));
+	}
+
+	// Output is `<path> NUL <attribute> NUL <value> NUL`
+	let output_string = String::from_utf8_lossy(&output.stdout);
+	let value = output_string.split('\0').nth(2).unwrap_or("");
+	Ok(match value {
+		"" | "unspecified" | "set" | "unset" => None,
+		value => Some(value.to_string()),
+	})
+}
+
 pub fn stage_file(path: &str) -> Result<(), GitError> {
 	let output = Command::new("git")
 		.arg("add")
@@ -342,7 +667,7 @@ pub fn stage_file(path: &str) -> Result<(), GitError> {
 			"{}",
 			String::from_utf8(output.stderr).expect("Invalid utf-8")
 		);
-		return Err(GitError {});
+		return Err(GitError::new("git add failed"));
 	}
 
 	Ok(())
@@ -364,8 +689,161 @@ pub fn unstage_file(path: &str) -> Result<(), GitError> {
 			"{}",
 			String::from_utf8(output.stderr).expect("Invalid utf-8")
 		);
-		return Err(GitError {});
+		return Err(GitError::new("git restore failed"));
 	}
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn commit(message: &str, trailers: &[(&str, &str)]) -> CommitDetails {
+		CommitDetails {
+			message: message.to_string(),
+			trailers: trailers
+				.iter()
+				.map(|(key, value)| Trailer {
+					key: key.to_string(),
+					value: value.to_string(),
+				})
+				.collect(),
+			..CommitDetails::default()
+		}
+	}
+
+	#[test]
+	fn subject_and_body() {
+		let commit = commit(
+			"Fix the parser\n\nIt read past the end.\n\nSigned-off-by: A <a@example.com>\n",
+			&[("Signed-off-by", "A <a@example.com>")],
+		);
+		assert_eq!(commit.subject(), "Fix the parser");
+		assert_eq!(commit.body(), "It read past the end.");
+	}
+
+	#[test]
+	fn body_keeps_a_last_paragraph_which_isnt_trailers() {
+		let untrailed = commit("Subject\n\nFirst.\n\nSecond: not a trailer\n", &[]);
+		assert_eq!(untrailed.body(), "First.\n\nSecond: not a trailer");
+
+		// Some lines of the last paragraph aren't trailers git found
+		let trailed = commit(
+			"Subject\n\nFirst.\n\nReviewed-by: B\nSee also: the docs\n",
+			&[("Reviewed-by", "B")],
+		);
+		assert_eq!(
+			trailed.body(),
+			"First.\n\nReviewed-by: B\nSee also: the docs"
+		);
+
+		// Folded trailer lines and keys in other cases still make a trailer block
+		let folded = commit(
+			"Subject\n\nFirst.\n\nreviewed-by: B\n  and C\n",
+			&[("Reviewed-by", "B and C")],
+		);
+		assert!(folded.is_trailer_block("reviewed-by: B\n  and C"));
+		assert_eq!(folded.body(), "First.");
+
+		assert_eq!(commit("Subject only", &[]).body(), "");
+	}
+
+	#[test]
+	fn numstat_lines_add_up() {
+		let mut diff_stat = DiffStat::default();
+		diff_stat.add_numstat_line("3\t1\tsrc/main.rs");
+		diff_stat.add_numstat_line("-\t-\timage.png");
+		diff_stat.add_numstat_line("0\t2\tpath with\ttab");
+		diff_stat.add_numstat_line("not numstat");
+		assert_eq!(diff_stat.files_changed, 3);
+		assert_eq!(diff_stat.insertions, 3);
+		assert_eq!(diff_stat.deletions, 3);
+		assert_eq!(
+			diff_stat.to_string(),
+			"3 files changed, 3 insertions(+), 3 deletions(-)"
+		);
+	}
+
+	#[test]
+	fn signature_status_letters() {
+		let statuses = [
+			("G", SignatureStatus::Good),
+			("B", SignatureStatus::Bad),
+			("U", SignatureStatus::Unknown),
+			("X", SignatureStatus::Expired),
+			("Y", SignatureStatus::ExpiredKey),
+			("R", SignatureStatus::RevokedKey),
+			("E", SignatureStatus::CannotCheck),
+		];
+		for (letter, status) in statuses {
+			assert_eq!(letter.parse::<SignatureStatus>().unwrap(), status);
+		}
+		assert!("N".parse::<SignatureStatus>().is_err());
+		assert!("".parse::<SignatureStatus>().is_err());
+	}
+
+	#[test]
+	fn parse_show_output() {
+		let hash = "a".repeat(40);
+		let fields = [
+			hash.as_str(),
+			"b1 c2",
+			"HEAD -> main, tag: v1.0",
+			"Author",
+			"author@example.com",
+			"2024-05-01 12:34:56 +0200",
+			"Committer",
+			"committer@example.com",
+			"2024-05-02 08:00:00 +0000",
+			"G",
+			"Signer <signer@example.com>",
+			"ABCDEF",
+			"Subject\n\nBody: with a colon\n\nSigned-off-by: Author\n",
+			"Signed-off-by: Author\n",
+		];
+		// git puts a blank line between the format and the raw and numstat output
+		let output = format!(
+			"{}\0\n:100644 100644 {} {} M\tsrc/lib.rs\n\n2\t1\tsrc/lib.rs\n-\t-\tlogo.png\n",
+			fields.join("\0"),
+			"1".repeat(40),
+			"2".repeat(40)
+		);
+
+		let show = parse_show(&output).unwrap();
+		let commit = &show.commit;
+		assert_eq!(commit.sha1, hash);
+		assert_eq!(commit.parents, ["b1", "c2"]);
+		assert_eq!(commit.refs, ["HEAD -> main", "tag: v1.0"]);
+		assert_eq!(commit.author.email, "author@example.com");
+		assert_eq!(commit.committer.date, "2024-05-02 08:00:00 +0000");
+		let signature = commit.signature.as_ref().unwrap();
+		assert_eq!(signature.status, SignatureStatus::Good);
+		assert_eq!(signature.key, "ABCDEF");
+		assert_eq!(commit.subject(), "Subject");
+		assert_eq!(commit.body(), "Body: with a colon");
+		assert_eq!(commit.trailers.len(), 1);
+		assert_eq!(commit.trailers[0].value, "Author");
+		assert_eq!(commit.diff_stat.files_changed, 2);
+		assert_eq!(commit.diff_stat.insertions, 2);
+
+		assert_eq!(show.entries.len(), 1);
+		assert_eq!(show.entries[0].right_sha1.0, [b'2'; 40]);
+	}
+
+	#[test]
+	fn parse_show_of_unsigned_commit_or_cut_short_output() {
+		let output = "h\0\0\0A\0a@x\0d\0C\0c@x\0d\0N\0\0\0Subject\n\0\0";
+		let show = parse_show(output).unwrap();
+		assert!(show.commit.parents.is_empty());
+		assert!(show.commit.refs.is_empty());
+		assert!(show.commit.signature.is_none());
+		assert!(show.entries.is_empty());
+
+		let error = parse_show("h\0p\0refs").unwrap_err();
+		assert_eq!(
+			error.message,
+			"git show output ended before the commit's author name"
+		);
+	}
+}
@@ -0,0 +1,115 @@
+// Compares shaping every visible row on each frame with reusing layouts from the
+// LineLayoutCache, which ShapedLineCache keeps its rows in, while scrolling through a large file
+// a few rows at a time. Rows are shaped by the platform's text system with a monospace font, the
+// same shaping DiffElement asks the window for.
+use assets::Assets;
+use criterion::{criterion_group, criterion_main, Criterion};
+use gpui::{font, px, AssetSource, FontId, FontRun, LineLayout, Pixels, PlatformTextSystem};
+use std::hint::black_box;
+use std::sync::Arc;
+
+#[path = "../src/views/diff_pane/line_cache.rs"]
+#[allow(dead_code)]
+mod line_cache;
+
+use line_cache::LineLayoutCache;
+
+const TOTAL_ROWS: usize = 20_000;
+const VISIBLE_ROWS: usize = 60;
+const SCROLL_STEP: usize = 3;
+const FONT_SIZE: Pixels = px(14.);
+
+fn make_lines() -> Vec<String> {
+	(0..TOTAL_ROWS)
+		.map(|ix| {
+			format!(
+				"\tlet value_{ix} = compute_something(&input[{ix}..], {}, \"literal\"); // row {ix}",
+				ix * 7
+			)
+		})
+		.collect()
+}
+
+/// The platform's text system with the bundled fonts loaded, like `Assets::load_fonts` does for
+/// the app, and a monospace font to shape with
+fn text_system() -> (Arc<dyn PlatformTextSystem>, FontId) {
+	let text_system = gpui_platform::current_platform(true).text_system();
+	let fonts = Assets
+		.list("fonts")
+		.expect("Failed to list the bundled fonts")
+		.into_iter()
+		.filter(|path| path.ends_with(".ttf"))
+		.filter_map(|path| Assets.load(&path).ok().flatten())
+		.collect();
+	text_system
+		.add_fonts(fonts)
+		.expect("Failed to load the bundled fonts");
+
+	let family = text_system
+		.all_font_names()
+		.into_iter()
+		.find(|name| name.contains("Mono"))
+		.expect("No monospace font");
+	let font_id = text_system
+		.font_id(&font(family))
+		.expect("Failed to resolve the font");
+	(text_system, font_id)
+}
+
+struct Shaper {
+	text_system: Arc<dyn PlatformTextSystem>,
+	font_id: FontId,
+}
+
+impl Shaper {
+	fn shape(&self, text: &str) -> Arc<LineLayout> {
+		let run = FontRun {
+			len: text.len(),
+			font_id: self.font_id,
+		};
+		Arc::new(self.text_system.layout_line(text, FONT_SIZE, &[run]))
+	}
+}
+
+fn scroll_uncached(shaper: &Shaper, lines: &[String]) {
+	for frame_start in (0..TOTAL_ROWS - VISIBLE_ROWS).step_by(SCROLL_STEP) {
+		for row in frame_start..frame_start + VISIBLE_ROWS {
+			black_box(shaper.shape(&lines[row]));
+		}
+	}
+}
+
+fn scroll_cached(shaper: &Shaper, lines: &[String]) {
+	let mut cache = LineLayoutCache::<usize, (u32, usize), Arc<LineLayout>>::default();
+	for frame_start in (0..TOTAL_ROWS - VISIBLE_ROWS).step_by(SCROLL_STEP) {
+		cache.validate((14, 0));
+		let visible = frame_start..frame_start + VISIBLE_ROWS;
+		for row in visible.clone() {
+			black_box(cache.get_or_insert_with(row, || shaper.shape(&lines[row])));
+		}
+		let keep = frame_start.saturating_sub(VISIBLE_ROWS * 4)..visible.end + VISIBLE_ROWS * 4;
+		cache.trim(1024, |row| keep.contains(row));
+	}
+}
+
+fn bench_scrolling(c: &mut Criterion) {
+	let lines = make_lines();
+	let (text_system, font_id) = text_system();
+	let shaper = Shaper {
+		text_system,
+		font_id,
+	};
+
+	let mut group = c.benchmark_group("scroll_through_diff");
+	group.sample_size(20);
+	group.bench_function("shape_every_frame", |b| {
+		b.iter(|| scroll_uncached(&shaper, &lines))
+	});
+	group.bench_function("line_layout_cache", |b| {
+		b.iter(|| scroll_cached(&shaper, &lines))
+	});
+	group.finish();
+}
+
+criterion_group!(benches, bench_scrolling);
+criterion_main!(benches);
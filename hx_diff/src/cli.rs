@@ -0,0 +1,103 @@
+// Command line arguments, checked against the repository before any window opens
+use clap::{CommandFactory, Parser, Subcommand};
+use git_cli_wrap as git;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+	/// What to show, the working tree's status when left out
+	#[command(subcommand)]
+	pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+	/// Staged and unstaged changes, like `git status`
+	Status,
+	/// Changes between the working tree, the index and commits, like `git diff`
+	Diff(DiffArgs),
+	/// Changes made by a commit, like `git show`
+	Show(ShowArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+	/// Commit to compare the working tree with, or `<rev>..<rev>` to compare two commits
+	pub rev: Option<String>,
+
+	/// Compare the index rather than the working tree
+	#[arg(long, visible_alias = "staged")]
+	pub cached: bool,
+
+	/// Compare with the merge base of the commit and HEAD
+	#[arg(long, requires = "rev")]
+	pub merge_base: bool,
+
+	/// Only show changes to these paths
+	#[arg(last = true, value_name = "PATHSPEC")]
+	pub paths: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ShowArgs {
+	/// Commit to show
+	pub rev: String,
+}
+
+impl DiffArgs {
+	pub fn options(&self) -> git::DiffOptions {
+		git::DiffOptions {
+			merge_base: self.merge_base,
+			cached: self.cached,
+			commit: self.rev.clone(),
+			paths: self.paths.clone(),
+		}
+	}
+}
+
+/// Commits named by `<rev>`, `<rev>..<rev>` or `<rev>...<rev>`, where a side left out is HEAD
+fn range_commits(rev: &str) -> Vec<&str> {
+	let Some((from, to)) = rev.split_once("...").or_else(|| rev.split_once("..")) else {
+		return vec![rev];
+	};
+	[from, to]
+		.into_iter()
+		.map(|side| if side.is_empty() { "HEAD" } else { side })
+		.collect()
+}
+
+impl Args {
+	/// Parses the command line, exiting with an error when it's malformed or names something the
+	/// repository doesn't have
+	pub fn parse_and_validate() -> Self {
+		let args = Self::parse();
+		if let Err(message) = args.validate() {
+			Self::command()
+				.error(clap::error::ErrorKind::InvalidValue, message)
+				.exit();
+		}
+		args
+	}
+
+	fn validate(&self) -> Result<(), String> {
+		if !git::is_inside_work_tree() {
+			return Err("not inside a git working tree".into());
+		}
+
+		let commits = match &self.command {
+			None | Some(Command::Status) => Vec::new(),
+			Some(Command::Diff(diff)) => {
+				let commits = diff.rev.as_deref().map_or_else(Vec::new, range_commits);
+				if diff.merge_base && commits.len() > 1 {
+					return Err("--merge-base doesn't work with a range of commits".into());
+				}
+				commits
+			}
+			Some(Command::Show(show)) => vec![show.rev.as_str()],
+		};
+		for commit in commits {
+			git::verify_commit(commit).map_err(|_| format!("unknown revision '{}'", commit))?;
+		}
+		Ok(())
+	}
+}
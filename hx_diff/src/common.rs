@@ -1,7 +1,53 @@
 use gpui::*;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 
-pub static WIDTH: f32 = 1024.;
-pub static HEIGHT: f32 = 600.;
+/// How often watched files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Incremented every time the active theme changes, so views can drop anything derived from
+/// the previous theme's colors.
+#[derive(Default)]
+pub struct ThemeGeneration(usize);
+
+impl Global for ThemeGeneration {}
+
+impl ThemeGeneration {
+	pub fn get(cx: &App) -> usize {
+		cx.try_global::<ThemeGeneration>().map_or(0, |g| g.0)
+	}
+
+	pub fn bump(cx: &mut App) {
+		cx.default_global::<ThemeGeneration>().0 += 1;
+	}
+}
+
+/// Calls `on_change` whenever the file is created, modified or removed, for as long as the view
+/// is around
+pub fn watch_file<V: 'static>(
+	path: Option<PathBuf>,
+	on_change: fn(&mut App),
+	cx: &mut Context<V>,
+) -> Task<()> {
+	let modified = move || {
+		let metadata = std::fs::metadata(path.as_ref()?).ok()?;
+		metadata.modified().ok()
+	};
+	cx.spawn(async move |this, cx| {
+		let mut last_modified = modified();
+		loop {
+			cx.background_executor().timer(POLL_INTERVAL).await;
+			let new_modified = modified();
+			if new_modified == last_modified {
+				continue;
+			}
+			last_modified = new_modified;
+			if this.update(cx, |_, cx| on_change(cx)).is_err() {
+				break;
+			}
+		}
+	})
+}
 
 // Setup window helper from Duane Bester https://github.com/duanebester which is based on work
 // from Matthias from  // https://github.com/MatthiasGrandl/Loungy
@@ -23,7 +69,7 @@ pub fn setup_window(app_width: f32, app_height: f32, cx: &mut App) -> WindowOpti
 	options
 }
 
-pub fn clean_path_display(path: &std::path::PathBuf) -> String {
+pub fn clean_path_display(path: &std::path::Path) -> String {
 	let path_str = path.to_string_lossy();
 	#[cfg(windows)]
 	{
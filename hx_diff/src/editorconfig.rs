@@ -0,0 +1,297 @@
+// Minimal .editorconfig support: the indentation properties which affect how a diff is displayed
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndentStyle {
+	Tab,
+	Space,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndentSize {
+	Columns(usize),
+	/// Indent by one tab, as wide as `tab_width`
+	Tab,
+}
+
+/// Properties resolved for one file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EditorConfig {
+	pub indent_style: Option<IndentStyle>,
+	pub indent_size: Option<IndentSize>,
+	pub tab_width: Option<usize>,
+}
+
+impl EditorConfig {
+	/// Reads every `.editorconfig` from the file's directory up to the first one marked
+	/// `root = true`. Files closer to `path` take precedence, as do later sections within a file.
+	pub fn for_file(path: &Path) -> Self {
+		let path = std::env::current_dir()
+			.map(|cwd| cwd.join(path))
+			.unwrap_or_else(|_| path.to_path_buf());
+
+		let mut config_files = Vec::new();
+		for dir in path.ancestors().skip(1) {
+			let Ok(contents) = std::fs::read_to_string(dir.join(".editorconfig")) else {
+				continue;
+			};
+			let config_file = ConfigFile::parse(dir.to_path_buf(), &contents);
+			let is_root = config_file.is_root;
+			config_files.push(config_file);
+			if is_root {
+				break;
+			}
+		}
+
+		let mut config = EditorConfig::default();
+		for config_file in config_files.iter().rev() {
+			config_file.apply(&path, &mut config);
+		}
+		config
+	}
+
+	/// Width of a tab in columns. Falls back to the indent size, as the spec says.
+	pub fn tab_width(&self) -> Option<usize> {
+		self.tab_width.or(match self.indent_size {
+			Some(IndentSize::Columns(columns)) => Some(columns),
+			_ => None,
+		})
+	}
+
+	fn set(&mut self, key: &str, value: &str) {
+		let unset = value == "unset";
+		match key {
+			"indent_style" => {
+				self.indent_style = match value {
+					"tab" => Some(IndentStyle::Tab),
+					"space" => Some(IndentStyle::Space),
+					_ if unset => None,
+					_ => self.indent_style,
+				}
+			}
+			"indent_size" => {
+				self.indent_size = match value {
+					"tab" => Some(IndentSize::Tab),
+					_ if unset => None,
+					_ => value
+						.parse()
+						.ok()
+						.filter(|columns| *columns > 0)
+						.map(IndentSize::Columns)
+						.or(self.indent_size),
+				}
+			}
+			"tab_width" => {
+				self.tab_width = match value.parse().ok().filter(|columns| *columns > 0) {
+					Some(columns) => Some(columns),
+					None if unset => None,
+					None => self.tab_width,
+				}
+			}
+			_ => {}
+		}
+	}
+}
+
+struct Section {
+	glob: Regex,
+	properties: Vec<(String, String)>,
+}
+
+struct ConfigFile {
+	dir: PathBuf,
+	is_root: bool,
+	sections: Vec<Section>,
+}
+
+impl ConfigFile {
+	fn parse(dir: PathBuf, contents: &str) -> Self {
+		let mut is_root = false;
+		let mut sections: Vec<Section> = Vec::new();
+		// Sections whose glob doesn't compile still have to swallow their properties
+		let mut in_invalid_section = false;
+
+		for line in contents.lines() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+				continue;
+			}
+
+			if let Some(glob) = line
+				.strip_prefix('[')
+				.and_then(|line| line.strip_suffix(']'))
+			{
+				match glob_to_regex(glob) {
+					Some(glob) => {
+						sections.push(Section {
+							glob,
+							properties: Vec::new(),
+						});
+						in_invalid_section = false;
+					}
+					None => in_invalid_section = true,
+				}
+				continue;
+			}
+
+			let Some((key, value)) = line.split_once('=') else {
+				continue;
+			};
+			let key = key.trim().to_lowercase();
+			let value = value.trim().to_lowercase();
+
+			match sections.last_mut() {
+				_ if in_invalid_section => {}
+				Some(section) => section.properties.push((key, value)),
+				// Properties before the first section form the preamble, where only `root` counts
+				None => is_root |= key == "root" && value == "true",
+			}
+		}
+
+		Self {
+			dir,
+			is_root,
+			sections,
+		}
+	}
+
+	fn apply(&self, path: &Path, config: &mut EditorConfig) {
+		let Ok(relative) = path.strip_prefix(&self.dir) else {
+			return;
+		};
+		let relative = relative
+			.components()
+			.map(|component| component.as_os_str().to_string_lossy())
+			.collect::<Vec<_>>()
+			.join("/");
+
+		for section in &self.sections {
+			if section.glob.is_match(&relative) {
+				for (key, value) in &section.properties {
+					config.set(key, value);
+				}
+			}
+		}
+	}
+}
+
+/// Translates an EditorConfig section glob into a regex matching paths relative to the file's
+/// directory. Globs without a slash match the file name in any subdirectory.
+fn glob_to_regex(glob: &str) -> Option<Regex> {
+	let (glob, anchored) = match glob.strip_prefix('/') {
+		Some(glob) => (glob, true),
+		None => (glob, glob.contains('/')),
+	};
+
+	let mut pattern = String::from(if anchored { "^" } else { "^(?:.*/)?" });
+	let mut chars = glob.chars().peekable();
+	let mut brace_depth = 0;
+
+	while let Some(ch) = chars.next() {
+		match ch {
+			'*' if chars.peek() == Some(&'*') => {
+				chars.next();
+				pattern.push_str(".*");
+			}
+			'*' => pattern.push_str("[^/]*"),
+			'?' => pattern.push_str("[^/]"),
+			'[' => {
+				let mut class = String::new();
+				let mut closed = false;
+				for ch in chars.by_ref() {
+					if ch == ']' {
+						closed = true;
+						break;
+					}
+					class.push(ch);
+				}
+				if !closed {
+					pattern.push_str(&regex::escape(&format!("[{}", class)));
+				} else if let Some(negated) = class.strip_prefix('!') {
+					pattern.push_str(&format!("[^/{}]", negated.replace('\\', "\\\\")));
+				} else {
+					pattern.push_str(&format!("[{}]", class.replace('\\', "\\\\")));
+				}
+			}
+			'{' => {
+				brace_depth += 1;
+				pattern.push_str("(?:");
+			}
+			',' if brace_depth > 0 => pattern.push('|'),
+			'}' if brace_depth > 0 => {
+				brace_depth -= 1;
+				pattern.push(')');
+			}
+			'\\' => {
+				if let Some(escaped) = chars.next() {
+					pattern.push_str(&regex::escape(&escaped.to_string()));
+				}
+			}
+			_ => pattern.push_str(&regex::escape(&ch.to_string())),
+		}
+	}
+
+	if brace_depth > 0 {
+		return None;
+	}
+
+	pattern.push('$');
+	Regex::new(&pattern).ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn matches(glob: &str, path: &str) -> bool {
+		glob_to_regex(glob).unwrap().is_match(path)
+	}
+
+	#[test]
+	fn escapes_regex_metacharacters() {
+		assert!(matches("*.c++", "src/main.c++"));
+		assert!(!matches("*.c++", "src/main.cc"));
+		assert!(matches("a.b", "a.b"));
+		assert!(!matches("a.b", "axb"));
+		assert!(matches("\\*.rs", "*.rs"));
+		assert!(!matches("\\*.rs", "main.rs"));
+	}
+
+	#[test]
+	fn star_stays_within_a_directory_and_double_star_crosses_them() {
+		assert!(matches("*.rs", "main.rs"));
+		assert!(matches("*.rs", "src/main.rs"));
+		assert!(matches("src/*.rs", "src/main.rs"));
+		assert!(!matches("src/*.rs", "src/views/main.rs"));
+		assert!(matches("src/**.rs", "src/views/main.rs"));
+		assert!(matches("/**/*.rs", "src/views/main.rs"));
+		assert!(!matches("/src/*.rs", "lib/src/main.rs"));
+	}
+
+	#[test]
+	fn question_mark_matches_one_character_but_not_a_slash() {
+		assert!(matches("?.md", "a.md"));
+		assert!(!matches("?.md", "ab.md"));
+		assert!(!matches("a?b", "a/b"));
+	}
+
+	#[test]
+	fn character_classes() {
+		assert!(matches("[ab].txt", "a.txt"));
+		assert!(!matches("[ab].txt", "c.txt"));
+		assert!(matches("file[0-9]", "file7"));
+		assert!(matches("[!ab].txt", "c.txt"));
+		assert!(!matches("[!ab].txt", "a.txt"));
+		assert!(!matches("a[!b]c", "a/c"));
+		// An unclosed bracket is taken literally
+		assert!(matches("[ab", "[ab"));
+	}
+
+	#[test]
+	fn braces_are_alternatives() {
+		assert!(matches("*.{js,ts}", "index.ts"));
+		assert!(!matches("*.{js,ts}", "index.rs"));
+		assert!(glob_to_regex("*.{js,ts").is_none());
+	}
+}
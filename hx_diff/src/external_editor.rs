@@ -0,0 +1,180 @@
+// Launching the user's own editor at a file and line
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Environment variable holding the command template, like `code --goto {file}:{line}:{column}`
+const TEMPLATE_VAR: &str = "HX_DIFF_EDITOR";
+
+/// A place to open, with 1-based line and column numbers
+#[derive(Clone, Debug, PartialEq)]
+pub struct EditorLocation {
+	pub path: PathBuf,
+	pub line: usize,
+	pub column: usize,
+}
+
+impl EditorLocation {
+	pub fn new(path: &Path, line: usize, column: usize) -> Self {
+		Self {
+			path: path.to_path_buf(),
+			line,
+			column,
+		}
+	}
+}
+
+/// The command template to run. Without one configured, `$VISUAL` or `$EDITOR` is used with the
+/// `+line` argument most terminal editors understand.
+pub fn command_template() -> Option<String> {
+	let non_empty = |var: &str| {
+		std::env::var(var)
+			.ok()
+			.filter(|value| !value.trim().is_empty())
+	};
+
+	non_empty(TEMPLATE_VAR).or_else(|| {
+		non_empty("VISUAL")
+			.or_else(|| non_empty("EDITOR"))
+			.map(|editor| editor_template(&editor))
+	})
+}
+
+/// Template for a `$VISUAL` or `$EDITOR` command, which gets the line as a `+line` argument
+fn editor_template(editor: &str) -> String {
+	format!("{} +{{line}} {{file}}", editor)
+}
+
+/// Splits a command line into words. Quotes group words containing spaces, as in a shell, but
+/// nothing else is interpreted.
+fn split_words(command: &str) -> Vec<String> {
+	let mut words = Vec::new();
+	let mut word = String::new();
+	let mut in_word = false;
+	let mut quote = None;
+
+	for ch in command.chars() {
+		match (quote, ch) {
+			(Some(open), ch) if ch == open => quote = None,
+			(Some(_), ch) => word.push(ch),
+			(None, '"' | '\'') => {
+				quote = Some(ch);
+				in_word = true;
+			}
+			(None, ch) if ch.is_whitespace() => {
+				if in_word {
+					words.push(std::mem::take(&mut word));
+					in_word = false;
+				}
+			}
+			(None, ch) => {
+				word.push(ch);
+				in_word = true;
+			}
+		}
+	}
+	if in_word {
+		words.push(word);
+	}
+
+	words
+}
+
+/// Fills in the template's `{file}`, `{line}` and `{column}` placeholders. A template without
+/// `{file}` gets the path appended.
+pub fn build_command(template: &str, location: &EditorLocation) -> Vec<String> {
+	let file = location.path.to_string_lossy();
+	let mut words = split_words(template)
+		.into_iter()
+		.map(|word| {
+			word.replace("{file}", &file)
+				.replace("{line}", &location.line.to_string())
+				.replace("{column}", &location.column.to_string())
+		})
+		.collect::<Vec<_>>();
+
+	if !template.contains("{file}") {
+		words.push(file.into_owned());
+	}
+	words
+}
+
+/// Starts the editor without waiting for it to exit
+pub fn open_in_editor(location: &EditorLocation) -> io::Result<()> {
+	let template = command_template().ok_or_else(|| {
+		io::Error::new(
+			io::ErrorKind::NotFound,
+			format!(
+				"Set {}, $VISUAL or $EDITOR to choose an editor.",
+				TEMPLATE_VAR
+			),
+		)
+	})?;
+
+	let words = build_command(&template, location);
+	let Some((program, args)) = words.split_first() else {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"The editor command is empty.",
+		));
+	};
+
+	let mut child = Command::new(program)
+		.args(args)
+		.spawn()
+		.map_err(|error| io::Error::new(error.kind(), format!("{}: {}", program, error)))?;
+	// Reap the editor once it exits, so it doesn't linger as a zombie process
+	std::thread::spawn(move || child.wait());
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn location() -> EditorLocation {
+		EditorLocation::new(Path::new("src/main file.rs"), 12, 5)
+	}
+
+	#[test]
+	fn quotes_group_words() {
+		assert_eq!(
+			split_words(r#""/opt/My Editor/bin/edit" --wait  'a b'c "#),
+			["/opt/My Editor/bin/edit", "--wait", "a bc"]
+		);
+		// Empty quotes are still a word, and an unclosed quote runs to the end
+		assert_eq!(split_words(r#"edit "" "x y"#), ["edit", "", "x y"]);
+		assert!(split_words("  ").is_empty());
+	}
+
+	#[test]
+	fn placeholders_are_filled_in() {
+		assert_eq!(
+			build_command(
+				r#""/opt/My Editor/edit" --goto {file}:{line}:{column}"#,
+				&location()
+			),
+			["/opt/My Editor/edit", "--goto", "src/main file.rs:12:5"]
+		);
+	}
+
+	#[test]
+	fn file_is_appended_without_a_placeholder() {
+		assert_eq!(
+			build_command("subl -n", &location()),
+			["subl", "-n", "src/main file.rs"]
+		);
+	}
+
+	#[test]
+	fn editor_gets_the_line_as_an_argument() {
+		assert_eq!(
+			build_command(&editor_template("vim"), &location()),
+			["vim", "+12", "src/main file.rs"]
+		);
+		assert_eq!(
+			build_command(&editor_template("emacsclient -t"), &location()),
+			["emacsclient", "-t", "+12", "src/main file.rs"]
+		);
+	}
+}
@@ -0,0 +1,45 @@
+// Key bindings: the platform's defaults, then the user's keymap.json on top of them. Both are
+// arrays of `{ "context": ..., "bindings": { "keystroke": "namespace::Action" } }` blocks, and
+// binding a keystroke to null unbinds it.
+use crate::paths;
+use gpui::App;
+use settings::{KeymapFile, KeymapFileLoadResult};
+
+const DEFAULT_KEYMAP: &str = if cfg!(target_os = "macos") {
+	include_str!("../assets/keymaps/default-macos.json")
+} else if cfg!(target_os = "windows") {
+	include_str!("../assets/keymaps/default-windows.json")
+} else {
+	include_str!("../assets/keymaps/default-linux.json")
+};
+
+/// Replaces the key bindings with the defaults and the user's keymap
+pub fn load(cx: &mut App) {
+	cx.clear_key_bindings();
+	bind(DEFAULT_KEYMAP, "the default keymap", cx);
+
+	let Some(path) = paths::keymap_file() else {
+		return;
+	};
+	match std::fs::read_to_string(&path) {
+		Ok(content) => bind(&content, &path.display().to_string(), cx),
+		Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+		Err(error) => eprintln!("Warning: Couldn't read {}: {}", path.display(), error),
+	}
+}
+
+fn bind(content: &str, source: &str, cx: &mut App) {
+	match KeymapFile::load(content, cx) {
+		KeymapFileLoadResult::Success { key_bindings } => cx.bind_keys(key_bindings),
+		KeymapFileLoadResult::SomeFailedToLoad {
+			key_bindings,
+			error_message,
+		} => {
+			eprintln!("Warning: Skipped bindings in {}: {}", source, error_message);
+			cx.bind_keys(key_bindings);
+		}
+		KeymapFileLoadResult::JsonParseFailure { error } => {
+			eprintln!("Warning: Couldn't read {}: {}", source, error)
+		}
+	}
+}
@@ -0,0 +1,270 @@
+// Working out the language of a file for highlighting, from more than its extension
+use std::path::Path;
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+
+/// Well-known file names, and a token naming their language. Syntect already recognizes names
+/// like `Makefile` on its own; these are the ones it would take by extension, or not at all.
+const LANGUAGES_BY_FILE_NAME: &[(&str, &str)] = &[
+	("CMakeLists.txt", "cmake"),
+	("Dockerfile", "dockerfile"),
+	("Containerfile", "dockerfile"),
+	("Jenkinsfile", "groovy"),
+	("Gemfile", "ruby"),
+	("Rakefile", "ruby"),
+	("Vagrantfile", "ruby"),
+	("Podfile", "ruby"),
+	("Brewfile", "ruby"),
+	("SConstruct", "python"),
+	("SConscript", "python"),
+	("PKGBUILD", "bash"),
+	("APKBUILD", "bash"),
+	(".bashrc", "bash"),
+	(".bash_profile", "bash"),
+	(".bash_aliases", "bash"),
+	(".bash_logout", "bash"),
+	(".profile", "bash"),
+	(".zshrc", "bash"),
+	(".zshenv", "bash"),
+	(".zprofile", "bash"),
+	("Cargo.lock", "toml"),
+	("Pipfile", "toml"),
+];
+
+/// Interpreters named by shebangs whose name isn't a token of their language
+const LANGUAGES_BY_INTERPRETER: &[(&str, &str)] = &[
+	("sh", "bash"),
+	("dash", "bash"),
+	("ksh", "bash"),
+	("zsh", "bash"),
+	("node", "js"),
+	("nodejs", "js"),
+	("deno", "ts"),
+	("runghc", "haskell"),
+	("runhaskell", "haskell"),
+	("Rscript", "r"),
+	("tclsh", "tcl"),
+	("wish", "tcl"),
+];
+
+/// Lines at each end of a file searched for a vim modeline, as vim does by default
+const MODELINE_LINES: usize = 5;
+
+/// Picks the syntax of a file: by its full name, then its extension, then its first line. A
+/// modeline naming the language takes precedence over all of them.
+pub fn detect<'a>(syntax_set: &'a SyntaxSet, content: &str, path: &Path) -> &'a SyntaxReference {
+	modeline_language(content)
+		.and_then(|language| find_language(syntax_set, &language))
+		.or_else(|| by_file_name(syntax_set, path))
+		.or_else(|| {
+			path.extension()
+				.and_then(|extension| extension.to_str())
+				.and_then(|extension| syntax_set.find_syntax_by_extension(extension))
+		})
+		.or_else(|| by_first_line(syntax_set, content))
+		.unwrap_or_else(|| syntax_set.find_syntax_plain_text())
+}
+
+/// Looks a language up by name, like "Python", or by a token, like "py" or "python"
+pub fn find_language<'a>(syntax_set: &'a SyntaxSet, language: &str) -> Option<&'a SyntaxReference> {
+	syntax_set
+		.syntaxes()
+		.iter()
+		.find(|syntax| syntax.name.eq_ignore_ascii_case(language))
+		.or_else(|| syntax_set.find_syntax_by_token(language))
+		.or_else(|| syntax_set.find_syntax_by_token(&language.to_ascii_lowercase()))
+}
+
+fn by_file_name<'a>(syntax_set: &'a SyntaxSet, path: &Path) -> Option<&'a SyntaxReference> {
+	let file_name = path.file_name()?.to_str()?;
+	// Syntect lists whole names like `Makefile` among a syntax's extensions
+	syntax_set.find_syntax_by_extension(file_name).or_else(|| {
+		// Variants like `Dockerfile.dev` are Dockerfiles too
+		let file_name = if file_name.starts_with("Dockerfile.") {
+			"Dockerfile"
+		} else {
+			file_name
+		};
+		let (_, language) = LANGUAGES_BY_FILE_NAME
+			.iter()
+			.find(|(name, _)| *name == file_name)?;
+		find_language(syntax_set, language)
+	})
+}
+
+fn by_first_line<'a>(syntax_set: &'a SyntaxSet, content: &str) -> Option<&'a SyntaxReference> {
+	let first_line = content.lines().next()?;
+	shebang_interpreter(first_line)
+		.and_then(|interpreter| {
+			let language = LANGUAGES_BY_INTERPRETER
+				.iter()
+				.find(|(name, _)| *name == interpreter)
+				.map_or(interpreter, |(_, language)| *language);
+			find_language(syntax_set, language)
+		})
+		// Syntaxes also recognize first lines of their own, like `<?xml`
+		.or_else(|| syntax_set.find_syntax_by_first_line(first_line))
+}
+
+/// The program a `#!` line runs, without its directory or version, looking through `env`:
+/// `#!/usr/bin/env -S python3.12 -u` gives `python`
+fn shebang_interpreter(first_line: &str) -> Option<&str> {
+	let mut words = first_line.strip_prefix("#!")?.split_whitespace();
+	let mut program = words.next()?.rsplit('/').next()?;
+	if program == "env" {
+		// Skip env's options and variable assignments
+		program = words.find(|word| !word.starts_with('-') && !word.contains('='))?;
+		program = program.rsplit('/').next()?;
+	}
+
+	let program = program.trim_end_matches(|ch: char| ch.is_ascii_digit() || ch == '.');
+	(!program.is_empty()).then_some(program)
+}
+
+/// Language named by an Emacs `-*- mode: name -*-` line or a vim `vim: set ft=name:` modeline
+fn modeline_language(content: &str) -> Option<String> {
+	// Emacs only looks at the first line, or the second after a shebang
+	let emacs_mode = content
+		.lines()
+		.take(2)
+		.find_map(emacs_mode)
+		.map(str::to_string);
+	if emacs_mode.is_some() {
+		return emacs_mode;
+	}
+
+	let lines = content.lines().collect::<Vec<_>>();
+	let tail_start = lines
+		.len()
+		.saturating_sub(MODELINE_LINES)
+		.max(MODELINE_LINES);
+	lines
+		.iter()
+		.take(MODELINE_LINES)
+		.chain(lines.iter().skip(tail_start))
+		.find_map(|line| vim_filetype(line))
+		.map(str::to_string)
+}
+
+fn emacs_mode(line: &str) -> Option<&str> {
+	let (_, rest) = line.split_once("-*-")?;
+	let (variables, _) = rest.split_once("-*-")?;
+	let variables = variables.trim();
+
+	// Either just the mode, or `var: value;` pairs among which `mode` is one
+	if !variables.contains(':') {
+		return (!variables.is_empty()).then_some(variables);
+	}
+	variables.split(';').find_map(|variable| {
+		let (name, value) = variable.split_once(':')?;
+		name.trim()
+			.eq_ignore_ascii_case("mode")
+			.then(|| value.trim())
+			.filter(|value| !value.is_empty())
+	})
+}
+
+fn vim_filetype(line: &str) -> Option<&str> {
+	// The marker has to start the line or follow whitespace, so `foovim:` doesn't count
+	let options = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+		line.match_indices(marker)
+			.find(|(ix, _)| {
+				line[..*ix]
+					.chars()
+					.next_back()
+					.is_none_or(char::is_whitespace)
+			})
+			.map(|(ix, _)| &line[ix + marker.len()..])
+	})?;
+
+	options
+		.split(|ch: char| ch.is_whitespace() || ch == ':')
+		.find_map(|option| {
+			let (name, value) = option.split_once('=')?;
+			matches!(name, "ft" | "filetype" | "syn" | "syntax")
+				.then_some(value)
+				.filter(|value| !value.is_empty())
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn shebang_interpreters() {
+		assert_eq!(shebang_interpreter("#!/bin/sh"), Some("sh"));
+		assert_eq!(
+			shebang_interpreter("#!/usr/bin/env python3"),
+			Some("python")
+		);
+		assert_eq!(
+			shebang_interpreter("#! /usr/bin/python3.12 -u"),
+			Some("python")
+		);
+		assert_eq!(
+			shebang_interpreter("#!/usr/bin/env -S LANG=C node --no-warnings"),
+			Some("node")
+		);
+	}
+
+	#[test]
+	fn lines_which_are_not_shebangs() {
+		assert_eq!(shebang_interpreter("# !/bin/sh"), None);
+		assert_eq!(shebang_interpreter("#!"), None);
+		assert_eq!(shebang_interpreter("#!/usr/bin/env"), None);
+		assert_eq!(shebang_interpreter("#!/usr/bin/env -i"), None);
+		assert_eq!(shebang_interpreter("#!/opt/3.11"), None);
+	}
+
+	#[test]
+	fn vim_modelines() {
+		assert_eq!(vim_filetype("# vim: set ft=python:"), Some("python"));
+		assert_eq!(vim_filetype("// vim: filetype=rust ts=4"), Some("rust"));
+		assert_eq!(vim_filetype("vi:syntax=make"), Some("make"));
+		assert_eq!(vim_filetype("/* ex: set sw=2 syn=c: */"), Some("c"));
+	}
+
+	#[test]
+	fn lines_which_are_not_vim_modelines() {
+		assert_eq!(vim_filetype("let foovim: set ft=python"), None);
+		assert_eq!(vim_filetype("# vim: set ts=4 sw=4:"), None);
+		assert_eq!(vim_filetype("# vim: set ft=:"), None);
+		assert_eq!(vim_filetype("# ft=python"), None);
+	}
+
+	#[test]
+	fn emacs_modes() {
+		assert_eq!(emacs_mode("# -*- python -*-"), Some("python"));
+		assert_eq!(
+			emacs_mode(";; -*- coding: utf-8; mode: lisp; -*-"),
+			Some("lisp")
+		);
+		assert_eq!(emacs_mode("/* -*- Mode: C -*- */"), Some("C"));
+	}
+
+	#[test]
+	fn lines_which_are_not_emacs_modes() {
+		assert_eq!(emacs_mode("# -*- coding: utf-8 -*-"), None);
+		assert_eq!(emacs_mode("# -*- python"), None);
+		assert_eq!(emacs_mode("# -*- -*-"), None);
+	}
+
+	#[test]
+	fn modelines_are_only_looked_for_where_editors_look() {
+		let emacs_after_shebang = "#!/bin/sh\n# -*- mode: perl -*-\necho\n";
+		assert_eq!(
+			modeline_language(emacs_after_shebang).as_deref(),
+			Some("perl")
+		);
+		let emacs_on_the_third_line = "one\ntwo\n# -*- mode: perl -*-\n";
+		assert_eq!(modeline_language(emacs_on_the_third_line), None);
+
+		let middle = (0..20)
+			.map(|ix| format!("line {}\n", ix))
+			.collect::<String>();
+		let vim_at_the_end = format!("{}# vim: ft=ruby\n", middle);
+		assert_eq!(modeline_language(&vim_at_the_end).as_deref(), Some("ruby"));
+		let vim_in_the_middle = format!("{}# vim: ft=ruby\n{}", middle, middle);
+		assert_eq!(modeline_language(&vim_in_the_middle), None);
+	}
+}
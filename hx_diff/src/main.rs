@@ -1,12 +1,23 @@
+mod cli;
 mod common;
+mod editorconfig;
+mod external_editor;
+mod keymap;
+mod language;
+mod paths;
 mod syntax;
+mod syntax_assets;
+#[cfg(feature = "tree-sitter")]
+mod tree_sitter_highlighter;
+mod user_settings;
 mod views;
 mod workspace;
 
-use crate::common::{setup_window, HEIGHT, WIDTH};
+use crate::cli::Args;
+use crate::common::{setup_window, ThemeGeneration};
+use crate::user_settings::HxDiffSettings;
 use crate::workspace::*;
 use assets::Assets;
-use clap::Parser;
 use git_cli_wrap;
 use gpui::*;
 use settings::{default_settings, Settings, SettingsStore};
@@ -36,33 +47,56 @@ fn cycle_theme(cx: &mut App) {
 		.position(|t| t.as_str() == current_theme_name.0.as_ref())
 		.unwrap();
 	let new_index = (current_index + 1) % all_themes.len();
+	let new_theme = all_themes[new_index].to_string();
 	theme_settings.theme =
-		theme::ThemeSelection::Static(settings::ThemeName(all_themes[new_index].as_ref().into()));
+		theme::ThemeSelection::Static(settings::ThemeName(new_theme.clone().into()));
 	theme::ThemeSettings::override_global(theme_settings, cx);
-	cx.refresh_windows()
-}
-
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-pub struct Args {
-	/// Sub-commmand to run
-	pub mode: Option<String>,
-
-	pub arg: Option<String>,
+	ThemeGeneration::bump(cx);
+	cx.refresh_windows();
 
-	/// diff options
-	#[arg(long = "merge-base")]
-	pub merge_base: bool,
+	user_settings::update_file("theme", Some(new_theme.into()));
+}
 
-	#[arg(long, action)]
-	pub cached: bool,
+/// Remembers the zoomed buffer font size in the settings file
+fn save_buffer_font_size(cx: &App) {
+	let font_size = f32::from(theme::ThemeSettings::get_global(cx).buffer_font_size(cx));
+	user_settings::update_file("buffer_font_size", Some(font_size.into()));
+}
 
-	#[arg(long, action)]
-	pub staged: bool,
+/// Quits, once unsaved edits in the window are saved or the user chose to drop them
+fn quit(cx: &mut App) {
+	let dirty_window = cx
+		.windows()
+		.into_iter()
+		.filter_map(|window| window.downcast::<HxDiff>())
+		.find(|window| window.read(cx).is_ok_and(|hx_diff| hx_diff.is_dirty(cx)));
+
+	match dirty_window {
+		Some(window) => {
+			window
+				.update(cx, |hx_diff, window, cx| {
+					hx_diff.confirm_leaving_edits(window, cx, |_, _, cx| cx.quit());
+				})
+				.ok();
+		}
+		None => cx.quit(),
+	}
 }
 
 fn main() {
-	let args = Args::parse();
+	let args = Args::parse_and_validate();
+	let workspace = match Workspace::from_args(&args) {
+		Ok(workspace) => workspace,
+		Err(error) => {
+			eprintln!("Error: {}", error);
+			std::process::exit(1);
+		}
+	};
+
+	// Compile the user's syntaxes while the window opens, so the first diff doesn't wait on them
+	std::thread::spawn(|| {
+		syntax_assets::syntax_set();
+	});
 
 	gpui_platform::application()
 		.with_assets(Assets)
@@ -77,14 +111,11 @@ fn main() {
 			theme::init(theme::LoadThemes::All(Box::new(Assets)), cx);
 			Assets.load_fonts(cx).expect("Failed to load fonts");
 
-			let theme_name = "One Dark";
+			user_settings::init(cx);
 
-			let mut theme_settings = theme::ThemeSettings::get_global(cx).clone();
-			theme_settings.theme =
-				theme::ThemeSelection::Static(settings::ThemeName(theme_name.into()));
-			theme::ThemeSettings::override_global(theme_settings, cx);
-
-			let options = setup_window(WIDTH, HEIGHT, cx);
+			let settings = HxDiffSettings::get(cx);
+			let (width, height) = (settings.window_width, settings.window_height);
+			let options = setup_window(width, height, cx);
 
 			cx.on_window_closed(|cx| {
 				if cx.windows().is_empty() {
@@ -93,58 +124,74 @@ fn main() {
 			})
 			.detach();
 
-			cx.on_action(|_act: &Quit, cx| cx.quit());
+			cx.on_action(|_act: &Quit, cx| quit(cx));
 			cx.on_action(|_act: &CycleTheme, cx| cycle_theme(cx));
 			cx.on_action(|_act: &DecreaseFontSize, cx| {
-				theme::adjust_buffer_font_size(cx, |size| size - px(1.0))
+				theme::adjust_buffer_font_size(cx, |size| size - px(1.0));
+				save_buffer_font_size(cx);
 			});
 			cx.on_action(|_act: &IncreaseFontSize, cx| {
-				theme::adjust_buffer_font_size(cx, |size| size + px(1.0))
+				theme::adjust_buffer_font_size(cx, |size| size + px(1.0));
+				save_buffer_font_size(cx);
+			});
+			cx.on_action(|_act: &ResetFontSize, cx| {
+				user_settings::update_file("buffer_font_size", None);
+				user_settings::reload(cx);
+				theme::reset_buffer_font_size(cx);
 			});
-			cx.on_action(|_act: &ResetFontSize, cx| theme::reset_buffer_font_size(cx));
-
-			cx.bind_keys([KeyBinding::new("f7", diff_pane::PreviousDifference, None)]);
-			cx.bind_keys([KeyBinding::new("f8", diff_pane::NextDifference, None)]);
-
-			// OS specific key bindings
-			if cfg!(target_os = "macos") {
-				cx.bind_keys([KeyBinding::new("cmd-q", Quit, None)]);
-				cx.bind_keys([KeyBinding::new("cmd-t", CycleTheme, None)]);
-				cx.bind_keys([KeyBinding::new("cmd-+", IncreaseFontSize, None)]);
-				cx.bind_keys([KeyBinding::new("cmd-=", IncreaseFontSize, None)]);
-				cx.bind_keys([KeyBinding::new("cmd--", DecreaseFontSize, None)]);
-				cx.bind_keys([KeyBinding::new("cmd-0", ResetFontSize, None)]);
-				cx.bind_keys([KeyBinding::new("cmd-r", RefreshFileList, None)]);
-			} else if cfg!(target_os = "windows") {
-				cx.bind_keys([KeyBinding::new("ctrl-t", CycleTheme, None)]);
-				cx.bind_keys([KeyBinding::new("ctrl-+", IncreaseFontSize, None)]);
-				cx.bind_keys([KeyBinding::new("ctrl-=", IncreaseFontSize, None)]);
-				cx.bind_keys([KeyBinding::new("ctrl--", DecreaseFontSize, None)]);
-				cx.bind_keys([KeyBinding::new("ctrl-0", ResetFontSize, None)]);
-				cx.bind_keys([KeyBinding::new("ctrl-r", RefreshFileList, None)]);
-			}
+
+			keymap::load(cx);
 
 			cx.set_menus(vec![
 				Menu {
 					name: "".into(),
 					items: vec![
+						MenuItem::action("Save", diff_pane::Save),
+						MenuItem::action("Open in Editor", diff_pane::OpenInEditor),
 						MenuItem::action("Quit", Quit),
 						MenuItem::action("Cycle Theme", CycleTheme),
 					],
 				},
+				Menu {
+					name: "Edit".into(),
+					items: vec![
+						MenuItem::action("Undo", diff_pane::Undo),
+						MenuItem::action("Redo", diff_pane::Redo),
+						MenuItem::separator(),
+						MenuItem::action("Copy", diff_pane::Copy),
+						MenuItem::action("Copy New Side", diff_pane::CopyNewSide),
+						MenuItem::action("Copy Old Side", diff_pane::CopyOldSide),
+						MenuItem::action("Copy as Patch", diff_pane::CopyAsPatch),
+						MenuItem::separator(),
+						MenuItem::action("Find", diff_pane::Find),
+					],
+				},
 				Menu {
 					name: "View".into(),
 					items: vec![
+						MenuItem::action("Command Palette…", command_palette::Toggle),
+						MenuItem::action("Go to File…", command_palette::GoToFile),
+						MenuItem::separator(),
 						MenuItem::action("Zoom In", IncreaseFontSize),
 						MenuItem::action("Decrease Font", DecreaseFontSize),
 						MenuItem::action("Reset Zoom", ResetFontSize),
 						MenuItem::separator(),
+						MenuItem::action("Cycle Line Numbers", diff_pane::CycleLineNumbers),
+						MenuItem::action("Toggle Soft Wrap", diff_pane::ToggleSoftWrap),
+						MenuItem::action("Show Whitespace", diff_pane::ToggleWhitespace),
+						MenuItem::action("Select Language…", diff_pane::SelectLanguage),
+						MenuItem::separator(),
+						MenuItem::action("Review All Files", diff_pane::ToggleReviewStream),
+						MenuItem::separator(),
+						MenuItem::action("Go to Line…", diff_pane::GoToLine),
+						MenuItem::action("Center Selection", diff_pane::CenterSelection),
+						MenuItem::separator(),
 						MenuItem::action("Refresh File List", RefreshFileList),
 					],
 				},
 			]);
 
-			let workspace = cx.new(|_cx| Workspace::from_args(&args));
+			let workspace = cx.new(|_cx| workspace);
 
 			cx.open_window(options, |window, cx| HxDiff::new(workspace, window, cx))
 				.expect("Failed to create window");
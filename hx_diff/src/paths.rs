@@ -0,0 +1,61 @@
+// Where hx_diff keeps the user's configuration and its own caches
+use std::path::PathBuf;
+
+const APP_DIR: &str = "hx_diff";
+
+fn env_dir(var: &str) -> Option<PathBuf> {
+	std::env::var_os(var)
+		.filter(|value| !value.is_empty())
+		.map(PathBuf::from)
+}
+
+fn home_dir() -> Option<PathBuf> {
+	env_dir("HOME").or_else(|| env_dir("USERPROFILE"))
+}
+
+/// `$HX_DIFF_CONFIG_DIR`, or `hx_diff` within the platform's config directory, like
+/// `~/.config/hx_diff`
+pub fn config_dir() -> Option<PathBuf> {
+	if let Some(dir) = env_dir("HX_DIFF_CONFIG_DIR") {
+		return Some(dir);
+	}
+
+	let base = if cfg!(target_os = "windows") {
+		env_dir("APPDATA")
+	} else {
+		env_dir("XDG_CONFIG_HOME").or_else(|| home_dir().map(|home| home.join(".config")))
+	};
+	base.map(|base| base.join(APP_DIR))
+}
+
+/// `hx_diff` within the platform's cache directory, like `~/.cache/hx_diff`
+pub fn cache_dir() -> Option<PathBuf> {
+	let base = if cfg!(target_os = "windows") {
+		env_dir("LOCALAPPDATA")
+	} else if cfg!(target_os = "macos") {
+		home_dir().map(|home| home.join("Library").join("Caches"))
+	} else {
+		env_dir("XDG_CACHE_HOME").or_else(|| home_dir().map(|home| home.join(".cache")))
+	};
+	base.map(|base| base.join(APP_DIR))
+}
+
+/// `settings.json`, holding the theme, fonts and view options
+pub fn settings_file() -> Option<PathBuf> {
+	config_dir().map(|dir| dir.join("settings.json"))
+}
+
+/// `keymap.json`, with key bindings added to or replacing the defaults
+pub fn keymap_file() -> Option<PathBuf> {
+	config_dir().map(|dir| dir.join("keymap.json"))
+}
+
+/// `.sublime-syntax` files added to the bundled syntaxes
+pub fn syntaxes_dir() -> Option<PathBuf> {
+	config_dir().map(|dir| dir.join("syntaxes"))
+}
+
+/// `.tmTheme` files, each replacing the syntax colours of the GPUI theme it is named after
+pub fn themes_dir() -> Option<PathBuf> {
+	config_dir().map(|dir| dir.join("themes"))
+}
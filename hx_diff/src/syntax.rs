@@ -1,8 +1,68 @@
-use gpui::Hsla;
-use std::path::Path;
-use syntect::easy::HighlightLines;
-use syntect::highlighting::ThemeSet;
-use syntect::parsing::SyntaxSet;
+use crate::common::ThemeGeneration;
+use crate::language;
+use crate::syntax_assets;
+use crate::user_settings::{HighlighterBackend, HxDiffSettings};
+use git_cli_wrap as git;
+use gpui::{App, Hsla};
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use syntect::highlighting::{
+	Color, HighlightIterator, HighlightState, Highlighter, StyleModifier, Theme, ThemeItem,
+};
+use syntect::parsing::{
+	ParseState, ScopeSelectors, ScopeStack, ScopeStackOp, SyntaxReference, SyntaxSet,
+};
+use theme::ActiveTheme;
+
+/// TextMate scopes coloured like each of the syntax highlight names of GPUI themes
+const SCOPES_BY_HIGHLIGHT: &[(&str, &str)] = &[
+	("comment", "comment"),
+	("comment.doc", "comment.block.documentation"),
+	("string", "string"),
+	("string.escape", "constant.character.escape"),
+	("string.regex", "string.regexp"),
+	("string.special", "string.other, string.unquoted"),
+	("number", "constant.numeric"),
+	("boolean", "constant.language.boolean"),
+	("constant", "constant, support.constant"),
+	("keyword", "keyword, storage"),
+	("operator", "keyword.operator"),
+	(
+		"function",
+		"entity.name.function, support.function, meta.function-call variable.function",
+	),
+	(
+		"type",
+		"entity.name.type, entity.name.class, entity.name.struct, entity.name.enum, \
+		 entity.name.trait, entity.name.impl, support.type, support.class",
+	),
+	("variable", "variable"),
+	("variable.special", "variable.language"),
+	(
+		"property",
+		"variable.other.member, variable.other.property, support.type.property-name",
+	),
+	(
+		"attribute",
+		"entity.other.attribute-name, meta.attribute, meta.annotation",
+	),
+	("tag", "entity.name.tag"),
+	("label", "entity.name.label"),
+	("lifetime", "storage.modifier.lifetime"),
+	("preproc", "meta.preprocessor"),
+	("punctuation", "punctuation"),
+	("title", "markup.heading, entity.name.section"),
+	("emphasis", "markup.italic"),
+	("emphasis.strong", "markup.bold"),
+	("link_text", "string.other.link"),
+	("link_uri", "markup.underline.link"),
+];
 
 #[derive(Clone, Debug)]
 pub struct HighlightRun {
@@ -10,54 +70,464 @@ pub struct HighlightRun {
 	pub color: Hsla,
 }
 
+/// Lines between the checkpoints kept while highlighting, from which it can resume after an edit
+pub const CHECKPOINT_INTERVAL: usize = 256;
+
+/// Environment variable naming the highlighting library, `syntect` or `tree-sitter`, in place of
+/// the `highlighter` setting
+const BACKEND_VAR: &str = "HX_DIFF_HIGHLIGHTER";
+
+/// Parser and highlighter state at the start of a line, so highlighting can resume from there
+/// instead of the top of the file
+#[derive(Clone)]
+pub struct Checkpoint {
+	pub line: usize,
+	/// Byte offset of the line within the text
+	pub offset: usize,
+	/// Tree-sitter parses the whole text every time, so only syntect has state to keep
+	syntect: Option<SyntectState>,
+}
+
+impl Checkpoint {
+	/// Checkpoint at a line of a text highlighted without syntect, or at the end of a text which
+	/// is highlighted throughout, from where highlighting doesn't go on
+	pub fn stateless(line: usize, offset: usize) -> Self {
+		Self {
+			line,
+			offset,
+			syntect: None,
+		}
+	}
+}
+
+#[derive(Clone)]
+struct SyntectState {
+	parse_state: ParseState,
+	highlight_state: HighlightState,
+	/// The same scopes as `highlight_state`, which doesn't expose them
+	scopes: ScopeStack,
+}
+
+/// Lines highlighted in one go, following on from a checkpoint
+pub struct HighlightBatch {
+	/// Highlight runs of each line
+	pub lines: Vec<Vec<HighlightRun>>,
+	/// Lines which name a definition, such as a function, type or heading, in ascending order
+	pub definitions: Vec<usize>,
+	/// Checkpoints passed along the way
+	pub checkpoints: Vec<Checkpoint>,
+	/// State after the last line, to continue from
+	pub end: Checkpoint,
+}
+
+/// What highlighting one file takes, detached from the highlighter so it can run on a background
+/// thread
+#[derive(Clone)]
+pub enum HighlightContext {
+	Syntect(SyntectContext),
+	#[cfg(feature = "tree-sitter")]
+	TreeSitter(crate::tree_sitter_highlighter::TreeSitterContext),
+}
+
+impl HighlightContext {
+	/// Name of the highlighting library, since the same language highlights differently in each
+	pub fn backend_name(&self) -> &'static str {
+		match self {
+			Self::Syntect(_) => "syntect",
+			#[cfg(feature = "tree-sitter")]
+			Self::TreeSitter(_) => "tree-sitter",
+		}
+	}
+
+	pub fn syntax_name(&self) -> &str {
+		match self {
+			Self::Syntect(context) => &context.syntax.name,
+			#[cfg(feature = "tree-sitter")]
+			Self::TreeSitter(context) => context.syntax_name(),
+		}
+	}
+
+	/// State at the top of the file
+	pub fn start(&self) -> Checkpoint {
+		match self {
+			Self::Syntect(context) => context.start(),
+			#[cfg(feature = "tree-sitter")]
+			Self::TreeSitter(_) => Checkpoint::stateless(0, 0),
+		}
+	}
+
+	/// Highlights up to `line_count` lines of `text` following `from`, noting which lines hold
+	/// definitions along the way
+	pub fn highlight(
+		&self,
+		text: &Arc<str>,
+		from: Checkpoint,
+		line_count: usize,
+	) -> HighlightBatch {
+		match self {
+			Self::Syntect(context) => context.highlight(text, from, line_count),
+			#[cfg(feature = "tree-sitter")]
+			Self::TreeSitter(context) => context.highlight(text, from, line_count),
+		}
+	}
+}
+
+#[derive(Clone)]
+pub struct SyntectContext {
+	syntax_set: &'static SyntaxSet,
+	syntax: &'static SyntaxReference,
+	theme: Arc<Theme>,
+	definition_scopes: Arc<ScopeSelectors>,
+}
+
+impl SyntectContext {
+	fn start(&self) -> Checkpoint {
+		let highlighter = Highlighter::new(&self.theme);
+		Checkpoint {
+			line: 0,
+			offset: 0,
+			syntect: Some(SyntectState {
+				parse_state: ParseState::new(self.syntax),
+				highlight_state: HighlightState::new(&highlighter, ScopeStack::new()),
+				scopes: ScopeStack::new(),
+			}),
+		}
+	}
+
+	fn highlight(&self, text: &str, from: Checkpoint, line_count: usize) -> HighlightBatch {
+		let highlighter = Highlighter::new(&self.theme);
+		let Checkpoint {
+			mut line,
+			mut offset,
+			syntect,
+		} = from;
+		let SyntectState {
+			mut parse_state,
+			mut highlight_state,
+			mut scopes,
+		} = syntect.expect("Checkpoint from another highlighter");
+		let mut lines = Vec::new();
+		let mut definitions = Vec::new();
+		let mut checkpoints = Vec::new();
+
+		for text_line in syntect::util::LinesWithEndings::from(&text[offset..]).take(line_count) {
+			if line % CHECKPOINT_INTERVAL == 0 {
+				checkpoints.push(Checkpoint {
+					line,
+					offset,
+					syntect: Some(SyntectState {
+						parse_state: parse_state.clone(),
+						highlight_state: highlight_state.clone(),
+						scopes: scopes.clone(),
+					}),
+				});
+			}
+
+			let ops = parse_state
+				.parse_line(text_line, self.syntax_set)
+				.unwrap_or_default();
+
+			let mut is_definition = false;
+			for (_, op) in &ops {
+				if scopes.apply(op).is_err() {
+					continue;
+				}
+				if matches!(op, ScopeStackOp::Push(_)) {
+					is_definition |= self
+						.definition_scopes
+						.does_match(scopes.as_slice())
+						.is_some();
+				}
+			}
+			if is_definition {
+				definitions.push(line);
+			}
+
+			let ranges =
+				HighlightIterator::new(&mut highlight_state, &ops, text_line, &highlighter)
+					.collect::<Vec<_>>();
+			// Runs cover the line's text, which leaves out the line terminator
+			let mut runs = ranges
+				.iter()
+				.map(|(style, text)| HighlightRun {
+					byte_len: text.len(),
+					color: syntect_color_to_hsla(style.foreground),
+				})
+				.collect::<Vec<_>>();
+			if let Some(last_run) = runs.last_mut() {
+				let terminator_len = if text_line.ends_with("\r\n") {
+					2
+				} else {
+					text_line.ends_with('\n') as usize
+				};
+				last_run.byte_len = last_run.byte_len.saturating_sub(terminator_len);
+			}
+			lines.push(runs);
+
+			line += 1;
+			offset += text_line.len();
+		}
+
+		HighlightBatch {
+			lines,
+			definitions,
+			checkpoints,
+			end: Checkpoint {
+				line,
+				offset,
+				syntect: Some(SyntectState {
+					parse_state,
+					highlight_state,
+					scopes,
+				}),
+			},
+		}
+	}
+}
+
+/// What the diff pane asks of a highlighter, whichever library it highlights with
+pub trait HighlightBackend {
+	/// Picks up the colours of a newly activated theme. Returns whether they changed, in which
+	/// case anything highlighted before is out of date.
+	fn sync_theme(&mut self, cx: &App) -> bool;
+
+	/// Theme of the colours highlighted with
+	fn theme_id(&self) -> &ThemeId;
+
+	/// Names of the languages which can be picked for a file, sorted
+	fn language_names(&self) -> Vec<&str>;
+
+	/// Language picked by hand for the file, if any
+	fn language_override(&self, file_path: &Path) -> Option<&str>;
+
+	/// Highlights the file as `language` from now on, or goes back to detecting it with `None`
+	fn set_language_override(&mut self, file_path: &Path, language: Option<String>);
+
+	/// Name of the language the file is highlighted as
+	fn language_name(&self, content: &str, file_path: &Path) -> &str;
+
+	/// Everything needed to highlight the file, with the syntax identified for it
+	fn context(&self, content: &str, file_path: &Path) -> HighlightContext;
+}
+
+/// Names a theme's colours. Unlike `ThemeGeneration` it's the same every time the theme is
+/// activated, so highlights from an earlier visit to the theme can be reused.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct ThemeId {
+	pub name: String,
+	/// Changes when the theme is edited under the same name
+	pub content_hash: u64,
+}
+
+impl ThemeId {
+	/// Hashes the colours' debug output, as syntect themes don't implement `Hash`
+	pub fn new(name: &str, colors: impl Debug) -> Self {
+		let mut hasher = DefaultHasher::new();
+		format!("{:?}", colors).hash(&mut hasher);
+		Self {
+			name: name.to_string(),
+			content_hash: hasher.finish(),
+		}
+	}
+}
+
+/// The highlighter named by `$HX_DIFF_HIGHLIGHTER`, or else by the `highlighter` setting
+pub fn new_highlighter(cx: &App) -> Box<dyn HighlightBackend> {
+	let setting = HxDiffSettings::get(cx).highlighter;
+	let backend = match std::env::var(BACKEND_VAR).unwrap_or_default().trim() {
+		"" => setting,
+		"syntect" => HighlighterBackend::Syntect,
+		"tree-sitter" => HighlighterBackend::TreeSitter,
+		backend => {
+			eprintln!(
+				"Warning: Unknown {} \"{}\", highlighting as the settings say",
+				BACKEND_VAR, backend
+			);
+			setting
+		}
+	};
+
+	match backend {
+		HighlighterBackend::Syntect => {}
+		#[cfg(feature = "tree-sitter")]
+		HighlighterBackend::TreeSitter => {
+			return Box::new(crate::tree_sitter_highlighter::TreeSitterHighlighter::new(
+				cx,
+			))
+		}
+		#[cfg(not(feature = "tree-sitter"))]
+		HighlighterBackend::TreeSitter => {
+			eprintln!("Warning: hx_diff was built without tree-sitter, highlighting with syntect")
+		}
+	}
+	Box::new(SyntaxHighlighter::new(cx))
+}
+
 pub struct SyntaxHighlighter {
-	syntax_set: SyntaxSet,
-	theme_set: ThemeSet,
+	syntax_set: &'static SyntaxSet,
+	/// The syntax colours of the active GPUI theme
+	theme: Arc<Theme>,
+	/// `ThemeGeneration` which `theme` was built for
+	theme_generation: usize,
+	theme_id: ThemeId,
+	/// Languages picked by hand, by file
+	language_overrides: HashMap<PathBuf, String>,
+	/// `linguist-language` gitattribute of each file highlighted so far, so git isn't asked again
+	/// on every edit
+	attribute_languages: RefCell<HashMap<PathBuf, Option<String>>>,
+	/// Scopes given to the names of definitions, which make a line a definition line
+	definition_scopes: Arc<ScopeSelectors>,
 }
 
 impl SyntaxHighlighter {
-	pub fn new() -> Self {
+	pub fn new(cx: &App) -> Self {
+		let theme = syntect_theme(cx);
 		Self {
-			syntax_set: SyntaxSet::load_defaults_newlines(),
-			theme_set: ThemeSet::load_defaults(),
+			syntax_set: syntax_assets::syntax_set(),
+			theme_id: ThemeId::new(&cx.theme().name, &theme),
+			theme: Arc::new(theme),
+			theme_generation: ThemeGeneration::get(cx),
+			language_overrides: HashMap::new(),
+			attribute_languages: RefCell::new(HashMap::new()),
+			definition_scopes: Arc::new(
+				ScopeSelectors::from_str(
+					"entity.name.function, entity.name.class, entity.name.struct, \
+					 entity.name.enum, entity.name.trait, entity.name.impl, entity.name.type, \
+					 entity.name.namespace, entity.name.section, markup.heading",
+				)
+				.expect("Invalid definition scope selectors"),
+			),
 		}
 	}
 
-	/// Highlights the given content using the syntax identified by the file extension.
-	/// Returns a Vec where each element is the list of highlight runs for one line.
-	pub fn highlight_content(&self, content: &str, file_path: &Path) -> Vec<Vec<HighlightRun>> {
-		let extension = file_path.extension().and_then(|e| e.to_str()).unwrap_or("");
+	/// Picks the syntax of a file: the language chosen by hand, then the one given by the
+	/// `linguist-language` gitattribute, then whatever the name and content suggest
+	fn syntax_for(&self, content: &str, file_path: &Path) -> &'static SyntaxReference {
+		let attribute_language = self
+			.attribute_languages
+			.borrow_mut()
+			.entry(file_path.to_path_buf())
+			.or_insert_with(|| {
+				git::check_attr("linguist-language", file_path)
+					.ok()
+					.flatten()
+			})
+			.clone();
+
+		self.language_override(file_path)
+			.and_then(|language| language::find_language(self.syntax_set, language))
+			.or_else(|| {
+				attribute_language
+					.and_then(|language| language::find_language(self.syntax_set, &language))
+			})
+			.unwrap_or_else(|| language::detect(self.syntax_set, content, file_path))
+	}
+}
 
-		let syntax = self
+impl HighlightBackend for SyntaxHighlighter {
+	fn sync_theme(&mut self, cx: &App) -> bool {
+		let theme_generation = ThemeGeneration::get(cx);
+		if theme_generation == self.theme_generation {
+			return false;
+		}
+
+		let theme = syntect_theme(cx);
+		self.theme_id = ThemeId::new(&cx.theme().name, &theme);
+		self.theme = Arc::new(theme);
+		self.theme_generation = theme_generation;
+		true
+	}
+
+	fn theme_id(&self) -> &ThemeId {
+		&self.theme_id
+	}
+
+	fn language_names(&self) -> Vec<&str> {
+		let mut names = self
 			.syntax_set
-			.find_syntax_by_extension(extension)
-			.unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
-
-		let theme = &self.theme_set.themes["base16-ocean.dark"];
-		let mut highlighter = HighlightLines::new(syntax, theme);
-
-		syntect::util::LinesWithEndings::from(content)
-			.map(|line| {
-				let ranges = highlighter
-					.highlight_line(&line, &self.syntax_set)
-					.unwrap_or_default();
-
-				// Split off the last entry so we can cap the byte_len to not include the newline
-				let ((last_style, last_text), elements) = ranges.split_last().unwrap();
-
-				elements
-					.iter()
-					.map(|(style, text)| HighlightRun {
-						byte_len: text.len(),
-						color: syntect_color_to_hsla(style.foreground),
-					})
-					.chain(std::iter::once(HighlightRun {
-						byte_len: last_text.len() - 1,
-						color: syntect_color_to_hsla(last_style.foreground),
-					}))
-					.collect()
+			.syntaxes()
+			.iter()
+			.filter(|syntax| !syntax.hidden)
+			.map(|syntax| syntax.name.as_str())
+			.collect::<Vec<_>>();
+		names.sort_by_key(|name| name.to_lowercase());
+		names.dedup();
+		names
+	}
+
+	fn language_override(&self, file_path: &Path) -> Option<&str> {
+		self.language_overrides.get(file_path).map(String::as_str)
+	}
+
+	fn set_language_override(&mut self, file_path: &Path, language: Option<String>) {
+		match language {
+			Some(language) => self
+				.language_overrides
+				.insert(file_path.to_path_buf(), language),
+			None => self.language_overrides.remove(file_path),
+		};
+	}
+
+	fn language_name(&self, content: &str, file_path: &Path) -> &str {
+		&self.syntax_for(content, file_path).name
+	}
+
+	fn context(&self, content: &str, file_path: &Path) -> HighlightContext {
+		HighlightContext::Syntect(SyntectContext {
+			syntax_set: self.syntax_set,
+			syntax: self.syntax_for(content, file_path),
+			theme: self.theme.clone(),
+			definition_scopes: self.definition_scopes.clone(),
+		})
+	}
+}
+
+/// Translates the syntax colours of the active GPUI theme into a syntect theme. Text which no
+/// highlight applies to keeps the editor's foreground colour. A user `.tmTheme` named after the
+/// GPUI theme is used instead, when there is one.
+fn syntect_theme(cx: &App) -> Theme {
+	let theme = cx.theme();
+	if let Some(user_theme) = syntax_assets::user_themes().themes.get(theme.name.as_ref()) {
+		return user_theme.clone();
+	}
+
+	let scopes = theme
+		.syntax()
+		.highlights
+		.iter()
+		.filter_map(|(name, style)| {
+			let (_, selectors) = SCOPES_BY_HIGHLIGHT
+				.iter()
+				.find(|(highlight, _)| *highlight == name.as_str())?;
+			Some(ThemeItem {
+				scope: ScopeSelectors::from_str(selectors).ok()?,
+				style: StyleModifier {
+					foreground: Some(hsla_to_syntect_color(style.color?)),
+					background: None,
+					font_style: None,
+				},
 			})
-			.collect()
+		})
+		.collect();
+
+	Theme {
+		settings: syntect::highlighting::ThemeSettings {
+			foreground: Some(hsla_to_syntect_color(theme.colors().editor_foreground)),
+			..Default::default()
+		},
+		scopes,
+		..Default::default()
+	}
+}
+
+fn hsla_to_syntect_color(color: Hsla) -> Color {
+	let rgba = gpui::Rgba::from(color);
+	let channel = |value: f32| (value.clamp(0., 1.) * 255.).round() as u8;
+	Color {
+		r: channel(rgba.r),
+		g: channel(rgba.g),
+		b: channel(rgba.b),
+		a: channel(rgba.a),
 	}
 }
 
@@ -0,0 +1,161 @@
+// Syntaxes and colour themes beyond syntect's defaults, loaded once from the config directory
+use crate::paths;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use syntect::dumps::{dump_to_uncompressed_file, from_uncompressed_dump_file};
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::{SyntaxDefinition, SyntaxSet};
+
+const CACHE_PREFIX: &str = "syntaxes-";
+const CACHE_EXTENSION: &str = "packdump";
+
+/// The bundled syntaxes along with the user's, shared by every highlighter
+pub fn syntax_set() -> &'static SyntaxSet {
+	static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+	SYNTAX_SET.get_or_init(load_syntax_set)
+}
+
+/// The user's `.tmTheme` files, by file name without the extension
+pub fn user_themes() -> &'static ThemeSet {
+	static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+	THEMES.get_or_init(load_themes)
+}
+
+/// Files with the extension anywhere below `dir`, in a stable order
+fn files_with_extension(dir: &Path, extension: &str) -> Vec<PathBuf> {
+	let mut files = Vec::new();
+	let mut dirs = vec![dir.to_path_buf()];
+	while let Some(dir) = dirs.pop() {
+		let Ok(entries) = std::fs::read_dir(&dir) else {
+			continue;
+		};
+		for path in entries.flatten().map(|entry| entry.path()) {
+			if path.is_dir() {
+				dirs.push(path);
+			} else if path.extension().is_some_and(|ext| ext == extension) {
+				files.push(path);
+			}
+		}
+	}
+	files.sort();
+	files
+}
+
+/// Changes whenever a syntax file is added, removed or modified, or hx_diff is updated
+fn fingerprint(files: &[PathBuf]) -> u64 {
+	let mut hasher = DefaultHasher::new();
+	env!("CARGO_PKG_VERSION").hash(&mut hasher);
+	for file in files {
+		file.hash(&mut hasher);
+		if let Ok(metadata) = std::fs::metadata(file) {
+			metadata.len().hash(&mut hasher);
+			metadata.modified().ok().hash(&mut hasher);
+		}
+	}
+	hasher.finish()
+}
+
+/// Compiling syntaxes takes long enough to notice, so the result is dumped to the cache
+/// directory and reused until the user's syntaxes change
+fn load_syntax_set() -> SyntaxSet {
+	let files = paths::syntaxes_dir()
+		.map(|dir| files_with_extension(&dir, "sublime-syntax"))
+		.unwrap_or_default();
+	if files.is_empty() {
+		return SyntaxSet::load_defaults_newlines();
+	}
+
+	let cache_path = paths::cache_dir().map(|dir| {
+		dir.join(format!(
+			"{}{:016x}.{}",
+			CACHE_PREFIX,
+			fingerprint(&files),
+			CACHE_EXTENSION
+		))
+	});
+	if let Some(syntax_set) = cache_path
+		.as_ref()
+		.and_then(|path| from_uncompressed_dump_file(path).ok())
+	{
+		return syntax_set;
+	}
+
+	let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+	for file in &files {
+		let definition = std::fs::read_to_string(file)
+			.map_err(|error| error.to_string())
+			.and_then(|contents| {
+				let fallback_name = file.file_stem().and_then(|stem| stem.to_str());
+				SyntaxDefinition::load_from_str(&contents, true, fallback_name)
+					.map_err(|error| error.to_string())
+			});
+		match definition {
+			Ok(definition) => builder.add(definition),
+			Err(error) => eprintln!("Warning: Skipped syntax {}: {}", file.display(), error),
+		}
+	}
+	let syntax_set = builder.build();
+
+	if let Some(cache_path) = cache_path {
+		write_cache(&syntax_set, &cache_path);
+	}
+	syntax_set
+}
+
+/// Replaces the dumps of earlier syntaxes with this one. Failing only slows the next startup.
+fn write_cache(syntax_set: &SyntaxSet, cache_path: &Path) {
+	let Some(cache_dir) = cache_path.parent() else {
+		return;
+	};
+	if let Err(error) = std::fs::create_dir_all(cache_dir) {
+		eprintln!(
+			"Warning: Couldn't create {}: {}",
+			cache_dir.display(),
+			error
+		);
+		return;
+	}
+
+	if let Ok(entries) = std::fs::read_dir(cache_dir) {
+		for path in entries.flatten().map(|entry| entry.path()) {
+			let is_dump = path.extension().is_some_and(|ext| ext == CACHE_EXTENSION)
+				&& path
+					.file_name()
+					.and_then(|name| name.to_str())
+					.is_some_and(|name| name.starts_with(CACHE_PREFIX));
+			if is_dump {
+				let _ = std::fs::remove_file(path);
+			}
+		}
+	}
+
+	if let Err(error) = dump_to_uncompressed_file(syntax_set, cache_path) {
+		eprintln!(
+			"Warning: Couldn't cache syntaxes in {}: {}",
+			cache_path.display(),
+			error
+		);
+	}
+}
+
+fn load_themes() -> ThemeSet {
+	let mut theme_set = ThemeSet::new();
+	let Some(dir) = paths::themes_dir() else {
+		return theme_set;
+	};
+
+	for file in files_with_extension(&dir, "tmTheme") {
+		let Some(name) = file.file_stem().and_then(|stem| stem.to_str()) else {
+			continue;
+		};
+		match ThemeSet::get_theme(&file) {
+			Ok(theme) => {
+				theme_set.themes.insert(name.to_string(), theme);
+			}
+			Err(error) => eprintln!("Warning: Skipped theme {}: {}", file.display(), error),
+		}
+	}
+	theme_set
+}
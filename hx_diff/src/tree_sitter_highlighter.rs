@@ -0,0 +1,545 @@
+// Highlighting with tree-sitter grammars, for the languages hx_diff vendors one for. Files in any
+// other language are highlighted with syntect as usual.
+use crate::syntax::{
+	Checkpoint, HighlightBackend, HighlightBatch, HighlightContext, HighlightRun,
+	SyntaxHighlighter, ThemeId, CHECKPOINT_INTERVAL,
+};
+use gpui::{App, Hsla};
+use std::cmp::Reverse;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use theme::ActiveTheme;
+use tree_sitter::{Language, Parser, Query, QueryCursor, StreamingIterator, Tree};
+
+/// Capture names given a colour, the same as the syntax highlight names of GPUI themes. Captures
+/// like `function.method.call` take the colour of their longest listed prefix.
+const HIGHLIGHT_NAMES: &[&str] = &[
+	"attribute",
+	"boolean",
+	"comment",
+	"comment.doc",
+	"constant",
+	"constant.builtin",
+	"constructor",
+	"embedded",
+	"emphasis",
+	"emphasis.strong",
+	"enum",
+	"escape",
+	"function",
+	"function.builtin",
+	"function.macro",
+	"function.method",
+	"keyword",
+	"label",
+	"lifetime",
+	"module",
+	"number",
+	"operator",
+	"preproc",
+	"property",
+	"punctuation",
+	"punctuation.bracket",
+	"punctuation.delimiter",
+	"punctuation.special",
+	"string",
+	"string.escape",
+	"string.regex",
+	"string.special",
+	"string.special.key",
+	"tag",
+	"title",
+	"type",
+	"type.builtin",
+	"variable",
+	"variable.builtin",
+	"variable.parameter",
+	"variable.special",
+];
+
+/// A vendored grammar with its queries compiled
+struct Grammar {
+	/// The same name as syntect's syntax for the language, where it has one
+	name: &'static str,
+	extensions: &'static [&'static str],
+	language: Language,
+	highlights: Query,
+	/// Index into `HIGHLIGHT_NAMES` of the colour of each of the highlights query's captures
+	capture_highlights: Vec<Option<usize>>,
+	/// Tags query, whose `definition.*` captures make a line a definition line
+	tags: Option<Query>,
+}
+
+/// Grammar sources and queries, before compiling
+struct GrammarSource {
+	name: &'static str,
+	extensions: &'static [&'static str],
+	language: Language,
+	highlights: String,
+	tags: Option<&'static str>,
+}
+
+fn grammar_sources() -> Vec<GrammarSource> {
+	vec![
+		GrammarSource {
+			name: "Rust",
+			extensions: &["rs"],
+			language: tree_sitter_rust::LANGUAGE.into(),
+			highlights: tree_sitter_rust::HIGHLIGHTS_QUERY.into(),
+			tags: Some(tree_sitter_rust::TAGS_QUERY),
+		},
+		GrammarSource {
+			name: "TypeScript",
+			extensions: &["ts", "mts", "cts"],
+			language: tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+			// TypeScript's queries only add to JavaScript's
+			highlights: [
+				tree_sitter_typescript::HIGHLIGHTS_QUERY,
+				tree_sitter_javascript::HIGHLIGHT_QUERY,
+			]
+			.join("\n"),
+			tags: Some(tree_sitter_typescript::TAGS_QUERY),
+		},
+		GrammarSource {
+			name: "TSX",
+			extensions: &["tsx"],
+			language: tree_sitter_typescript::LANGUAGE_TSX.into(),
+			highlights: [
+				tree_sitter_typescript::HIGHLIGHTS_QUERY,
+				tree_sitter_javascript::JSX_HIGHLIGHT_QUERY,
+				tree_sitter_javascript::HIGHLIGHT_QUERY,
+			]
+			.join("\n"),
+			tags: Some(tree_sitter_typescript::TAGS_QUERY),
+		},
+		GrammarSource {
+			name: "JavaScript",
+			extensions: &["js", "mjs", "cjs", "jsx"],
+			language: tree_sitter_javascript::LANGUAGE.into(),
+			highlights: [
+				tree_sitter_javascript::JSX_HIGHLIGHT_QUERY,
+				tree_sitter_javascript::HIGHLIGHT_QUERY,
+			]
+			.join("\n"),
+			tags: Some(tree_sitter_javascript::TAGS_QUERY),
+		},
+		GrammarSource {
+			name: "Python",
+			extensions: &["py", "pyi", "pyw"],
+			language: tree_sitter_python::LANGUAGE.into(),
+			highlights: tree_sitter_python::HIGHLIGHTS_QUERY.into(),
+			tags: Some(tree_sitter_python::TAGS_QUERY),
+		},
+		GrammarSource {
+			name: "Go",
+			extensions: &["go"],
+			language: tree_sitter_go::LANGUAGE.into(),
+			highlights: tree_sitter_go::HIGHLIGHTS_QUERY.into(),
+			tags: Some(tree_sitter_go::TAGS_QUERY),
+		},
+		GrammarSource {
+			name: "JSON",
+			extensions: &["json"],
+			language: tree_sitter_json::LANGUAGE.into(),
+			highlights: tree_sitter_json::HIGHLIGHTS_QUERY.into(),
+			tags: None,
+		},
+	]
+}
+
+/// The vendored grammars, compiled on first use. A grammar whose queries don't compile is left
+/// to syntect.
+fn grammars() -> &'static [Grammar] {
+	static GRAMMARS: OnceLock<Vec<Grammar>> = OnceLock::new();
+	GRAMMARS.get_or_init(|| {
+		grammar_sources()
+			.into_iter()
+			.filter_map(|source| {
+				let compiled =
+					Query::new(&source.language, &source.highlights).and_then(|highlights| {
+						let tags = source
+							.tags
+							.map(|tags| Query::new(&source.language, tags))
+							.transpose()?;
+						Ok((highlights, tags))
+					});
+				match compiled {
+					Ok((highlights, tags)) => Some(Grammar {
+						name: source.name,
+						extensions: source.extensions,
+						language: source.language,
+						capture_highlights: capture_highlights(&highlights),
+						highlights,
+						tags,
+					}),
+					Err(error) => {
+						eprintln!("Warning: Skipped {} grammar: {}", source.name, error);
+						None
+					}
+				}
+			})
+			.collect()
+	})
+}
+
+/// Colour of each of the query's captures, that of the longest of `HIGHLIGHT_NAMES` which the
+/// capture's name starts with, like `function.method` for `function.method.call`
+fn capture_highlights(query: &Query) -> Vec<Option<usize>> {
+	query
+		.capture_names()
+		.iter()
+		.map(|name| {
+			let mut name = *name;
+			loop {
+				if let Some(highlight) = HIGHLIGHT_NAMES.iter().position(|&known| known == name) {
+					return Some(highlight);
+				}
+				name = name.rsplit_once('.')?.0;
+			}
+		})
+		.collect()
+}
+
+/// Looks a grammar up by name, like "Rust", or by an extension, like "rs"
+fn find_grammar(language: &str) -> Option<&'static Grammar> {
+	grammars().iter().find(|grammar| {
+		grammar.name.eq_ignore_ascii_case(language)
+			|| grammar
+				.extensions
+				.iter()
+				.any(|extension| extension.eq_ignore_ascii_case(language))
+	})
+}
+
+pub struct TreeSitterHighlighter {
+	/// Detects languages, and highlights those without a vendored grammar
+	syntect: SyntaxHighlighter,
+	/// Colour of each of `HIGHLIGHT_NAMES` in the active GPUI theme
+	colors: Arc<Vec<Hsla>>,
+	/// Colour of text no capture applies to
+	foreground: Hsla,
+	theme_id: ThemeId,
+}
+
+impl TreeSitterHighlighter {
+	pub fn new(cx: &App) -> Self {
+		let (colors, foreground) = highlight_colors(cx);
+		let syntect = SyntaxHighlighter::new(cx);
+		Self {
+			theme_id: tree_sitter_theme_id(&syntect, &colors, foreground, cx),
+			syntect,
+			colors,
+			foreground,
+		}
+	}
+
+	/// Grammar to highlight the file with. A language picked by hand without a grammar, or a file
+	/// syntect detects as another language, is left to syntect.
+	fn grammar_for(&self, content: &str, file_path: &Path) -> Option<&'static Grammar> {
+		if let Some(language) = self.syntect.language_override(file_path) {
+			return find_grammar(language);
+		}
+
+		find_grammar(self.syntect.language_name(content, file_path)).or_else(|| {
+			// Syntect has no syntax at all for some, like TypeScript
+			let extension = file_path.extension()?.to_str()?;
+			grammars()
+				.iter()
+				.find(|grammar| grammar.extensions.contains(&extension))
+		})
+	}
+}
+
+impl HighlightBackend for TreeSitterHighlighter {
+	fn sync_theme(&mut self, cx: &App) -> bool {
+		if !self.syntect.sync_theme(cx) {
+			return false;
+		}
+
+		(self.colors, self.foreground) = highlight_colors(cx);
+		self.theme_id = tree_sitter_theme_id(&self.syntect, &self.colors, self.foreground, cx);
+		true
+	}
+
+	fn theme_id(&self) -> &ThemeId {
+		&self.theme_id
+	}
+
+	fn language_names(&self) -> Vec<&str> {
+		let mut names = self.syntect.language_names();
+		names.extend(grammars().iter().map(|grammar| grammar.name));
+		names.sort_by_key(|name| name.to_lowercase());
+		names.dedup();
+		names
+	}
+
+	fn language_override(&self, file_path: &Path) -> Option<&str> {
+		self.syntect.language_override(file_path)
+	}
+
+	fn set_language_override(&mut self, file_path: &Path, language: Option<String>) {
+		self.syntect.set_language_override(file_path, language);
+	}
+
+	fn language_name(&self, content: &str, file_path: &Path) -> &str {
+		match self.grammar_for(content, file_path) {
+			Some(grammar) => grammar.name,
+			None => self.syntect.language_name(content, file_path),
+		}
+	}
+
+	fn context(&self, content: &str, file_path: &Path) -> HighlightContext {
+		match self.grammar_for(content, file_path) {
+			Some(grammar) => HighlightContext::TreeSitter(TreeSitterContext {
+				grammar,
+				colors: self.colors.clone(),
+				foreground: self.foreground,
+				parsed: Arc::default(),
+			}),
+			None => self.syntect.context(content, file_path),
+		}
+	}
+}
+
+/// What highlighting one file with tree-sitter takes
+#[derive(Clone)]
+pub struct TreeSitterContext {
+	grammar: &'static Grammar,
+	colors: Arc<Vec<Hsla>>,
+	foreground: Hsla,
+	/// Tree of the text last highlighted, shared by the batches highlighting the same version of it
+	parsed: Arc<Mutex<Option<ParsedText>>>,
+}
+
+struct ParsedText {
+	text: Arc<str>,
+	tree: Tree,
+}
+
+impl TreeSitterContext {
+	pub fn syntax_name(&self) -> &str {
+		self.grammar.name
+	}
+
+	/// Tree-sitter has no state to resume from partway through a file. The whole text is parsed
+	/// once instead, and each batch only queries the tree for its own lines.
+	pub fn highlight(
+		&self,
+		text: &Arc<str>,
+		from: Checkpoint,
+		line_count: usize,
+	) -> HighlightBatch {
+		// Byte ranges of the lines' text, which leaves out their terminators
+		let mut line_ranges = Vec::new();
+		let mut offset = from.offset;
+		for text_line in text[from.offset..].split_inclusive('\n').take(line_count) {
+			let line_text = text_line
+				.strip_suffix('\n')
+				.map_or(text_line, |line| line.strip_suffix('\r').unwrap_or(line));
+			line_ranges.push(offset..offset + line_text.len());
+			offset += text_line.len();
+		}
+
+		let checkpoints = line_ranges
+			.iter()
+			.enumerate()
+			.map(|(ix, range)| (from.line + ix, range.start))
+			.filter(|(line, _)| line % CHECKPOINT_INTERVAL == 0)
+			.map(|(line, offset)| Checkpoint::stateless(line, offset))
+			.collect();
+		let lines = from.line..from.line + line_ranges.len();
+
+		let tree = self.tree(text);
+		HighlightBatch {
+			lines: self.highlight_lines(text, tree.as_ref(), &line_ranges),
+			definitions: tree
+				.map(|tree| self.definitions(text, &tree, lines.clone(), from.offset..offset))
+				.unwrap_or_default(),
+			checkpoints,
+			end: Checkpoint::stateless(lines.end, offset),
+		}
+	}
+
+	/// Tree of `text`, which is only parsed when it's another version than the last batch's
+	fn tree(&self, text: &Arc<str>) -> Option<Tree> {
+		let mut parsed = self.parsed.lock().ok()?;
+		if let Some(parsed) = parsed
+			.as_ref()
+			.filter(|parsed| Arc::ptr_eq(&parsed.text, text))
+		{
+			return Some(parsed.tree.clone());
+		}
+
+		let mut parser = Parser::new();
+		parser.set_language(&self.grammar.language).ok()?;
+		let tree = parser.parse(text.as_bytes(), None)?;
+		*parsed = Some(ParsedText {
+			text: text.clone(),
+			tree: tree.clone(),
+		});
+		Some(tree)
+	}
+
+	fn highlight_lines(
+		&self,
+		text: &str,
+		tree: Option<&Tree>,
+		line_ranges: &[Range<usize>],
+	) -> Vec<Vec<HighlightRun>> {
+		let mut lines = vec![Vec::new(); line_ranges.len()];
+		let (Some(first), Some(last)) = (line_ranges.first(), line_ranges.last()) else {
+			return lines;
+		};
+		let batch = first.start..last.end;
+
+		// Highlight of each byte of the batch. Text without one, or which failed to parse, is drawn
+		// in the fallback colour.
+		let mut highlights = vec![None; batch.len()];
+		if let Some(tree) = tree {
+			for (range, highlight) in self.captured_ranges(text, tree, batch.clone()) {
+				let start = range.start.clamp(batch.start, batch.end) - batch.start;
+				let end = range.end.clamp(batch.start, batch.end) - batch.start;
+				highlights[start..end].fill(Some(highlight));
+			}
+		}
+
+		for (range, runs) in line_ranges.iter().zip(&mut lines) {
+			for highlight in &highlights[range.start - batch.start..range.end - batch.start] {
+				let color = highlight
+					.and_then(|highlight| self.colors.get(highlight))
+					.copied()
+					.unwrap_or(self.foreground);
+				match runs.last_mut() {
+					Some(run) if run.color == color => run.byte_len += 1,
+					_ => runs.push(HighlightRun { byte_len: 1, color }),
+				}
+			}
+		}
+		lines
+	}
+
+	/// Byte ranges among `bytes` captured by the highlights query, with their highlight. Captures
+	/// come before those nested in them, so that painting them in order leaves the innermost on
+	/// top. A node captured by several patterns takes the highlight of the first.
+	fn captured_ranges(
+		&self,
+		text: &str,
+		tree: &Tree,
+		bytes: Range<usize>,
+	) -> Vec<(Range<usize>, usize)> {
+		let mut cursor = QueryCursor::new();
+		cursor.set_byte_range(bytes);
+		let mut captures = Vec::new();
+		let mut query_captures =
+			cursor.captures(&self.grammar.highlights, tree.root_node(), text.as_bytes());
+		while let Some((query_match, capture_ix)) = query_captures.next() {
+			let capture = query_match.captures[*capture_ix];
+			if let Some(highlight) = self.grammar.capture_highlights[capture.index as usize] {
+				captures.push(CapturedRange {
+					order: captures.len(),
+					node: capture.node.id(),
+					pattern: query_match.pattern_index,
+					range: capture.node.byte_range(),
+					highlight,
+				});
+			}
+		}
+
+		captures.sort_by_key(|capture| (capture.node, capture.pattern));
+		captures.dedup_by_key(|capture| capture.node);
+		// Nodes spanning the same bytes, like a parent with a single child, keep the order they
+		// were captured in
+		captures.sort_by_key(|capture| {
+			(
+				capture.range.start,
+				Reverse(capture.range.end),
+				capture.order,
+			)
+		});
+		captures
+			.into_iter()
+			.map(|capture| (capture.range, capture.highlight))
+			.collect()
+	}
+
+	/// Lines among `lines` which name a definition, found by the grammar's tags query
+	fn definitions(
+		&self,
+		text: &str,
+		tree: &Tree,
+		lines: Range<usize>,
+		bytes: Range<usize>,
+	) -> Vec<usize> {
+		let Some(tags) = &self.grammar.tags else {
+			return Vec::new();
+		};
+
+		let capture_names = tags.capture_names();
+		let mut cursor = QueryCursor::new();
+		cursor.set_byte_range(bytes);
+		let mut definitions = Vec::new();
+		let mut matches = cursor.matches(tags, tree.root_node(), text.as_bytes());
+		while let Some(tag) = matches.next() {
+			let is_definition = tag
+				.captures
+				.iter()
+				.any(|capture| capture_names[capture.index as usize].starts_with("definition."));
+			let name = tag
+				.captures
+				.iter()
+				.find(|capture| capture_names[capture.index as usize] == "name");
+			if let (true, Some(name)) = (is_definition, name) {
+				definitions.push(name.node.start_position().row);
+			}
+		}
+		definitions.retain(|line| lines.contains(line));
+		definitions.sort_unstable();
+		definitions.dedup();
+		definitions
+	}
+}
+
+/// A capture of the highlights query, while sorting out which colour each byte takes
+struct CapturedRange {
+	order: usize,
+	node: usize,
+	pattern: usize,
+	range: Range<usize>,
+	highlight: usize,
+}
+
+/// Theme of both the capture colours and syntect's, which highlights files without a grammar
+fn tree_sitter_theme_id(
+	syntect: &SyntaxHighlighter,
+	colors: &[Hsla],
+	foreground: Hsla,
+	cx: &App,
+) -> ThemeId {
+	let content = (syntect.theme_id().content_hash, colors, foreground);
+	ThemeId::new(&cx.theme().name, content)
+}
+
+/// Colours of `HIGHLIGHT_NAMES` in the active GPUI theme, and of text without a capture. A name
+/// the theme doesn't colour takes the colour of its parent, like `type` for `type.builtin`.
+fn highlight_colors(cx: &App) -> (Arc<Vec<Hsla>>, Hsla) {
+	let theme = cx.theme();
+	let foreground = theme.colors().editor_foreground;
+	let highlights = &theme.syntax().highlights;
+	let color_of = |mut name: &str| loop {
+		let color = highlights
+			.iter()
+			.find(|(highlight, _)| highlight == name)
+			.and_then(|(_, style)| style.color);
+		if color.is_some() {
+			return color;
+		}
+		name = name.rsplit_once('.')?.0;
+	};
+
+	let colors = HIGHLIGHT_NAMES
+		.iter()
+		.map(|name| color_of(name).unwrap_or(foreground))
+		.collect();
+	(Arc::new(colors), foreground)
+}
@@ -0,0 +1,327 @@
+// The user's settings.json. Keys GPUI's own settings know, like the theme and buffer font, go to
+// the SettingsStore; hx_diff's own keys are read into `HxDiffSettings`.
+use crate::common::ThemeGeneration;
+use crate::paths;
+use gpui::{App, Global};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use settings::{Settings, SettingsStore};
+use std::path::Path;
+use std::sync::Arc;
+use theme::ActiveTheme;
+
+/// Theme used when the settings don't name one
+pub const DEFAULT_THEME: &str = "One Dark";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineNumbers {
+	#[default]
+	Both,
+	Old,
+	New,
+	Off,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViewMode {
+	/// The diff of the selected file
+	#[default]
+	File,
+	/// Every file one after another, as with View > Review All Files
+	Review,
+}
+
+/// Library syntax highlighting is done with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HighlighterBackend {
+	#[default]
+	Syntect,
+	/// Only for the languages with a vendored grammar, when built with the `tree-sitter` feature
+	TreeSitter,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HxDiffSettings {
+	/// Tab width of files whose `.editorconfig` doesn't give one
+	pub tab_size: usize,
+	pub line_numbers: LineNumbers,
+	/// What the window shows when it opens
+	pub view_mode: ViewMode,
+	/// Overridden by `$HX_DIFF_HIGHLIGHTER`
+	pub highlighter: HighlighterBackend,
+	/// How hunks are grouped: changes with fewer than twice this many unchanged lines between
+	/// them share a hunk header. The whole file is shown either way.
+	pub context_lines: usize,
+	pub file_list_width: f32,
+	pub window_width: f32,
+	pub window_height: f32,
+}
+
+impl Default for HxDiffSettings {
+	fn default() -> Self {
+		Self {
+			tab_size: 4,
+			line_numbers: LineNumbers::Both,
+			view_mode: ViewMode::File,
+			highlighter: HighlighterBackend::Syntect,
+			context_lines: 3,
+			file_list_width: 300.,
+			window_width: 1024.,
+			window_height: 600.,
+		}
+	}
+}
+
+impl Global for HxDiffSettings {}
+
+impl HxDiffSettings {
+	pub fn get(cx: &App) -> &Self {
+		cx.global::<Self>()
+	}
+
+	/// Reads hx_diff's keys from the settings file. A key with an invalid value keeps its value
+	/// from `previous` and is passed to `warn`, without affecting the other keys.
+	fn from_file(
+		contents: &Map<String, Value>,
+		previous: &Self,
+		mut warn: impl FnMut(&str, serde_json::Error),
+	) -> Self {
+		let mut settings = Self::default();
+		read_key(
+			contents,
+			"tab_size",
+			&mut settings.tab_size,
+			&previous.tab_size,
+			&mut warn,
+		);
+		read_key(
+			contents,
+			"line_numbers",
+			&mut settings.line_numbers,
+			&previous.line_numbers,
+			&mut warn,
+		);
+		read_key(
+			contents,
+			"view_mode",
+			&mut settings.view_mode,
+			&previous.view_mode,
+			&mut warn,
+		);
+		read_key(
+			contents,
+			"highlighter",
+			&mut settings.highlighter,
+			&previous.highlighter,
+			&mut warn,
+		);
+		read_key(
+			contents,
+			"context_lines",
+			&mut settings.context_lines,
+			&previous.context_lines,
+			&mut warn,
+		);
+		read_key(
+			contents,
+			"file_list_width",
+			&mut settings.file_list_width,
+			&previous.file_list_width,
+			&mut warn,
+		);
+		read_key(
+			contents,
+			"window_width",
+			&mut settings.window_width,
+			&previous.window_width,
+			&mut warn,
+		);
+		read_key(
+			contents,
+			"window_height",
+			&mut settings.window_height,
+			&previous.window_height,
+			&mut warn,
+		);
+		settings
+	}
+}
+
+/// Sets `field` to the value of `key`, when the file has it
+fn read_key<T: DeserializeOwned + Clone>(
+	contents: &Map<String, Value>,
+	key: &str,
+	field: &mut T,
+	previous: &T,
+	warn: &mut impl FnMut(&str, serde_json::Error),
+) {
+	let Some(value) = contents.get(key) else {
+		return;
+	};
+	match T::deserialize(value) {
+		Ok(value) => *field = value,
+		Err(error) => {
+			*field = previous.clone();
+			warn(key, error);
+		}
+	}
+}
+
+/// Loads the settings file, if there is one, before the window opens
+pub fn init(cx: &mut App) {
+	cx.set_global(HxDiffSettings::default());
+	reload(cx);
+}
+
+/// Indent of the keys written to the settings file
+const FILE_TAB_SIZE: usize = 2;
+
+/// The file's text, which is empty when it doesn't exist
+fn read_file_text(path: &Path) -> Result<String, String> {
+	match std::fs::read_to_string(path) {
+		Ok(text) => Ok(text),
+		Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+		Err(error) => Err(error.to_string()),
+	}
+}
+
+fn parse_file_text(text: &str) -> Result<Map<String, Value>, String> {
+	if text.trim().is_empty() {
+		return Ok(Map::new());
+	}
+	settings::parse_json_with_comments(text).map_err(|error| error.to_string())
+}
+
+/// The file's settings, or none when it doesn't exist
+fn read_file(path: &Path) -> Result<Map<String, Value>, String> {
+	parse_file_text(&read_file_text(path)?)
+}
+
+/// Applies the settings file. A file which doesn't parse leaves the settings as they were, and an
+/// invalid value of one of hx_diff's own keys leaves just that setting as it was.
+pub fn reload(cx: &mut App) {
+	let Some(path) = paths::settings_file() else {
+		return;
+	};
+	let contents = match read_file(&path) {
+		Ok(contents) => contents,
+		Err(error) => {
+			eprintln!("Warning: Couldn't read {}: {}", path.display(), error);
+			return;
+		}
+	};
+
+	let previous_theme = cx.theme().clone();
+	let user_settings = Value::Object(contents.clone()).to_string();
+	cx.update_global::<SettingsStore, _>(|store, cx| {
+		// Mistakes in hx_diff's own keys are reported below; GPUI's settings ignore what they
+		// don't understand
+		let _ = store.set_user_settings(&user_settings, cx);
+	});
+	if !contents.contains_key("theme") {
+		let mut theme_settings = theme::ThemeSettings::get_global(cx).clone();
+		theme_settings.theme =
+			theme::ThemeSelection::Static(settings::ThemeName(DEFAULT_THEME.into()));
+		theme::ThemeSettings::override_global(theme_settings, cx);
+	}
+
+	let hx_diff_settings =
+		HxDiffSettings::from_file(&contents, HxDiffSettings::get(cx), |key, error| {
+			eprintln!(
+				"Warning: Invalid \"{}\" setting in {}: {}",
+				key,
+				path.display(),
+				error
+			)
+		});
+	if *HxDiffSettings::get(cx) != hx_diff_settings {
+		cx.set_global(hx_diff_settings);
+	}
+
+	// The theme follows the settings once their observers have run. Themes are shared from the
+	// registry, so only another one needs everything derived from the colours redone.
+	cx.defer(move |cx| {
+		if !Arc::ptr_eq(&previous_theme, cx.theme()) {
+			ThemeGeneration::bump(cx);
+			cx.refresh_windows();
+		}
+	});
+	cx.refresh_windows();
+}
+
+/// Sets a key of the settings file, like after picking another theme, or removes it with `None`.
+/// Only the key's value is edited, which keeps the rest of the file as the user wrote it, comments
+/// included.
+pub fn update_file(key: &str, value: Option<Value>) {
+	let Some(path) = paths::settings_file() else {
+		return;
+	};
+	// Editing a file which doesn't parse could make it worse
+	let text = read_file_text(&path).and_then(|text| parse_file_text(&text).map(|_| text));
+	let mut text = match text {
+		Ok(text) => text,
+		Err(error) => {
+			eprintln!(
+				"Warning: Didn't save settings, couldn't read {}: {}",
+				path.display(),
+				error
+			);
+			return;
+		}
+	};
+	if text.trim().is_empty() {
+		text = "{\n}\n".to_string();
+	}
+
+	let (range, replacement) =
+		settings::replace_value_in_json_text(&text, &[key], FILE_TAB_SIZE, value.as_ref(), None);
+	text.replace_range(range, &replacement);
+
+	let written = path
+		.parent()
+		.map_or(Ok(()), std::fs::create_dir_all)
+		.and_then(|_| std::fs::write(&path, text));
+	if let Err(error) = written {
+		eprintln!("Warning: Couldn't save {}: {}", path.display(), error);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn an_invalid_key_keeps_only_its_previous_value() {
+		let previous = HxDiffSettings {
+			tab_size: 8,
+			context_lines: 5,
+			..HxDiffSettings::default()
+		};
+		let contents = serde_json::json!({
+			"tab_size": "wide",
+			"context_lines": 1,
+			"line_numbers": "old",
+			"theme": "One Light",
+		});
+		let mut invalid_keys = Vec::new();
+		let settings =
+			HxDiffSettings::from_file(contents.as_object().unwrap(), &previous, |key, _error| {
+				invalid_keys.push(key.to_string())
+			});
+
+		assert_eq!(invalid_keys, ["tab_size"]);
+		assert_eq!(settings.tab_size, 8);
+		assert_eq!(settings.context_lines, 1);
+		assert_eq!(settings.line_numbers, LineNumbers::Old);
+		// Keys missing from the file go back to their defaults
+		assert_eq!(settings.view_mode, ViewMode::File);
+		assert_eq!(
+			settings.window_width,
+			HxDiffSettings::default().window_width
+		);
+	}
+}
@@ -1,8 +1,12 @@
+pub mod command_palette;
 pub mod diff_pane;
 pub mod file_list;
 pub mod hx_diff;
 
+pub use command_palette::CommandPalette;
+pub use command_palette::CommandPaletteEvent;
 pub use diff_pane::DiffPane;
+pub use diff_pane::DiffPaneEvent;
 pub use file_list::FileList;
 pub use file_list::FileListEvent;
 pub use hx_diff::HxDiff;
@@ -0,0 +1,399 @@
+// The command palette: a fuzzy filtered list of the actions available where the focus was, or
+// of the workspace's files for going to one of them
+use super::diff_pane::edit_text_field;
+use crate::common::clean_path_display;
+use crate::workspace::{CategoryKind, EntryKind, ProjectEntryId, Workspace};
+use gpui::prelude::*;
+use gpui::*;
+use std::ops::Range;
+use theme::ActiveTheme;
+
+actions!(
+	command_palette,
+	[
+		Toggle,
+		GoToFile,
+		SelectNext,
+		SelectPrevious,
+		Confirm,
+		Dismiss
+	]
+);
+
+const ROW_HEIGHT: Pixels = px(28.);
+/// Rows shown before the list scrolls
+const MAX_VISIBLE_ROWS: usize = 12;
+
+#[derive(Debug)]
+pub enum CommandPaletteEvent {
+	Dismissed,
+	/// Run on the view which had the focus before the palette opened
+	RunAction(Box<dyn Action>),
+	OpenedEntry {
+		entry_id: ProjectEntryId,
+	},
+}
+
+enum Target {
+	Action(Box<dyn Action>),
+	Entry(ProjectEntryId),
+}
+
+struct Candidate {
+	label: SharedString,
+	/// Key binding of an action, or category of a file
+	detail: Option<SharedString>,
+	target: Target,
+}
+
+pub struct CommandPalette {
+	placeholder: SharedString,
+	candidates: Vec<Candidate>,
+	query: String,
+	/// Indices of the candidates matching the query, best match first
+	matches: Vec<usize>,
+	selected: usize,
+	focus_handle: FocusHandle,
+	scroll_handle: UniformListScrollHandle,
+}
+
+impl EventEmitter<CommandPaletteEvent> for CommandPalette {}
+
+/// Turns `diff_pane::NextDifference` into `diff pane: next difference`
+fn humanize_action_name(name: &str) -> String {
+	let (namespace, action) = name.rsplit_once("::").unwrap_or(("", name));
+	let mut humanized = namespace.replace("::", " ").replace('_', " ");
+	if !humanized.is_empty() {
+		humanized.push(':');
+	}
+	for char in action.chars() {
+		if char.is_uppercase() {
+			if !humanized.is_empty() {
+				humanized.push(' ');
+			}
+			humanized.extend(char.to_lowercase());
+		} else {
+			humanized.push(char);
+		}
+	}
+	humanized
+}
+
+/// How well `query` matches `text` as a case insensitive subsequence, ignoring the query's
+/// spaces, or none when it doesn't. Characters matched one after another or at the start of a
+/// word score higher.
+fn fuzzy_score(query: &str, text: &str) -> Option<usize> {
+	let mut score = 0;
+	let mut text_chars = text.chars();
+	let mut previous: Option<char> = None;
+	let mut follows_match = false;
+	for query_char in query.chars().filter(|char| !char.is_whitespace()) {
+		loop {
+			let char = text_chars.next()?;
+			let is_word_start = previous.is_none_or(|previous| {
+				!previous.is_alphanumeric() || (previous.is_lowercase() && char.is_uppercase())
+			});
+			previous = Some(char);
+
+			if char.to_lowercase().eq(query_char.to_lowercase()) {
+				score += 1;
+				if follows_match {
+					score += 2;
+				}
+				if is_word_start {
+					score += 3;
+				}
+				follows_match = true;
+				break;
+			}
+			follows_match = false;
+		}
+	}
+	Some(score)
+}
+
+impl CommandPalette {
+	/// Lists the actions which can be dispatched to the focused view, with their key bindings
+	pub fn commands(window: &mut Window, cx: &mut App) -> Entity<Self> {
+		let mut candidates = window
+			.available_actions(cx)
+			.into_iter()
+			.filter(|action| !action.as_any().is::<Toggle>())
+			.map(|action| {
+				let binding = window.bindings_for_action(action.as_ref()).pop();
+				let detail = binding.map(|binding| {
+					binding
+						.keystrokes()
+						.iter()
+						.map(ToString::to_string)
+						.collect::<Vec<_>>()
+						.join(" ")
+						.into()
+				});
+				Candidate {
+					label: humanize_action_name(action.name()).into(),
+					detail,
+					target: Target::Action(action),
+				}
+			})
+			.collect::<Vec<_>>();
+		candidates.sort_by(|a, b| a.label.cmp(&b.label));
+		candidates.dedup_by(|a, b| a.label == b.label);
+
+		Self::new("Run a command…", candidates, cx)
+	}
+
+	/// Lists the workspace's files, with the category each one is listed under
+	pub fn files(workspace: &Workspace, cx: &mut App) -> Entity<Self> {
+		let mut category = None;
+		let mut candidates = Vec::new();
+		for entry in &workspace.entries {
+			match entry.kind {
+				EntryKind::Category(CategoryKind::Staged) => category = Some("Staged"),
+				EntryKind::Category(CategoryKind::Working) => category = Some("Unstaged"),
+				EntryKind::Category(CategoryKind::Commit) => category = None,
+				EntryKind::Directory(_) => {}
+				EntryKind::File(_) => candidates.push(Candidate {
+					label: clean_path_display(&entry.path).into(),
+					detail: category.map(SharedString::from),
+					target: Target::Entry(entry.id),
+				}),
+			}
+		}
+
+		Self::new("Go to file…", candidates, cx)
+	}
+
+	fn new(placeholder: &'static str, candidates: Vec<Candidate>, cx: &mut App) -> Entity<Self> {
+		cx.new(|cx| Self {
+			placeholder: placeholder.into(),
+			matches: (0..candidates.len()).collect(),
+			candidates,
+			query: String::new(),
+			selected: 0,
+			focus_handle: cx.focus_handle(),
+			scroll_handle: UniformListScrollHandle::new(),
+		})
+	}
+
+	/// Matches the candidates against the query. Among equal scores shorter labels come first,
+	/// then the candidates' own order.
+	fn update_matches(&mut self, cx: &mut Context<Self>) {
+		let mut scored = self
+			.candidates
+			.iter()
+			.enumerate()
+			.filter_map(|(ix, candidate)| {
+				let score = fuzzy_score(&self.query, &candidate.label)?;
+				Some((score, ix))
+			})
+			.collect::<Vec<_>>();
+		scored.sort_by(|(a_score, a_ix), (b_score, b_ix)| {
+			b_score.cmp(a_score).then_with(|| {
+				let (a, b) = (&self.candidates[*a_ix], &self.candidates[*b_ix]);
+				a.label.len().cmp(&b.label.len())
+			})
+		});
+		self.matches = scored.into_iter().map(|(_, ix)| ix).collect();
+		self.select(0, cx);
+	}
+
+	fn select(&mut self, ix: usize, cx: &mut Context<Self>) {
+		self.selected = ix;
+		if ix < self.matches.len() {
+			self.scroll_handle.scroll_to_item(ix, ScrollStrategy::Top);
+		}
+		cx.notify();
+	}
+
+	fn select_next(&mut self, _: &SelectNext, _window: &mut Window, cx: &mut Context<Self>) {
+		if !self.matches.is_empty() {
+			self.select((self.selected + 1) % self.matches.len(), cx);
+		}
+	}
+
+	fn select_previous(
+		&mut self,
+		_: &SelectPrevious,
+		_window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		if !self.matches.is_empty() {
+			let count = self.matches.len();
+			self.select((self.selected + count - 1) % count, cx);
+		}
+	}
+
+	fn confirm(&mut self, _: &Confirm, _window: &mut Window, cx: &mut Context<Self>) {
+		let Some(&ix) = self.matches.get(self.selected) else {
+			return;
+		};
+		match &self.candidates[ix].target {
+			Target::Action(action) => {
+				cx.emit(CommandPaletteEvent::RunAction(action.boxed_clone()));
+			}
+			&Target::Entry(entry_id) => {
+				cx.emit(CommandPaletteEvent::OpenedEntry { entry_id });
+			}
+		}
+	}
+
+	fn dismiss(&mut self, _: &Dismiss, _window: &mut Window, cx: &mut Context<Self>) {
+		cx.emit(CommandPaletteEvent::Dismissed);
+	}
+
+	fn handle_key_down(
+		&mut self,
+		event: &KeyDownEvent,
+		_window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		if edit_text_field(&mut self.query, event) {
+			cx.stop_propagation();
+			self.update_matches(cx);
+		}
+	}
+
+	fn render_match(&self, ix: usize, cx: &mut Context<Self>) -> impl IntoElement {
+		let colors = cx.theme().colors();
+		let candidate = &self.candidates[self.matches[ix]];
+		div()
+			.id(ix)
+			.h(ROW_HEIGHT)
+			.flex()
+			.flex_row()
+			.items_center()
+			.px_2()
+			.gap_2()
+			.text_sm()
+			.when(ix == self.selected, |div| div.bg(colors.element_selected))
+			.hover(|style| style.bg(colors.element_hover))
+			.child(
+				div()
+					.flex_grow()
+					.overflow_hidden()
+					.text_color(colors.text)
+					.child(candidate.label.clone()),
+			)
+			.children(
+				candidate
+					.detail
+					.clone()
+					.map(|detail| div().text_color(colors.text_muted).child(detail)),
+			)
+			.on_click(cx.listener(move |this, _event: &ClickEvent, window, cx| {
+				this.selected = ix;
+				this.confirm(&Confirm, window, cx);
+			}))
+	}
+}
+
+impl Focusable for CommandPalette {
+	fn focus_handle(&self, _cx: &App) -> FocusHandle {
+		self.focus_handle.clone()
+	}
+}
+
+impl Render for CommandPalette {
+	fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+		let colors = cx.theme().colors();
+		let visible_rows = self.matches.len().clamp(1, MAX_VISIBLE_ROWS);
+
+		let query = div()
+			.px_2()
+			.py_1()
+			.border_b_1()
+			.border_color(colors.border)
+			.map(|div| {
+				if self.query.is_empty() {
+					div.text_color(colors.text_placeholder)
+						.child(self.placeholder.clone())
+				} else {
+					div.text_color(colors.text).child(self.query.clone())
+				}
+			});
+
+		let list = if self.matches.is_empty() {
+			div()
+				.h(ROW_HEIGHT)
+				.px_2()
+				.text_sm()
+				.text_color(colors.text_muted)
+				.child("No matches")
+				.into_any_element()
+		} else {
+			uniform_list("command-palette-matches", self.matches.len(), {
+				cx.processor(|this, range: Range<usize>, _window, cx| {
+					range.map(|ix| this.render_match(ix, cx)).collect()
+				})
+			})
+			.track_scroll(&self.scroll_handle)
+			.h(ROW_HEIGHT * visible_rows as f32)
+			.into_any_element()
+		};
+
+		div()
+			.key_context("CommandPalette")
+			.track_focus(&self.focus_handle)
+			.on_action(cx.listener(Self::select_next))
+			.on_action(cx.listener(Self::select_previous))
+			.on_action(cx.listener(Self::confirm))
+			.on_action(cx.listener(Self::dismiss))
+			.on_key_down(cx.listener(Self::handle_key_down))
+			.on_mouse_down_out(cx.listener(|_, _event: &MouseDownEvent, _window, cx| {
+				cx.emit(CommandPaletteEvent::Dismissed);
+			}))
+			.w(px(560.))
+			.flex()
+			.flex_col()
+			.border_1()
+			.rounded_md()
+			.border_color(colors.border)
+			.bg(colors.elevated_surface_background)
+			.shadow_lg()
+			.occlude()
+			.child(query)
+			.child(list)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn action_names_are_humanized() {
+		assert_eq!(
+			humanize_action_name("diff_pane::NextDifference"),
+			"diff pane: next difference"
+		);
+		assert_eq!(
+			humanize_action_name("editor::actions::SelectAll"),
+			"editor actions: select all"
+		);
+		assert_eq!(humanize_action_name("Quit"), "quit");
+	}
+
+	#[test]
+	fn word_starts_and_consecutive_matches_score_higher() {
+		// Each match scores 1, plus 2 after another match and 3 at a word start
+		assert_eq!(fuzzy_score("nd", "next difference"), Some(8));
+		assert_eq!(fuzzy_score("ne", "next difference"), Some(7));
+		assert_eq!(fuzzy_score("xt", "next difference"), Some(4));
+		assert_eq!(fuzzy_score("ND", "NextDifference"), Some(8));
+		assert_eq!(fuzzy_score("", "anything"), Some(0));
+
+		assert!(fuzzy_score("nd", "next difference") > fuzzy_score("nd", "undo"));
+		assert_eq!(fuzzy_score("dn", "next difference"), Some(5));
+		assert_eq!(fuzzy_score("xyz", "next difference"), None);
+	}
+
+	#[test]
+	fn spaces_in_the_query_are_ignored() {
+		assert_eq!(
+			fuzzy_score("next diff", "next difference"),
+			fuzzy_score("nextdiff", "next difference")
+		);
+		assert_eq!(fuzzy_score(" n  d ", "nextdifference"), Some(5));
+	}
+}
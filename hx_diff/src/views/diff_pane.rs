@@ -1,42 +1,178 @@
+mod commit_header;
 mod diff_element;
+mod display_map;
+mod document;
+mod edit_buffer;
+mod editing;
+mod find_bar;
+mod go_to_line;
+mod highlight_cache;
+mod highlighting;
+mod line_cache;
+mod review_stream;
+mod search;
+mod selection;
+mod summary;
+mod whitespace;
 
-use std::{cell::Cell, rc::Rc};
+use std::{
+	cell::{Cell, RefCell},
+	path::PathBuf,
+	rc::Rc,
+	sync::Arc,
+};
 
-use self::workspace::{EntryKind, FileEntry, FileSource, ProjectEntryId, Workspace};
-use crate::syntax::{HighlightRun, SyntaxHighlighter};
+use self::workspace::{CategoryKind, EntryKind, FileEntry, FileSource, ProjectEntryId, Workspace};
+use crate::editorconfig::{EditorConfig, IndentStyle};
+use crate::external_editor::{self, EditorLocation};
+use crate::syntax::{self, HighlightBackend};
+use crate::user_settings::{HxDiffSettings, LineNumbers};
 use crate::*;
-use diff_element::DiffElement;
+use diff_element::{DiffElement, ShapedLineCache};
+use display_map::DisplayMap;
+pub use document::{DiffDocument, DiffType};
+use edit_buffer::EditBuffer;
+use editing::PendingRediff;
+pub(crate) use find_bar::edit_text_field;
+use find_bar::SearchState;
 use git_cli_wrap as git;
+use go_to_line::GoToLineState;
+use gpui::prelude::*;
 use gpui::*;
-use similar::{ChangeTag, TextDiff};
+use highlight_cache::ContentKey;
+use highlighting::LazyHighlights;
+use review_stream::ReviewState;
+use selection::{CopyMode, Granularity, TextPosition, TextSelection};
+use summary::Summary;
 use theme::ThemeSettings;
 
-actions!(diff_pane, [PreviousDifference, NextDifference]);
+actions!(
+	diff_pane,
+	[
+		PreviousDifference,
+		NextDifference,
+		CycleLineNumbers,
+		ToggleSoftWrap,
+		ToggleWhitespace,
+		Copy,
+		CopyNewSide,
+		CopyOldSide,
+		CopyAsPatch,
+		Find,
+		DismissFind,
+		SelectNextMatch,
+		SelectPreviousMatch,
+		MoveUp,
+		MoveDown,
+		PageUp,
+		PageDown,
+		MoveToBeginning,
+		MoveToEnd,
+		GoToLine,
+		ConfirmGoToLine,
+		DismissGoToLine,
+		CenterSelection,
+		Backspace,
+		Delete,
+		Newline,
+		Tab,
+		MoveLeft,
+		MoveRight,
+		MoveToLineStart,
+		MoveToLineEnd,
+		Undo,
+		Redo,
+		Save,
+		ToggleReviewStream,
+		OpenInEditor,
+		SelectLanguage,
+	]
+);
 
-#[derive(Clone, PartialEq, Copy)]
-pub enum DiffType {
-	_Header,
-	Normal,
-	Added,
-	Removed,
+#[derive(Debug)]
+pub enum DiffPaneEvent {
+	/// Next difference was asked for after the last change of the file
+	PassedLastDifference,
+	/// Previous difference was asked for before the first change of the file
+	PassedFirstDifference,
+	/// The review stream was scrolled to another file
+	ScrolledToEntry { entry_id: ProjectEntryId },
+	/// A file was clicked on a summary page
+	OpenedEntry { entry_id: ProjectEntryId },
 }
 
-#[derive(Clone)]
-pub struct DiffLine {
-	pub text: SharedString,
-	pub diff_type: DiffType,
-	pub _old_index: Option<usize>,
-	pub new_index: Option<usize>,
-	pub highlight_runs: Vec<HighlightRun>,
+/// Which line number columns are shown in the gutter
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LineNumberColumns {
+	Old,
+	New,
+	Both,
+}
+
+impl LineNumberColumns {
+	/// Columns shown for the setting, if any
+	fn from_setting(line_numbers: LineNumbers) -> Option<Self> {
+		match line_numbers {
+			LineNumbers::Both => Some(Self::Both),
+			LineNumbers::Old => Some(Self::Old),
+			LineNumbers::New => Some(Self::New),
+			LineNumbers::Off => None,
+		}
+	}
+
+	pub fn show_old(self) -> bool {
+		matches!(self, Self::Old | Self::Both)
+	}
+
+	pub fn show_new(self) -> bool {
+		matches!(self, Self::New | Self::Both)
+	}
+
+	fn column_count(self) -> usize {
+		match self {
+			Self::Both => 2,
+			_ => 1,
+		}
+	}
+
+	fn next(self) -> Self {
+		match self {
+			Self::Both => Self::Old,
+			Self::Old => Self::New,
+			Self::New => Self::Both,
+		}
+	}
 }
 
 #[derive(Clone, Debug)]
 pub struct GutterDimensions {
-	// pub left_padding: Pixels,
 	pub right_padding: Pixels,
+	/// Width of a single line number column, including the padding on its left
+	pub column_width: Pixels,
 	pub width: Pixels,
 }
 
+impl GutterDimensions {
+	/// Offset from the left of the gutter where old line numbers are right-aligned to
+	pub fn old_column_right(&self) -> Pixels {
+		self.column_width
+	}
+
+	/// Offset from the left of the gutter where new line numbers are right-aligned to
+	pub fn new_column_right(&self) -> Pixels {
+		self.width - self.right_padding
+	}
+}
+
+/// What the pane shows for the open entry
+enum Page {
+	Diff,
+	/// The commit's details, for its category in `show` mode
+	CommitDetails,
+	/// The files of a category or directory
+	Summary(Summary),
+}
+
 enum MoveDirection {
 	Up,
 	Down,
@@ -48,17 +184,53 @@ pub struct DiffStyle {
 
 pub struct DiffPane {
 	style: DiffStyle,
-	diff_text: SharedString,
-	diff_lines: Vec<DiffLine>,
+	document: Arc<DiffDocument>,
 	workspace: Entity<Workspace>,
+	/// Path of the open file in the working tree
+	file_path: Option<PathBuf>,
 	show_line_numbers: bool,
+	line_number_columns: LineNumberColumns,
+	/// Tab width of the open file, from its .editorconfig or the settings
 	pub tab_size: usize,
+	/// Indent style of the open file, from its .editorconfig. Indentation not following it is
+	/// highlighted when whitespace is shown.
+	indent_style: Option<IndentStyle>,
+	soft_wrap: bool,
+	show_whitespace: bool,
+	/// Vertical scroll position, in display rows
 	scroll_y: f32,
+	scroll_x: Pixels,
+	display_map: Rc<DisplayMap>,
 	last_bounds: Option<Bounds<Pixels>>,
 	focus_handle: FocusHandle,
 	selection: Option<usize>,
+	text_selection: Option<TextSelection>,
+	is_selecting_text: bool,
+	context_menu: Option<(Entity<ui::ContextMenu>, gpui::Point<Pixels>, Subscription)>,
+	search: SearchState,
+	find_focus_handle: FocusHandle,
+	go_to_line: GoToLineState,
+	go_to_line_focus_handle: FocusHandle,
+	/// Scroll target requested before the pane was first laid out, and whether to also scroll
+	/// horizontally
+	pending_autoscroll: Option<(TextPosition, bool)>,
 	scrollbar_drag_state: Rc<Cell<Option<f32>>>,
-	syntax_highlighter: SyntaxHighlighter,
+	/// Distance from the left of the horizontal scrollbar thumb to the mouse while dragging it
+	horizontal_scrollbar_drag_state: Rc<Cell<Option<Pixels>>>,
+	line_cache: Rc<RefCell<ShapedLineCache>>,
+	syntax_highlighter: Box<dyn HighlightBackend>,
+	highlights: LazyHighlights,
+	/// The right file while it comes from the working tree, which makes that side editable
+	edit_buffer: Option<EditBuffer>,
+	/// Edit made while typing, diffed once the typing pauses
+	pending_rediff: Option<PendingRediff>,
+	/// Set on the panes inside the review stream, which are as tall as their rows and leave the
+	/// vertical scrolling to the stream
+	fit_to_rows: bool,
+	review: ReviewState,
+	page: Page,
+	/// Settings applied so far, to tell which ones a change to the settings file affects
+	settings: HxDiffSettings,
 }
 
 impl DiffPane {
@@ -81,24 +253,95 @@ impl DiffPane {
 			..Default::default()
 		};
 
-		let file_list = cx.new(|_cx| DiffPane {
-			style: DiffStyle { text: text_style },
-			diff_text: SharedString::from("Diff content goes here."),
-			diff_lines: Vec::new(),
-			workspace,
-			show_line_numbers: true,
-			tab_size: 4,
-			scroll_y: 0.0,
-			focus_handle,
-			last_bounds: None,
-			selection: None,
-			scrollbar_drag_state: Rc::new(Cell::new(None)),
-			syntax_highlighter: SyntaxHighlighter::new(),
+		let find_focus_handle = cx.focus_handle();
+		let go_to_line_focus_handle = cx.focus_handle();
+		let settings = HxDiffSettings::get(cx).clone();
+		let line_number_columns = LineNumberColumns::from_setting(settings.line_numbers);
+
+		let file_list = cx.new(|cx| {
+			cx.observe_global::<HxDiffSettings>(Self::apply_settings)
+				.detach();
+
+			DiffPane {
+				style: DiffStyle { text: text_style },
+				document: Arc::new(DiffDocument::default()),
+				workspace,
+				file_path: None,
+				show_line_numbers: line_number_columns.is_some(),
+				line_number_columns: line_number_columns.unwrap_or(LineNumberColumns::Both),
+				tab_size: settings.tab_size,
+				indent_style: None,
+				soft_wrap: false,
+				show_whitespace: false,
+				scroll_y: 0.0,
+				scroll_x: px(0.),
+				display_map: Rc::new(DisplayMap::default()),
+				focus_handle,
+				last_bounds: None,
+				selection: None,
+				text_selection: None,
+				is_selecting_text: false,
+				context_menu: None,
+				search: SearchState::default(),
+				find_focus_handle,
+				go_to_line: GoToLineState::default(),
+				go_to_line_focus_handle,
+				pending_autoscroll: None,
+				scrollbar_drag_state: Rc::new(Cell::new(None)),
+				horizontal_scrollbar_drag_state: Rc::new(Cell::new(None)),
+				line_cache: Rc::new(RefCell::new(ShapedLineCache::default())),
+				syntax_highlighter: syntax::new_highlighter(cx),
+				highlights: LazyHighlights::default(),
+				edit_buffer: None,
+				pending_rediff: None,
+				fit_to_rows: false,
+				review: ReviewState::default(),
+				page: Page::Diff,
+				settings,
+			}
 		});
 
 		file_list
 	}
 
+	/// Picks up the settings which changed in the settings file
+	fn apply_settings(&mut self, cx: &mut Context<Self>) {
+		let settings = HxDiffSettings::get(cx).clone();
+
+		if settings.line_numbers != self.settings.line_numbers {
+			let line_number_columns = LineNumberColumns::from_setting(settings.line_numbers);
+			self.show_line_numbers = line_number_columns.is_some();
+			if let Some(line_number_columns) = line_number_columns {
+				self.line_number_columns = line_number_columns;
+			}
+		}
+		if settings.tab_size != self.settings.tab_size {
+			self.tab_size = self
+				.file_path
+				.as_ref()
+				.and_then(|path| EditorConfig::for_file(path).tab_width())
+				.unwrap_or(settings.tab_size);
+			self.line_cache.borrow_mut().clear_rows();
+		}
+		if settings.context_lines != self.settings.context_lines {
+			// Rows move as hunks grow or shrink
+			self.document = Arc::new(self.document.with_context_lines(settings.context_lines));
+			self.line_cache.borrow_mut().clear_rows();
+			self.name_hunks_by_syntax(cx);
+			self.selection = None;
+			self.text_selection = None;
+			self.refresh_search_matches();
+		}
+		if settings.highlighter != self.settings.highlighter {
+			self.syntax_highlighter = syntax::new_highlighter(cx);
+			self.restart_highlighting(cx);
+		}
+		self.update_display_map(cx);
+
+		self.settings = settings;
+		cx.notify();
+	}
+
 	pub fn get_file_contents(file_entry: &FileEntry, file_source: &FileSource) -> String {
 		match file_source {
 			FileSource::Empty => String::new(),
@@ -123,6 +366,9 @@ impl DiffPane {
 			.expect("Entry not found.");
 
 		self.scroll_y = 0.;
+		self.scroll_x = px(0.);
+		self.page = Page::Diff;
+		self.file_path = None;
 
 		// Mid
 
@@ -133,143 +379,270 @@ impl DiffPane {
 
 				let right_contents =
 					DiffPane::get_file_contents(file_entry, &file_entry.right_source);
+				// A file already open for editing in another pane, like a section of the review
+				// stream, is only shown here. The buffer this pane had is let go first, in case
+				// it's the same file.
+				self.edit_buffer = None;
+				self.edit_buffer = matches!(file_entry.right_source, FileSource::Working)
+					.then(|| EditBuffer::open(file_entry.path.clone(), right_contents.clone()))
+					.flatten();
+				self.pending_rediff = None;
+				self.file_path = Some(file_entry.path.clone());
 
-				let left_highlights = self
-					.syntax_highlighter
-					.highlight_content(&left_contents, &file_entry.path);
-				let right_highlights = self
-					.syntax_highlighter
-					.highlight_content(&right_contents, &file_entry.path);
-
-				let diff = TextDiff::from_lines(&left_contents, &right_contents);
-				let mut diff_lines = Vec::new();
-				// for group in diff.grouped_ops(3) {
-				// 	diff_lines.push(DiffLine {
-				// 		text: "Diff Group".into(),
-				// 		diff_type: DiffType::Header,
-				// 	});
-
-				// 	for op in group {
-				// 		for change in diff.iter_changes(&op) {
-				// 			let diff_type = match change.tag() {
-				// 				ChangeTag::Delete => DiffType::Removed,
-				// 				ChangeTag::Insert => DiffType::Added,
-				// 				ChangeTag::Equal => DiffType::Normal,
-				// 			};
-
-				// 			let text = change;
-
-				// 			diff_lines.push(DiffLine {
-				// 				text: text.value().trim_end().to_string().into(),
-				// 				diff_type,
-				// 			});
-				// 		}
-				// 	}
-				// }
-
-				let mut first_change_line = None;
-
-				for change in diff.iter_all_changes() {
-					let diff_type = match change.tag() {
-						ChangeTag::Delete => DiffType::Removed,
-						ChangeTag::Insert => DiffType::Added,
-						ChangeTag::Equal => DiffType::Normal,
-					};
-
-					if first_change_line.is_none() && change.tag() != ChangeTag::Equal {
-						first_change_line = Some(diff_lines.len());
-					}
-
-					let text = change;
-					let text_str = text.value().trim_end_matches('\n').to_string();
-
-					// TODO: Can we avoid cloning the highlights data? Use reference?
-					let highlight_runs = match diff_type {
-						DiffType::Removed => change
-							.old_index()
-							.and_then(|idx| left_highlights.get(idx))
-							.map(|runs| runs.clone())
-							.unwrap_or_default(),
-						_ => change
-							.new_index()
-							.and_then(|idx| right_highlights.get(idx))
-							.map(|runs| runs.clone())
-							.unwrap_or_default(),
-					};
-
-					diff_lines.push(DiffLine {
-						text: text_str.into(),
-						diff_type,
-						_old_index: change.old_index(),
-						new_index: change.new_index(),
-						highlight_runs,
-					});
-				}
-				self.diff_lines = diff_lines;
+				let document =
+					DiffDocument::new(left_contents, right_contents, self.settings.context_lines);
+				self.set_content_keys(
+					ContentKey::for_source(file_entry, &file_entry.left_source),
+					ContentKey::for_source(file_entry, &file_entry.right_source),
+				);
+				let first_change_line = document.first_change();
+				let editor_config = EditorConfig::for_file(&file_entry.path);
+				self.tab_size = editor_config.tab_width().unwrap_or(self.settings.tab_size);
+				self.indent_style = editor_config.indent_style;
+				self.document = Arc::new(document);
+				self.update_display_map(cx);
+				self.text_selection = None;
+				self.restart_highlighting(cx);
+				self.refresh_search_matches();
 
-				if let Some(first_change_line) = first_change_line {
+				// The review stream shows every file from its top
+				if let Some(first_change_line) = first_change_line.filter(|_| !self.fit_to_rows) {
 					self.scroll_to(first_change_line, window, cx);
 				}
 			}
-			EntryKind::Directory(_) => {
-				self.diff_text = SharedString::from("Directory diff not supported.");
+			EntryKind::Category(CategoryKind::Commit)
+				if self.workspace.read(cx).commit.is_some() =>
+			{
+				self.clear_document(cx);
+				self.page = Page::CommitDetails;
+				cx.notify();
 			}
-			EntryKind::Category(_) => {
-				self.diff_text = SharedString::from("Category diff not supported.");
+			EntryKind::Directory(_) | EntryKind::Category(_) => {
+				self.open_summary(id, cx);
 			}
 		}
 	}
 
+	/// Empties the diff behind a page, so stepping to the next difference moves on to the next
+	/// file
+	fn clear_document(&mut self, cx: &mut App) {
+		self.document = Arc::new(DiffDocument::default());
+		self.edit_buffer = None;
+		self.pending_rediff = None;
+		self.update_display_map(cx);
+		self.selection = None;
+		self.text_selection = None;
+		self.set_content_keys(None, None);
+		self.restart_highlighting(cx);
+	}
+
 	fn get_gutter_dimensions(&self, cx: &App) -> GutterDimensions {
 		if self.show_line_numbers {
-			let settings = ThemeSettings::get_global(cx);
-			let buffer_font = settings.buffer_font.clone();
-			let font_size = settings.buffer_font_size(cx);
-			let font_id = cx.text_system().resolve_font(&buffer_font);
-
-			let em_advance = cx
-				.text_system()
-				.advance(font_id, font_size, 'm')
-				.unwrap()
-				.width;
-
-			let line_count = self.diff_lines.len() as f32;
-			let chars = line_count.log10().ceil().max(1.);
+			let em_advance = self.em_advance(cx);
+
+			// Line numbers are 1-based, so the largest one shown is the line count itself
+			let max_line_number = self
+				.document
+				.left_line_count()
+				.max(self.document.right_line_count()) as f32;
+			let chars = (max_line_number.log10().floor() + 1.).max(1.);
 			let left_padding = em_advance;
 			let right_padding = em_advance;
+			let column_width = chars * em_advance + left_padding;
+			let columns = self.line_number_columns.column_count() as f32;
 			GutterDimensions {
-				width: chars * em_advance + left_padding + right_padding,
-				// left_padding,
+				width: columns * column_width + right_padding,
+				column_width,
 				right_padding,
 			}
 		} else {
 			GutterDimensions {
 				width: px(0.),
-				// left_padding: px(0.),
+				column_width: px(0.),
 				right_padding: px(0.),
 			}
 		}
 	}
 
+	fn em_advance(&self, cx: &App) -> Pixels {
+		let settings = ThemeSettings::get_global(cx);
+		let font_size = settings.buffer_font_size(cx);
+		let font_id = cx.text_system().resolve_font(&settings.buffer_font);
+
+		cx.text_system()
+			.advance(font_id, font_size, 'm')
+			.unwrap()
+			.width
+	}
+
+	/// Width available to the text, between the gutter and the vertical scrollbar
+	fn text_width(&self, cx: &App) -> Option<Pixels> {
+		let bounds = self.last_bounds?;
+		let width =
+			bounds.size.width - self.get_gutter_dimensions(cx).width - DiffElement::SCROLLBAR_WIDTH;
+		Some(width.max(px(0.)))
+	}
+
+	fn wrap_column(&self, cx: &App) -> Option<usize> {
+		if !self.soft_wrap {
+			return None;
+		}
+
+		let text_width = self.text_width(cx)?;
+		Some(((text_width / self.em_advance(cx)).floor() as usize).max(1))
+	}
+
+	/// Rebuilds the display rows when the document, tab size or wrap width changed. Within the
+	/// same document, the line at the top of the viewport stays there.
+	fn update_display_map(&mut self, cx: &App) {
+		let wrap_column = self.wrap_column(cx);
+		if self
+			.display_map
+			.is_current(&self.document, self.tab_size, wrap_column)
+		{
+			return;
+		}
+
+		let top_row = Arc::ptr_eq(self.display_map.document(), &self.document)
+			.then(|| self.display_map.display_row(self.scroll_y as usize))
+			.flatten()
+			.map(|display_row| display_row.row);
+
+		self.display_map = Rc::new(DisplayMap::new(
+			self.document.clone(),
+			self.tab_size,
+			wrap_column,
+		));
+
+		if let Some(top_row) = top_row {
+			self.scroll_y = self.display_map.display_row_for(top_row) as f32;
+		}
+	}
+
+	/// Largest horizontal scroll offset, which brings the end of the widest line into view
+	fn max_scroll_x(&self, cx: &App) -> Pixels {
+		let Some(text_width) = self.text_width(cx) else {
+			return px(0.);
+		};
+
+		// Leave room after the last character for a selected line break
+		let content_width = (self.display_map.max_columns() + 1) as f32 * self.em_advance(cx);
+		(content_width - text_width).max(px(0.))
+	}
+
+	fn set_scroll_x(&mut self, scroll_x: Pixels, cx: &App) {
+		self.scroll_x = if self.soft_wrap {
+			px(0.)
+		} else {
+			scroll_x.clamp(px(0.), self.max_scroll_x(cx))
+		};
+	}
+
+	fn toggle_soft_wrap(
+		&mut self,
+		_: &ToggleSoftWrap,
+		_window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		self.soft_wrap = !self.soft_wrap;
+		self.scroll_x = px(0.);
+		self.update_display_map(cx);
+		self.sync_section_options(cx);
+		cx.notify();
+	}
+
+	fn toggle_whitespace(
+		&mut self,
+		_: &ToggleWhitespace,
+		_window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		self.show_whitespace = !self.show_whitespace;
+		self.sync_section_options(cx);
+		cx.notify();
+	}
+
+	fn cycle_line_numbers(
+		&mut self,
+		_: &CycleLineNumbers,
+		_window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		self.line_number_columns = self.line_number_columns.next();
+		self.sync_section_options(cx);
+		cx.notify();
+	}
+
 	fn scroll_to(&mut self, index: usize, window: &mut Window, cx: &mut Context<Self>) {
 		self.selection = Some(index);
+		self.autoscroll(TextPosition::new(index, 0), false, window, cx);
+	}
+
+	/// Like `scroll_to`, but also brings the column into view, horizontally or by picking the
+	/// right wrapped row
+	fn scroll_to_position(
+		&mut self,
+		position: TextPosition,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		self.selection = Some(position.row);
+		self.autoscroll(position, true, window, cx);
+	}
+
+	fn autoscroll(
+		&mut self,
+		position: TextPosition,
+		horizontal: bool,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		// A pane which was never laid out has no viewport yet, so the element scrolls once it does
+		let Some(bounds) = self.last_bounds else {
+			self.pending_autoscroll = Some((position, horizontal));
+			return;
+		};
 
 		let line_height = self.get_line_height(window, cx);
-		let height_in_lines = self.last_bounds.unwrap().size.height / line_height;
+		let height_in_lines = bounds.size.height / line_height;
 		const SCROLL_THRESHOLD: f32 = 0.25;
 		let scroll_threshold = height_in_lines * SCROLL_THRESHOLD;
 
 		// Don't scroll if destination line is comfortably visible
-		let index_float = index as f32;
+		let index_float = self.display_map.display_row_for_position(position) as f32;
 		if index_float < self.scroll_y + scroll_threshold {
 			self.scroll_y = (index_float - height_in_lines + scroll_threshold).max(0.);
 		} else if index_float > self.scroll_y + height_in_lines - scroll_threshold {
 			self.scroll_y = (index_float - scroll_threshold).max(0.);
 		}
 
+		if let (true, false, Some(text_width)) = (horizontal, self.soft_wrap, self.text_width(cx)) {
+			let em_advance = self.em_advance(cx);
+			let x = self.display_map.column_for_position(position) as f32 * em_advance;
+			let margin = (em_advance * 4.).min(text_width / 4.);
+			if x < self.scroll_x + margin {
+				self.set_scroll_x(x - margin, cx);
+			} else if x > self.scroll_x + text_width - margin {
+				self.set_scroll_x(x - text_width + margin, cx);
+			}
+		}
+
 		window.refresh();
 	}
 
+	/// Number of display rows which fit in the pane, zero before it is laid out
+	fn visible_row_count(&self, window: &mut Window, cx: &mut Context<Self>) -> f32 {
+		match self.last_bounds {
+			Some(bounds) => bounds.size.height / self.get_line_height(window, cx),
+			None => 0.,
+		}
+	}
+
+	fn max_scroll_y(&self, window: &mut Window, cx: &mut Context<Self>) -> f32 {
+		let visible_row_count = self.visible_row_count(window, cx).floor();
+		(self.display_map.len() as f32 - visible_row_count).max(0.)
+	}
+
 	fn get_line_height(&self, window: &mut Window, cx: &mut Context<Self>) -> Pixels {
 		let settings = ThemeSettings::get_global(cx);
 		let font_size = settings.buffer_font_size(cx);
@@ -279,24 +652,32 @@ impl DiffPane {
 			.round()
 	}
 
+	/// Scrolls to the next change found in `remaining_lines`, skipping the one they start in.
+	/// Returns false if there is none.
 	fn jump_to_next_difference<R>(
 		&mut self,
 		remaining_lines: R,
 		window: &mut Window,
 		cx: &mut Context<Self>,
-	) where
+	) -> bool
+	where
 		R: Iterator<Item = usize>,
 	{
 		let mut in_original_diff = true;
 		for index in remaining_lines {
-			let diff_type = self.diff_lines[index].diff_type;
-			if in_original_diff && diff_type == DiffType::Normal {
+			// An empty document still starts from row zero
+			let Some(diff_type) = self.document.lines().get(index).map(|line| line.diff_type)
+			else {
+				break;
+			};
+			if in_original_diff && !diff_type.is_change() {
 				in_original_diff = false;
-			} else if !in_original_diff && diff_type != DiffType::Normal {
+			} else if !in_original_diff && diff_type.is_change() {
 				self.scroll_to(index, window, cx);
-				break;
+				return true;
 			}
 		}
+		false
 	}
 
 	fn previous_difference(
@@ -305,13 +686,400 @@ impl DiffPane {
 		window: &mut Window,
 		cx: &mut Context<Self>,
 	) {
+		if self.review.is_open {
+			self.step_review_section(MoveDirection::Up, cx);
+			return;
+		}
 		let diff_index = self.selection.unwrap_or(0);
-		self.jump_to_next_difference((0..=diff_index).rev(), window, cx);
+		if !self.jump_to_next_difference((0..=diff_index).rev(), window, cx) {
+			cx.emit(DiffPaneEvent::PassedFirstDifference);
+		}
 	}
 
 	fn next_difference(&mut self, _: &NextDifference, window: &mut Window, cx: &mut Context<Self>) {
+		if self.review.is_open {
+			self.step_review_section(MoveDirection::Down, cx);
+			return;
+		}
 		let diff_index = self.selection.unwrap_or(0);
-		self.jump_to_next_difference(diff_index..self.diff_lines.len(), window, cx);
+		if !self.jump_to_next_difference(diff_index..self.document.len(), window, cx) {
+			cx.emit(DiffPaneEvent::PassedLastDifference);
+		}
+	}
+
+	/// Selects the start of the file's last change, for stepping backwards into it from the
+	/// next file
+	pub fn select_last_difference(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+		let regions = self.document.regions();
+		let Some(last) = regions.last() else {
+			return;
+		};
+
+		// Removals directly followed by additions make up one change
+		let mut row = last.rows.start;
+		for region in regions.iter().rev().skip(1) {
+			if region.rows.end != row {
+				break;
+			}
+			row = region.rows.start;
+		}
+		self.scroll_to(row, window, cx);
+	}
+
+	/// Row at the top of the viewport, where keyboard movement starts when nothing is selected
+	fn top_row(&self) -> usize {
+		self.display_map
+			.display_row(self.scroll_y as usize)
+			.map_or(0, |display_row| display_row.row)
+	}
+
+	/// Selects `row` and scrolls just enough to keep it in view, for stepping through lines
+	fn move_selection_to(&mut self, row: usize, window: &mut Window, cx: &mut Context<Self>) {
+		self.selection = Some(row);
+		self.text_selection = None;
+
+		if self.last_bounds.is_some() {
+			let visible_row_count = self.visible_row_count(window, cx).floor().max(1.);
+			let display_row = self.display_map.display_row_for(row) as f32;
+			if display_row < self.scroll_y {
+				self.scroll_y = display_row;
+			} else if display_row + 1. > self.scroll_y + visible_row_count {
+				self.scroll_y = display_row + 1. - visible_row_count;
+			}
+		}
+
+		cx.notify();
+	}
+
+	fn move_up(&mut self, _: &MoveUp, window: &mut Window, cx: &mut Context<Self>) {
+		if self.move_caret_vertically(MoveDirection::Up, window, cx) {
+			return;
+		}
+
+		let row = match self.selection {
+			Some(row) => row.saturating_sub(1),
+			None => self.top_row(),
+		};
+		self.move_selection_to(row, window, cx);
+	}
+
+	fn move_down(&mut self, _: &MoveDown, window: &mut Window, cx: &mut Context<Self>) {
+		if self.document.is_empty() || self.move_caret_vertically(MoveDirection::Down, window, cx) {
+			return;
+		}
+
+		let row = match self.selection {
+			Some(row) => (row + 1).min(self.document.len() - 1),
+			None => self.top_row(),
+		};
+		self.move_selection_to(row, window, cx);
+	}
+
+	/// Scrolls a page and moves the selection along by the same number of rows
+	fn move_by_page(
+		&mut self,
+		direction: MoveDirection,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		if self.display_map.is_empty() {
+			return;
+		}
+
+		// Keep one row of the previous page in view
+		let page = (self.visible_row_count(window, cx).floor() - 1.).max(1.);
+		let delta = match direction {
+			MoveDirection::Up => -page,
+			MoveDirection::Down => page,
+		};
+
+		let display_row = self.selection.map_or(self.scroll_y, |row| {
+			self.display_map.display_row_for(row) as f32
+		});
+		let target = (display_row + delta).clamp(0., (self.display_map.len() - 1) as f32);
+		self.scroll_y = (self.scroll_y + delta).clamp(0., self.max_scroll_y(window, cx));
+
+		let row = self
+			.display_map
+			.display_row(target as usize)
+			.map_or(0, |display_row| display_row.row);
+		self.move_selection_to(row, window, cx);
+	}
+
+	fn page_up(&mut self, _: &PageUp, window: &mut Window, cx: &mut Context<Self>) {
+		if self.review.is_open {
+			self.scroll_review_by_page(MoveDirection::Up, window, cx);
+		} else {
+			self.move_by_page(MoveDirection::Up, window, cx);
+		}
+	}
+
+	fn page_down(&mut self, _: &PageDown, window: &mut Window, cx: &mut Context<Self>) {
+		if self.review.is_open {
+			self.scroll_review_by_page(MoveDirection::Down, window, cx);
+		} else {
+			self.move_by_page(MoveDirection::Down, window, cx);
+		}
+	}
+
+	fn move_to_beginning(
+		&mut self,
+		_: &MoveToBeginning,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		if self.review.is_open {
+			self.scroll_review_to_end(MoveDirection::Up, cx);
+		} else if !self.document.is_empty() {
+			self.move_selection_to(0, window, cx);
+		}
+	}
+
+	fn move_to_end(&mut self, _: &MoveToEnd, window: &mut Window, cx: &mut Context<Self>) {
+		if self.review.is_open {
+			self.scroll_review_to_end(MoveDirection::Down, cx);
+		} else if !self.document.is_empty() {
+			self.move_selection_to(self.document.len() - 1, window, cx);
+		}
+	}
+
+	fn center_selection(
+		&mut self,
+		_: &CenterSelection,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		let Some(row) = self.selection else {
+			return;
+		};
+
+		let visible_row_count = self.visible_row_count(window, cx);
+		let display_row = self.display_map.display_row_for(row) as f32;
+		self.scroll_y = (display_row - (visible_row_count / 2.).floor())
+			.clamp(0., self.max_scroll_y(window, cx));
+		cx.notify();
+	}
+}
+
+impl DiffPane {
+	fn begin_text_selection(
+		&mut self,
+		position: TextPosition,
+		click_count: usize,
+		extend: bool,
+		cx: &mut Context<Self>,
+	) {
+		let document = self.document.clone();
+		match self.text_selection.as_mut() {
+			Some(text_selection) if extend => text_selection.extend_to(&document, position),
+			_ if extend => {
+				let anchor = TextPosition::new(self.selection.unwrap_or(position.row), 0);
+				let mut text_selection =
+					TextSelection::new(&document, anchor, Granularity::Character);
+				text_selection.extend_to(&document, position);
+				self.text_selection = Some(text_selection);
+			}
+			_ => {
+				let granularity = Granularity::from_click_count(click_count);
+				self.text_selection = Some(TextSelection::new(&document, position, granularity));
+			}
+		}
+
+		self.selection = Some(position.row);
+		self.is_selecting_text = true;
+		cx.notify();
+	}
+
+	fn extend_text_selection(&mut self, position: TextPosition, cx: &mut Context<Self>) {
+		let document = self.document.clone();
+		if let Some(text_selection) = self.text_selection.as_mut() {
+			text_selection.extend_to(&document, position);
+			self.selection = Some(position.row);
+			cx.notify();
+		}
+	}
+
+	fn end_text_selection(&mut self) {
+		self.is_selecting_text = false;
+	}
+
+	/// Copies the text selection, or the active line when nothing is selected
+	fn copy_selection(&mut self, mode: CopyMode, cx: &mut Context<Self>) {
+		let text_selection = match (&self.text_selection, self.selection) {
+			(Some(text_selection), _) if !text_selection.is_empty() => text_selection.clone(),
+			(_, Some(row)) if row < self.document.len() => {
+				TextSelection::new(&self.document, TextPosition::new(row, 0), Granularity::Line)
+			}
+			_ => return,
+		};
+
+		let text = text_selection.text(&self.document, mode);
+		cx.write_to_clipboard(ClipboardItem::new_string(text));
+	}
+
+	fn copy(&mut self, _: &Copy, _window: &mut Window, cx: &mut Context<Self>) {
+		self.copy_selection(CopyMode::Displayed, cx);
+	}
+
+	fn copy_new_side(&mut self, _: &CopyNewSide, _window: &mut Window, cx: &mut Context<Self>) {
+		self.copy_selection(CopyMode::NewSide, cx);
+	}
+
+	fn copy_old_side(&mut self, _: &CopyOldSide, _window: &mut Window, cx: &mut Context<Self>) {
+		self.copy_selection(CopyMode::OldSide, cx);
+	}
+
+	fn copy_as_patch(&mut self, _: &CopyAsPatch, _window: &mut Window, cx: &mut Context<Self>) {
+		self.copy_selection(CopyMode::Patch, cx);
+	}
+
+	/// Opens the working tree file in the external editor at the selected line. Removed lines
+	/// open at the closest line which is still in the file.
+	fn open_in_editor(&mut self, _: &OpenInEditor, window: &mut Window, cx: &mut Context<Self>) {
+		let Some(path) = self.file_path.as_ref() else {
+			return;
+		};
+
+		let row = self.selection.unwrap_or_else(|| self.top_row());
+		let line = self.document.nearest_right_line(row).unwrap_or(0);
+		// The column only carries over when the selected row itself is in the new file
+		let column = self
+			.text_selection
+			.as_ref()
+			.map(|text_selection| text_selection.start)
+			.filter(|position| {
+				position.row == row
+					&& self
+						.document
+						.lines()
+						.get(row)
+						.is_some_and(|line| line.new_index.is_some())
+			})
+			.and_then(|position| self.document.line_text(row).get(..position.column))
+			.map_or(0, |text| text.chars().count());
+
+		let location = EditorLocation::new(path, line + 1, column + 1);
+		if let Err(error) = external_editor::open_in_editor(&location) {
+			let _ = window.prompt(
+				PromptLevel::Critical,
+				"Failed to open the editor",
+				Some(&error.to_string()),
+				&["OK"],
+				cx,
+			);
+		}
+	}
+
+	fn deploy_context_menu(
+		&mut self,
+		position: Point<Pixels>,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		let context_menu = ui::ContextMenu::build(window, cx, |menu, _window, _cx| {
+			menu.action("Copy", Box::new(Copy))
+				.action("Copy New Side", Box::new(CopyNewSide))
+				.action("Copy Old Side", Box::new(CopyOldSide))
+				.action("Copy as Patch", Box::new(CopyAsPatch))
+				.separator()
+				.action("Open in Editor", Box::new(OpenInEditor))
+				.action("Select Language…", Box::new(SelectLanguage))
+		});
+		self.show_context_menu(context_menu, position, window, cx);
+	}
+
+	/// Lists the languages the open file can be highlighted as, to override the detected one
+	fn deploy_language_menu(
+		&mut self,
+		_: &SelectLanguage,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		let Some(path) = self.file_path.as_ref() else {
+			return;
+		};
+
+		let pane = cx.entity().downgrade();
+		let is_overridden = self.syntax_highlighter.language_override(path).is_some();
+		let current_language = self
+			.syntax_highlighter
+			.language_name(self.document.text(document::Side::Right), path);
+		let context_menu = ui::ContextMenu::build(window, cx, |mut menu, _window, _cx| {
+			let pane_for_detection = pane.clone();
+			menu = menu
+				.toggleable_entry(
+					"Auto-detect",
+					!is_overridden,
+					ui::IconPosition::Start,
+					None,
+					move |_window, cx| {
+						pane_for_detection
+							.update(cx, |pane, cx| pane.set_language(None, cx))
+							.ok();
+					},
+				)
+				.separator();
+
+			for name in self.syntax_highlighter.language_names() {
+				let pane = pane.clone();
+				let language = name.to_string();
+				menu = menu.toggleable_entry(
+					name.to_string(),
+					is_overridden && name == current_language,
+					ui::IconPosition::Start,
+					None,
+					move |_window, cx| {
+						pane.update(cx, |pane, cx| pane.set_language(Some(language.clone()), cx))
+							.ok();
+					},
+				);
+			}
+			menu
+		});
+
+		// Open it over the top left of the diff, since it's usually asked for from the keyboard
+		let position = self
+			.last_bounds
+			.map_or_else(Point::default, |bounds| bounds.origin);
+		self.show_context_menu(context_menu, position, window, cx);
+	}
+
+	fn set_language(&mut self, language: Option<String>, cx: &mut Context<Self>) {
+		let Some(path) = self.file_path.clone() else {
+			return;
+		};
+
+		self.syntax_highlighter
+			.set_language_override(&path, language);
+		self.restart_highlighting(cx);
+		cx.notify();
+	}
+
+	fn show_context_menu(
+		&mut self,
+		context_menu: Entity<ui::ContextMenu>,
+		position: Point<Pixels>,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		cx.focus_view(&context_menu, window);
+
+		let subscription = cx.subscribe_in(
+			&context_menu,
+			window,
+			|this, _, _: &DismissEvent, window, cx| {
+				if this.context_menu.as_ref().is_some_and(|context_menu| {
+					context_menu.0.focus_handle(cx).contains_focused(window, cx)
+				}) {
+					cx.focus_self(window);
+				}
+				this.context_menu.take();
+				cx.notify();
+			},
+		);
+
+		self.context_menu = Some((context_menu, position, subscription));
+		cx.notify();
 	}
 }
 
@@ -324,6 +1092,90 @@ impl Focusable for DiffPane {
 
 impl Render for DiffPane {
 	fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
-		DiffElement::new(&cx.entity())
+		if self.syntax_highlighter.sync_theme(cx) {
+			self.restart_highlighting(cx);
+		}
+
+		if self.review.is_open {
+			return div()
+				.size_full()
+				.flex()
+				.flex_col()
+				.on_action(cx.listener(Self::toggle_review_stream))
+				// The diff element isn't painted, so the actions which make sense across the
+				// stream are registered here instead
+				.on_action(cx.listener(Self::next_difference))
+				.on_action(cx.listener(Self::previous_difference))
+				.on_action(cx.listener(Self::page_up))
+				.on_action(cx.listener(Self::page_down))
+				.on_action(cx.listener(Self::move_to_beginning))
+				.on_action(cx.listener(Self::move_to_end))
+				.on_action(cx.listener(Self::cycle_line_numbers))
+				.on_action(cx.listener(Self::toggle_soft_wrap))
+				.on_action(cx.listener(Self::toggle_whitespace))
+				.child(self.render_review_stream(cx))
+				.into_any_element();
+		}
+
+		let page = match &self.page {
+			Page::Diff => None,
+			Page::CommitDetails => self
+				.render_commit_header(cx)
+				.map(IntoElement::into_any_element),
+			Page::Summary(summary) => Some(self.render_summary(summary, cx).into_any_element()),
+		};
+		if let Some(page) = page {
+			return div()
+				.size_full()
+				.track_focus(&self.focus_handle)
+				.on_action(cx.listener(Self::next_difference))
+				.on_action(cx.listener(Self::previous_difference))
+				.on_action(cx.listener(Self::toggle_review_stream))
+				.child(page)
+				.into_any_element();
+		}
+
+		div()
+			.map(|div| {
+				if self.fit_to_rows {
+					div.w_full()
+				} else {
+					div.size_full()
+				}
+			})
+			.flex()
+			.flex_col()
+			.on_action(cx.listener(Self::deploy_find))
+			// Panes inside the stream leave this to the one showing it
+			.when(!self.fit_to_rows, |div| {
+				div.on_action(cx.listener(Self::toggle_review_stream))
+			})
+			.children(self.render_edit_bar(cx))
+			.when(self.search.is_open, |div| {
+				div.child(self.render_find_bar(cx))
+			})
+			.when(self.go_to_line.is_open, |div| {
+				div.child(self.render_go_to_line(cx))
+			})
+			.child(
+				div()
+					.key_context("DiffPane")
+					.on_key_down(cx.listener(Self::handle_key_down))
+					.flex_grow()
+					.min_h_0()
+					.child(DiffElement::new(&cx.entity())),
+			)
+			.children(self.context_menu.as_ref().map(|(menu, position, _)| {
+				deferred(
+					anchored()
+						.position(*position)
+						.anchor(gpui::Corner::TopLeft)
+						.child(menu.clone()),
+				)
+				.with_priority(1)
+			}))
+			.into_any_element()
 	}
 }
+
+impl EventEmitter<DiffPaneEvent> for DiffPane {}
@@ -0,0 +1,114 @@
+// The details of the commit in `show` mode, shown while its category is selected
+use super::*;
+use theme::ActiveTheme;
+
+/// A labelled row of the header, like the author or the parents
+fn field(label: &'static str, value: impl IntoElement, cx: &App) -> Div {
+	div()
+		.flex()
+		.flex_row()
+		.gap_2()
+		.child(
+			div()
+				.w(px(96.))
+				.flex_none()
+				.text_color(cx.theme().colors().text_muted)
+				.child(label),
+		)
+		.child(value)
+}
+
+fn person(signature: &git::Signature) -> String {
+	format!(
+		"{} <{}>  {}",
+		signature.name, signature.email, signature.date
+	)
+}
+
+impl DiffPane {
+	pub(super) fn render_commit_header(&self, cx: &Context<Self>) -> Option<impl IntoElement> {
+		let commit = self.workspace.read(cx).commit.as_ref()?;
+		let colors = cx.theme().colors();
+		let status = cx.theme().status();
+		let buffer_font = ThemeSettings::get_global(cx).buffer_font.clone();
+
+		let signature = commit.signature.as_ref().map(|signature| {
+			let color = match signature.status {
+				git::SignatureStatus::Good => status.success,
+				git::SignatureStatus::Bad | git::SignatureStatus::RevokedKey => status.error,
+				_ => status.warning,
+			};
+			let mut text = signature.status.to_string();
+			if !signature.signer.is_empty() {
+				text.push_str(&format!(" from {}", signature.signer));
+			}
+			if !signature.key.is_empty() {
+				text.push_str(&format!(" (key {})", signature.key));
+			}
+			field("Signature", div().text_color(color).child(text), cx)
+		});
+
+		let parents_label = if commit.parents.len() > 1 {
+			"Parents"
+		} else {
+			"Parent"
+		};
+		let body = commit.body();
+
+		Some(
+			div()
+				.id("commit-header")
+				.size_full()
+				.overflow_y_scroll()
+				.p_4()
+				.flex()
+				.flex_col()
+				.gap_1()
+				.bg(colors.editor_background)
+				.text_color(colors.text)
+				.text_sm()
+				.child(
+					div()
+						.pb_2()
+						.text_lg()
+						.font_weight(FontWeight::BOLD)
+						.child(commit.subject().to_string()),
+				)
+				.child(field("Commit", commit.sha1.clone(), cx))
+				.when(!commit.parents.is_empty(), |div| {
+					div.child(field(parents_label, commit.parents.join(" "), cx))
+				})
+				.when(!commit.refs.is_empty(), |div| {
+					div.child(field("Refs", commit.refs.join(", "), cx))
+				})
+				.child(field("Author", person(&commit.author), cx))
+				.child(field("Committer", person(&commit.committer), cx))
+				.children(signature)
+				.when(!body.is_empty(), |div| {
+					div.child(
+						gpui::div()
+							.pt_3()
+							.font_family(buffer_font.family.clone())
+							.child(body.to_string()),
+					)
+				})
+				.when(!commit.trailers.is_empty(), |div| {
+					div.child(
+						gpui::div()
+							.pt_3()
+							.children(commit.trailers.iter().map(|trailer| {
+								gpui::div()
+									.font_family(buffer_font.family.clone())
+									.child(format!("{}: {}", trailer.key, trailer.value))
+							})),
+					)
+				})
+				.child(
+					div()
+						.pt_3()
+						.text_color(colors.text_muted)
+						.child(commit.diff_stat.to_string()),
+				),
+		)
+	}
+}
@@ -1,5 +1,9 @@
-use super::DiffType;
-use crate::diff_pane::DiffLine;
+use super::display_map::{DisplayMap, DisplayRow};
+use super::document::DiffType;
+use super::line_cache::LineLayoutCache;
+use super::selection::TextPosition;
+use super::whitespace::find_whitespace;
+use crate::common::ThemeGeneration;
 use crate::diff_pane::GutterDimensions;
 use crate::syntax::HighlightRun;
 use crate::DiffPane;
@@ -8,6 +12,7 @@ use settings::Settings;
 use std::any::TypeId;
 use std::fmt::Write;
 use std::ops::Range;
+use std::rc::Rc;
 use theme::{ActiveTheme, ThemeSettings};
 
 pub fn register_action<T: Action>(
@@ -70,17 +75,221 @@ impl ScrollbarLayout {
 	}
 }
 
-type DiffRegions = Vec<((usize, usize), DiffType)>;
+/// Scrollbar along the bottom of the text, shown when soft wrap is off and a line doesn't fit
+#[derive(Clone, Debug)]
+struct HorizontalScrollbarLayout {
+	hitbox: Hitbox,
+	thumb_bounds: Bounds<Pixels>,
+	max_scroll_x: Pixels,
+}
+
+impl HorizontalScrollbarLayout {
+	/// Scroll offset which puts the left edge of the thumb at `thumb_left`
+	fn scroll_x_for_thumb_left(&self, thumb_left: Pixels) -> Pixels {
+		let travel = self.hitbox.size.width - self.thumb_bounds.size.width;
+		if travel <= px(0.) {
+			return px(0.);
+		}
+		self.max_scroll_x * ((thumb_left - self.hitbox.left()) / travel)
+	}
+}
+
+/// Shaped old and new line numbers for one row
+struct LineNumberLayout {
+	old: Option<ShapedLine>,
+	new: Option<ShapedLine>,
+}
+
+/// A shaped run of text between tabs, positioned at its tab stop
+pub struct LineSegment {
+	shaped: ShapedLine,
+	x_offset: Pixels,
+	/// Offset of the segment's first byte within the line text
+	byte_start: usize,
+}
+
+/// Maps a byte offset within a row to its x position, relative to the start of the text
+fn x_for_column(segments: &[LineSegment], column: usize) -> Pixels {
+	let mut x = px(0.);
+	for segment in segments {
+		if column < segment.byte_start {
+			break;
+		}
+		let column_in_segment = (column - segment.byte_start).min(segment.shaped.len());
+		x = segment.x_offset + segment.shaped.x_for_index(column_in_segment);
+	}
+	x
+}
+
+/// Maps an x position, relative to the start of the text, to the nearest byte offset in the row
+fn column_for_x(segments: &[LineSegment], x: Pixels) -> usize {
+	let Some(segment) = segments
+		.iter()
+		.rev()
+		.find(|segment| segment.x_offset <= x)
+		.or(segments.first())
+	else {
+		return 0;
+	};
+
+	if x < segment.x_offset {
+		return 0;
+	}
+
+	segment.byte_start + segment.shaped.closest_index_for_x(x - segment.x_offset)
+}
+
+/// Converts window positions over the text area into `TextPosition`s, for mouse selection
+struct PositionMap {
+	text_bounds: Bounds<Pixels>,
+	line_height: Pixels,
+	scroll_y: f32,
+	scroll_x: Pixels,
+	/// Segments of each visible display row, starting at display row `scroll_y as usize`
+	rows: Vec<Rc<[LineSegment]>>,
+	display_map: Rc<DisplayMap>,
+}
+
+impl PositionMap {
+	fn position_for_point(&self, position: Point<Pixels>) -> TextPosition {
+		let document = self.display_map.document();
+		if self.display_map.is_empty() {
+			return TextPosition::default();
+		}
+
+		let y = (position.y - self.text_bounds.top()) / self.line_height + self.scroll_y;
+		if y < 0. {
+			return TextPosition::new(0, 0);
+		}
+
+		let display_ix = y as usize;
+		let Some(display_row) = self.display_map.display_row(display_ix) else {
+			let last_row = document.len() - 1;
+			return TextPosition::new(last_row, document.line_text(last_row).len());
+		};
+
+		// Rows dragged over outside the viewport have no layout, so snap to their start or end
+		let first_row = self.scroll_y as usize;
+		let columns = display_row.columns.clone();
+		let column = match display_ix
+			.checked_sub(first_row)
+			.and_then(|ix| self.rows.get(ix))
+		{
+			Some(segments) => {
+				let x = position.x - self.text_bounds.left()
+					+ self.scroll_x + x_for_column(segments, columns.start);
+				column_for_x(segments, x).clamp(columns.start, columns.end)
+			}
+			None if display_ix < first_row => columns.start,
+			None => columns.end,
+		};
+
+		TextPosition::new(display_row.row, column)
+	}
+}
+
+struct RowLayout {
+	display_row: DisplayRow,
+	segments: Rc<[LineSegment]>,
+	/// Offset of the display row's first column within the shaped line, for wrapped rows
+	x_shift: Pixels,
+	/// Where the row's text ends within the shaped line, when the line wraps onto the next row
+	x_end: Option<Pixels>,
+	background: Hsla,
+	/// Horizontal extent of the selection highlight on this row
+	selection: Option<Range<Pixels>>,
+	/// Horizontal extents of search matches on this row
+	search_matches: Vec<Range<Pixels>>,
+	whitespace: Option<WhitespaceLayout>,
+	/// Position of the editing caret, when it is on this row
+	caret: Option<Pixels>,
+}
+
+/// Positions of the whitespace markers on a row, relative to the start of the text
+struct WhitespaceLayout {
+	/// Extent of each tab, from its start to the tab stop
+	tabs: Vec<Range<Pixels>>,
+	spaces: Vec<Range<Pixels>>,
+	/// Trailing whitespace and indentation against the file's indent style
+	highlights: Vec<Range<Pixels>>,
+	/// Where the line ends, if it ends in `\r\n`
+	carriage_return: Option<Pixels>,
+}
+
+/// Glyphs drawn in place of whitespace
+struct WhitespaceGlyphs {
+	tab: ShapedLine,
+	space: ShapedLine,
+	carriage_return: ShapedLine,
+}
 
 pub struct DiffLayout {
-	lines: Vec<(Vec<(ShapedLine, Pixels)>, Hsla)>,
-	// gutter_hitbox: Hitbox,
+	/// The part of the element on screen. Only differs from its bounds when the pane fits its
+	/// rows inside the review stream.
+	bounds: Bounds<Pixels>,
+	lines: Vec<RowLayout>,
+	gutter_hitbox: Hitbox,
 	gutter_dimensions: GutterDimensions,
 	scrollbar_layout: Option<ScrollbarLayout>,
+	horizontal_scrollbar_layout: Option<HorizontalScrollbarLayout>,
 	text_hitbox: Hitbox,
 	line_height: Pixels,
-	line_numbers: Vec<ShapedLine>,
-	diff_regions: DiffRegions,
+	scroll_x: Pixels,
+	line_numbers: Vec<LineNumberLayout>,
+	position_map: Rc<PositionMap>,
+	display_map: Rc<DisplayMap>,
+	/// Display rows containing a search match, for the scrollbar markers
+	search_match_rows: Vec<usize>,
+	/// Only shaped while whitespace is shown
+	whitespace_glyphs: Option<WhitespaceGlyphs>,
+	sticky_header: Option<StickyHeader>,
+}
+
+/// The first line of the definition the viewport is in, pinned to the top of the pane
+struct StickyHeader {
+	row: usize,
+	segments: Rc<[LineSegment]>,
+	hitbox: Hitbox,
+}
+
+/// Everything that affects how a line is shaped, other than its own contents
+#[derive(Clone, PartialEq)]
+struct ShapeParams {
+	font: Font,
+	font_size: Pixels,
+	/// Tab stops decide where each segment of a row starts
+	tab_size: usize,
+	theme_generation: usize,
+}
+
+/// Shaped lines kept across frames, so scrolling or moving the selection only shapes rows which
+/// weren't visible before.
+#[derive(Default)]
+pub struct ShapedLineCache {
+	/// Keyed by row, each entry holding the tab-separated segments of that row
+	rows: LineLayoutCache<usize, ShapeParams, Rc<[LineSegment]>>,
+	/// Keyed by line number and whether it is the active line
+	line_numbers: LineLayoutCache<(usize, bool), ShapeParams, ShapedLine>,
+}
+
+impl ShapedLineCache {
+	/// Maximum number of rows kept before rows far from the viewport get evicted
+	const MAX_CACHED_ROWS: usize = 1024;
+
+	/// Forgets the shaped rows, which are only meaningful for the document they were built from
+	pub fn clear_rows(&mut self) {
+		self.rows.clear();
+	}
+
+	/// Forgets the shaped rows in `rows`, like when they've been highlighted since
+	pub fn clear_row_range(&mut self, rows: Range<usize>) {
+		self.rows.remove_where(|row| rows.contains(row));
+	}
+
+	fn validate(&mut self, params: ShapeParams) {
+		self.rows.validate(params.clone());
+		self.line_numbers.validate(params);
+	}
 }
 
 /// Build `TextRun`s covering the byte range `[seg_start, seg_end)` of the original line text,
@@ -123,16 +332,36 @@ fn extract_runs_for_segment(
 	runs
 }
 
-/// Split a line at tab characters and return `(segment_text, runs, x_offset)` triples.
+/// Height of a row, from the buffer font settings, rounded to whole pixels
+fn buffer_line_height(window: &Window, cx: &App) -> Pixels {
+	let settings = ThemeSettings::get_global(cx);
+	let font_size = settings.buffer_font_size(cx);
+	relative(settings.buffer_line_height.value())
+		.to_pixels(font_size.into(), window.rem_size())
+		.round()
+}
+
+/// Text colour for rows, or parts of rows, which syntax highlighting doesn't cover
+fn fallback_color(diff_type: DiffType, cx: &App) -> Hsla {
+	match diff_type {
+		DiffType::Header => cx.theme().colors().text_muted,
+		DiffType::Normal => cx.theme().colors().editor_foreground,
+		DiffType::Added => cx.theme().status().created,
+		DiffType::Removed => cx.theme().status().deleted,
+	}
+}
+
+/// Split a line at tab characters and return `(segment_text, runs, x_offset, byte_start)` tuples.
 /// Each segment is the text between consecutive tabs, positioned at its tab-stop-aligned column.
-/// Lines with no tabs produce a single segment at x_offset zero.
+/// Lines with no tabs produce a single segment at x_offset zero. Empty segments are kept, so the
+/// position after every tab, including consecutive and trailing ones, can be looked up.
 fn build_line_segments(
 	text: &str,
 	highlight_runs: &[HighlightRun],
 	tab_size: usize,
 	font: &Font,
 	char_advance: Pixels,
-) -> Vec<(SharedString, Vec<TextRun>, Pixels)> {
+) -> Vec<(SharedString, Vec<TextRun>, Pixels, usize)> {
 	let mut segments = Vec::new();
 	let mut col: usize = 0;
 	let mut seg_start_byte: usize = 0;
@@ -140,11 +369,14 @@ fn build_line_segments(
 
 	for (byte_pos, ch) in text.char_indices() {
 		if ch == '\t' {
-			if byte_pos > seg_start_byte {
-				let seg_text: SharedString = text[seg_start_byte..byte_pos].to_string().into();
-				let runs = extract_runs_for_segment(highlight_runs, seg_start_byte, byte_pos, font);
-				segments.push((seg_text, runs, char_advance * seg_start_col as f32));
-			}
+			let seg_text: SharedString = text[seg_start_byte..byte_pos].to_string().into();
+			let runs = extract_runs_for_segment(highlight_runs, seg_start_byte, byte_pos, font);
+			segments.push((
+				seg_text,
+				runs,
+				char_advance * seg_start_col as f32,
+				seg_start_byte,
+			));
 			col = ((col / tab_size) + 1) * tab_size;
 			seg_start_byte = byte_pos + 1; // '\t' is always one byte
 			seg_start_col = col;
@@ -154,11 +386,14 @@ fn build_line_segments(
 	}
 
 	// Final (or only) segment after the last tab.
-	if seg_start_byte < text.len() {
-		let seg_text: SharedString = text[seg_start_byte..].to_string().into();
-		let runs = extract_runs_for_segment(highlight_runs, seg_start_byte, text.len(), font);
-		segments.push((seg_text, runs, char_advance * seg_start_col as f32));
-	}
+	let seg_text: SharedString = text[seg_start_byte..].to_string().into();
+	let runs = extract_runs_for_segment(highlight_runs, seg_start_byte, text.len(), font);
+	segments.push((
+		seg_text,
+		runs,
+		char_advance * seg_start_col as f32,
+		seg_start_byte,
+	));
 
 	segments
 }
@@ -174,13 +409,15 @@ impl DiffElement {
 
 	fn layout_line_numbers(
 		&self,
-		rows: std::ops::Range<usize>,
-		diff_lines: &[DiffLine],
+		display_rows: &[DisplayRow],
+		display_map: &DisplayMap,
+		cache: &mut ShapedLineCache,
 		window: &mut Window,
 		cx: &mut App,
-	) -> Vec<ShapedLine> {
+	) -> Vec<LineNumberLayout> {
 		let diff_pane = self.diff_pane.clone();
 		let show_line_numbers = diff_pane.read(cx).show_line_numbers;
+		let columns = diff_pane.read(cx).line_number_columns;
 		let selection = diff_pane.read(cx).selection;
 
 		if show_line_numbers {
@@ -192,59 +429,72 @@ impl DiffElement {
 			let active_bg_color = cx.theme().colors().editor_active_line_background;
 			let font_size = settings.buffer_font_size(cx);
 
-			rows.map(|ix| {
-				let diff_line = &diff_lines[ix];
-				scratch_string.clear();
-				if let Some(new_index) = diff_line.new_index {
-					write!(&mut scratch_string, "{}", new_index).unwrap();
-				}
+			let mut shape_number = |index: Option<usize>, is_active: bool| -> Option<ShapedLine> {
+				// Indices are 0-based, but line numbers are displayed 1-based like in editors
+				let number = index? + 1;
+				Some(
+					cache
+						.line_numbers
+						.get_or_insert_with((number, is_active), || {
+							scratch_string.clear();
+							write!(&mut scratch_string, "{}", number).unwrap();
+
+							let (color, background_color) = if is_active {
+								(active_color, Some(active_bg_color))
+							} else {
+								(base_color, None)
+							};
+
+							let run = TextRun {
+								len: scratch_string.len(),
+								font: buffer_font.clone(),
+								color,
+								background_color,
+								underline: None,
+								strikethrough: None,
+							};
+							window.text_system().shape_line(
+								scratch_string.clone().into(),
+								font_size,
+								&[run],
+								None,
+							)
+						}),
+				)
+			};
 
-				let (color, background_color) = if Some(ix) == selection {
-					(active_color, Some(active_bg_color))
-				} else {
-					(base_color, None)
-				};
+			display_rows
+				.iter()
+				.map(|display_row| {
+					// Wrapped lines only show their numbers on the first row
+					if display_row.is_continuation() {
+						return LineNumberLayout {
+							old: None,
+							new: None,
+						};
+					}
 
-				let run = TextRun {
-					len: scratch_string.len(),
-					font: buffer_font.clone(),
-					color,
-					background_color,
-					underline: None,
-					strikethrough: None,
-				};
-				window.text_system().shape_line(
-					scratch_string.clone().into(),
-					font_size,
-					&[run],
-					None,
-				)
-			})
-			.collect()
-		} else {
-			Vec::new()
-		}
-	}
+					let ix = display_row.row;
+					let diff_line = &display_map.document().lines()[ix];
+					let is_active = Some(ix) == selection;
 
-	fn compute_diff_regions(&self, diff_lines: &[DiffLine]) -> Vec<((usize, usize), DiffType)> {
-		let mut diff_markers = Vec::new();
-		let mut last_diff_index = 0;
-		let mut last_diff_type = DiffType::Normal;
-		for (ix, diff) in diff_lines.iter().enumerate() {
-			if diff.diff_type != last_diff_type {
-				if last_diff_type != DiffType::Normal {
-					diff_markers.push(((last_diff_index, ix), last_diff_type));
-				}
-				last_diff_index = ix;
-				last_diff_type = diff.diff_type;
-			}
-		}
+					let old = if columns.show_old() {
+						shape_number(diff_line.old_index, is_active)
+					} else {
+						None
+					};
+					let new = if columns.show_new() {
+						shape_number(diff_line.new_index, is_active)
+					} else {
+						None
+					};
 
-		if last_diff_type != DiffType::Normal {
-			diff_markers.push(((last_diff_index, diff_lines.len()), last_diff_type));
+					LineNumberLayout { old, new }
+				})
+				.collect()
+		} else {
+			Vec::new()
 		}
-
-		diff_markers
 	}
 
 	fn layout_scrollbar(
@@ -279,30 +529,99 @@ impl DiffElement {
 		})
 	}
 
+	fn layout_horizontal_scrollbar(
+		&self,
+		text_bounds: Bounds<Pixels>,
+		scroll_x: Pixels,
+		max_scroll_x: Pixels,
+		window: &mut Window,
+	) -> Option<HorizontalScrollbarLayout> {
+		if max_scroll_x <= px(0.) {
+			return None;
+		}
+
+		let track_bounds = Bounds::from_corners(
+			point(
+				text_bounds.left(),
+				text_bounds.bottom() - DiffElement::SCROLLBAR_WIDTH,
+			),
+			point(
+				text_bounds.right() - DiffElement::SCROLLBAR_WIDTH,
+				text_bounds.bottom(),
+			),
+		);
+
+		// The track spans exactly the visible text width
+		let track_width = track_bounds.size.width;
+		let content_width = track_width + max_scroll_x;
+		let thumb_width = (track_width * (track_width / content_width))
+			.max(ScrollbarLayout::MIN_THUMB_HEIGHT)
+			.min(track_width);
+		let thumb_left =
+			track_bounds.left() + (track_width - thumb_width) * (scroll_x / max_scroll_x);
+
+		Some(HorizontalScrollbarLayout {
+			hitbox: window.insert_hitbox(track_bounds, HitboxBehavior::Normal),
+			thumb_bounds: Bounds {
+				origin: point(thumb_left, track_bounds.top()),
+				size: size(thumb_width, track_bounds.size.height),
+			},
+			max_scroll_x,
+		})
+	}
+
 	fn mouse_left_down(
 		diff_pane: &mut DiffPane,
 		event: &MouseDownEvent,
+		position_map: &PositionMap,
 		text_hitbox: &Hitbox,
-		line_height: Pixels,
+		gutter_hitbox: &Hitbox,
 		window: &mut Window,
 		cx: &mut Context<DiffPane>,
 	) {
 		if window.default_prevented() {
 			return;
 		}
+		// The rows painted don't have a pending edit yet, so where the click landed is only
+		// known once they do
+		if diff_pane.flush_rediff(window, cx) {
+			cx.stop_propagation();
+			return;
+		}
 
-		if !text_hitbox.is_hovered(window) {
+		if gutter_hitbox.is_hovered(window) {
+			// Clicking a line number selects the whole line
+			let position = position_map.position_for_point(event.position);
+			if position.row < diff_pane.document.len() {
+				diff_pane.selection = Some(position.row);
+				diff_pane.text_selection = None;
+			}
+		} else if text_hitbox.is_hovered(window) {
+			let position = position_map.position_for_point(event.position);
+			diff_pane.begin_text_selection(position, event.click_count, event.modifiers.shift, cx);
+		} else {
 			return;
 		}
 
-		let line_height = line_height;
-		let click_y = (event.position.y - text_hitbox.top()) / line_height;
-		let final_y = click_y + diff_pane.scroll_y;
+		window.refresh();
 
-		diff_pane.selection = Some(final_y as usize);
+		cx.stop_propagation();
+	}
 
-		window.refresh();
+	fn mouse_dragged(
+		diff_pane: &mut DiffPane,
+		event: &MouseMoveEvent,
+		position_map: &PositionMap,
+		window: &mut Window,
+		cx: &mut Context<DiffPane>,
+	) {
+		if !diff_pane.is_selecting_text || event.pressed_button != Some(MouseButton::Left) {
+			return;
+		}
 
+		let position = position_map.position_for_point(event.position);
+		diff_pane.extend_text_selection(position, cx);
+		window.refresh();
 		cx.stop_propagation();
 	}
 
@@ -328,12 +647,15 @@ impl DiffElement {
 				BorderStyle::default(),
 			));
 
-			for diff_region in &layout.diff_regions {
+			let display_map = &layout.display_map;
+			for diff_region in display_map.document().regions() {
 				window.paint_quad(quad(
-					scrollbar_layout
-						.marker_bounds(diff_region.0 .0 as f32, diff_region.0 .1 as f32),
+					scrollbar_layout.marker_bounds(
+						display_map.display_row_for(diff_region.rows.start) as f32,
+						display_map.display_row_for(diff_region.rows.end) as f32,
+					),
 					Corners::default(),
-					if diff_region.1 == DiffType::Added {
+					if diff_region.diff_type == DiffType::Added {
 						cx.theme().status().created
 					} else {
 						cx.theme().status().deleted
@@ -349,6 +671,29 @@ impl DiffElement {
 				));
 			}
 
+			// Search matches sit in the left half of the track, over the diff regions. Rows which
+			// land on the same pixel only get one marker.
+			let mut last_marker_top = None;
+			for &row in &layout.search_match_rows {
+				let top = scrollbar_layout.y_for_row(row as f32).floor();
+				if last_marker_top == Some(top) {
+					continue;
+				}
+				last_marker_top = Some(top);
+
+				let track = scrollbar_layout.hitbox.bounds;
+				window.paint_quad(fill(
+					Bounds {
+						origin: point(track.left() + ScrollbarLayout::BORDER_WIDTH, top),
+						size: size(
+							(track.size.width - ScrollbarLayout::BORDER_WIDTH) / 2.,
+							ScrollbarLayout::LINE_MARKER_HEIGHT,
+						),
+					},
+					cx.theme().colors().search_match_background.opacity(1.),
+				));
+			}
+
 			window.paint_quad(quad(
 				thumb_bounds,
 				Corners::default(),
@@ -392,11 +737,10 @@ impl DiffElement {
 						let thumb_top_offset = thumb_bounds.size.height / 2. / hitbox.size.height;
 						is_dragging.set(Some(thumb_top_offset));
 
-						let y = diff_pane.diff_lines.len() as f32 * percentage - rows_per_page / 2.;
-						diff_pane.scroll_y = y.clamp(
-							0.0,
-							diff_pane.diff_lines.len() as f32 - height_in_lines.floor(),
-						);
+						let row_count = diff_pane.display_map.len() as f32;
+						let y = row_count * percentage - rows_per_page / 2.;
+						diff_pane.scroll_y =
+							y.clamp(0.0, (row_count - height_in_lines.floor()).max(0.));
 					} else {
 						let thumb_top_offset =
 							(event.position.y - thumb_bounds.origin.y) / hitbox.size.height;
@@ -424,11 +768,10 @@ impl DiffElement {
 						(event.position.y - hitbox.top()) / hitbox.size.height - drag_state;
 
 					diff_pane.update(cx, |diff_pane, _cx| {
-						let y = diff_pane.diff_lines.len() as f32 * percentage;
-						diff_pane.scroll_y = y.clamp(
-							0.0,
-							diff_pane.diff_lines.len() as f32 - height_in_lines.floor(),
-						);
+						let row_count = diff_pane.display_map.len() as f32;
+						let y = row_count * percentage;
+						diff_pane.scroll_y =
+							y.clamp(0.0, (row_count - height_in_lines.floor()).max(0.));
 						window.refresh();
 					});
 
@@ -447,19 +790,127 @@ impl DiffElement {
 		});
 	}
 
+	fn paint_horizontal_scrollbar(
+		&mut self,
+		layout: &mut DiffLayout,
+		window: &mut Window,
+		cx: &mut App,
+	) {
+		let Some(scrollbar_layout) = layout.horizontal_scrollbar_layout.clone() else {
+			return;
+		};
+
+		let thumb_bounds = scrollbar_layout.thumb_bounds;
+
+		window.paint_layer(scrollbar_layout.hitbox.bounds, |window| {
+			window.paint_quad(quad(
+				scrollbar_layout.hitbox.bounds,
+				Corners::default(),
+				cx.theme().colors().scrollbar_track_background,
+				Edges {
+					top: ScrollbarLayout::BORDER_WIDTH,
+					right: Pixels::ZERO,
+					bottom: Pixels::ZERO,
+					left: Pixels::ZERO,
+				},
+				cx.theme().colors().scrollbar_track_border,
+				BorderStyle::default(),
+			));
+
+			window.paint_quad(quad(
+				thumb_bounds,
+				Corners::default(),
+				cx.theme().colors().scrollbar_thumb_background,
+				Edges {
+					top: ScrollbarLayout::BORDER_WIDTH,
+					right: Pixels::ZERO,
+					bottom: Pixels::ZERO,
+					left: Pixels::ZERO,
+				},
+				cx.theme().colors().scrollbar_thumb_border,
+				BorderStyle::default(),
+			));
+		});
+
+		window.on_mouse_event({
+			let diff_pane = self.diff_pane.clone();
+			let scrollbar_layout = scrollbar_layout.clone();
+
+			move |event: &MouseDownEvent, phase, window, cx| {
+				if phase == DispatchPhase::Capture || !scrollbar_layout.hitbox.is_hovered(window) {
+					return;
+				}
+
+				diff_pane.update(cx, |diff_pane, cx| {
+					let x = event.position.x;
+					let drag_state = diff_pane.horizontal_scrollbar_drag_state.clone();
+
+					if x < thumb_bounds.left() || thumb_bounds.right() < x {
+						// Center the thumb on the click, then drag it from its middle
+						let thumb_offset = thumb_bounds.size.width / 2.;
+						drag_state.set(Some(thumb_offset));
+						let scroll_x = scrollbar_layout.scroll_x_for_thumb_left(x - thumb_offset);
+						diff_pane.set_scroll_x(scroll_x, cx);
+					} else {
+						drag_state.set(Some(x - thumb_bounds.left()));
+					}
+
+					window.refresh();
+					cx.stop_propagation();
+				});
+			}
+		});
+
+		window.on_mouse_event({
+			let diff_pane = self.diff_pane.clone();
+
+			move |event: &MouseMoveEvent, phase, window, cx| {
+				if phase.capture() {
+					return;
+				}
+
+				let drag_state = diff_pane.read(cx).horizontal_scrollbar_drag_state.clone();
+
+				if let Some(thumb_offset) = drag_state.get().filter(|_| event.dragging()) {
+					let scroll_x =
+						scrollbar_layout.scroll_x_for_thumb_left(event.position.x - thumb_offset);
+					diff_pane.update(cx, |diff_pane, cx| diff_pane.set_scroll_x(scroll_x, cx));
+					window.refresh();
+					cx.stop_propagation();
+				} else {
+					drag_state.set(None);
+				}
+			}
+		});
+
+		let is_dragging = self
+			.diff_pane
+			.read(cx)
+			.horizontal_scrollbar_drag_state
+			.clone();
+		window.on_mouse_event(move |_event: &MouseUpEvent, phase, _window, _cx| {
+			if phase.bubble() {
+				is_dragging.set(None);
+			}
+		});
+	}
+
 	fn paint_mouse_listeners(
 		&mut self,
 		layout: &mut DiffLayout,
 		bounds: Bounds<gpui::Pixels>,
 		window: &mut Window,
-		_cx: &mut App,
+		cx: &mut App,
 	) {
 		let line_height = layout.line_height;
 		let text_hitbox = layout.text_hitbox.clone();
+		let gutter_hitbox = layout.gutter_hitbox.clone();
 		let height_in_lines = bounds.size.height / line_height;
+		let fit_to_rows = self.diff_pane.read(cx).fit_to_rows;
 
 		window.on_mouse_event({
 			let diff_pane = self.diff_pane.clone();
+			let position_map = layout.position_map.clone();
 
 			move |event: &MouseDownEvent, phase, window, cx| {
 				if phase == DispatchPhase::Bubble {
@@ -468,18 +919,48 @@ impl DiffElement {
 							Self::mouse_left_down(
 								diff_pane,
 								event,
+								&position_map,
 								&text_hitbox,
-								line_height,
+								&gutter_hitbox,
 								window,
 								cx,
 							);
 						}),
+						MouseButton::Right if text_hitbox.is_hovered(window) => {
+							diff_pane.update(cx, |diff_pane, cx| {
+								diff_pane.deploy_context_menu(event.position, window, cx);
+							});
+							cx.stop_propagation();
+						}
 						_ => (),
 					}
 				}
 			}
 		});
 
+		window.on_mouse_event({
+			let diff_pane = self.diff_pane.clone();
+			let position_map = layout.position_map.clone();
+
+			move |event: &MouseMoveEvent, phase, window, cx| {
+				if phase == DispatchPhase::Bubble {
+					diff_pane.update(cx, |diff_pane, cx| {
+						Self::mouse_dragged(diff_pane, event, &position_map, window, cx);
+					});
+				}
+			}
+		});
+
+		window.on_mouse_event({
+			let diff_pane = self.diff_pane.clone();
+
+			move |event: &MouseUpEvent, phase, _window, cx| {
+				if phase == DispatchPhase::Bubble && event.button == MouseButton::Left {
+					diff_pane.update(cx, |diff_pane, _cx| diff_pane.end_text_selection());
+				}
+			}
+		});
+
 		window.on_mouse_event({
 			let diff_pane = self.diff_pane.clone();
 			let mut delta = ScrollDelta::default();
@@ -489,17 +970,31 @@ impl DiffElement {
 				if phase == DispatchPhase::Bubble && hitbox.is_hovered(window) {
 					delta = delta.coalesce(event.delta);
 
+					let (delta_x, delta_y) = match delta {
+						ScrollDelta::Lines(lines) => (lines.x * line_height, lines.y),
+						ScrollDelta::Pixels(point) => (point.x, point.y / line_height),
+					};
+
+					// Wheels without a horizontal axis scroll sideways while shift is held
+					let (delta_x, delta_y) = if event.modifiers.shift && delta_x == px(0.) {
+						(delta_y * line_height, 0.)
+					} else {
+						(delta_x, delta_y)
+					};
+
+					// Inside the review stream, scrolling vertically moves the stream instead
+					if fit_to_rows && delta_x == px(0.) {
+						return;
+					}
+
 					diff_pane.update(cx, |diff_pane, cx| {
-						let new_y = match delta {
-							ScrollDelta::Lines(lines) => diff_pane.scroll_y - lines.y,
-							ScrollDelta::Pixels(point) => {
-								diff_pane.scroll_y - point.y / line_height
-							}
-						};
-						diff_pane.scroll_y = new_y.clamp(
-							0.0,
-							diff_pane.diff_lines.len() as f32 - height_in_lines.floor(),
-						);
+						if !fit_to_rows {
+							let row_count = diff_pane.display_map.len() as f32;
+							diff_pane.scroll_y = (diff_pane.scroll_y - delta_y)
+								.clamp(0.0, (row_count - height_in_lines.floor()).max(0.));
+						}
+						let scroll_x = diff_pane.scroll_x - delta_x;
+						diff_pane.set_scroll_x(scroll_x, cx);
 						cx.notify();
 					});
 					cx.stop_propagation();
@@ -508,12 +1003,136 @@ impl DiffElement {
 		});
 	}
 
+	fn paint_sticky_header(&mut self, layout: &mut DiffLayout, window: &mut Window, cx: &mut App) {
+		let Some(sticky_header) = layout.sticky_header.as_ref() else {
+			return;
+		};
+
+		let header_bounds = sticky_header.hitbox.bounds;
+		let text_origin = point(layout.text_hitbox.bounds.left(), header_bounds.top());
+		window.paint_layer(header_bounds, |window| {
+			window.paint_quad(fill(
+				header_bounds,
+				cx.theme().colors().editor_subheader_background,
+			));
+			window.paint_quad(fill(
+				Bounds {
+					origin: header_bounds.bottom_left() - point(px(0.), px(1.)),
+					size: size(header_bounds.size.width, px(1.)),
+				},
+				cx.theme().colors().border,
+			));
+
+			window.with_content_mask(
+				Some(ContentMask {
+					bounds: Bounds::from_corners(text_origin, header_bounds.bottom_right()),
+				}),
+				|window| {
+					for segment in sticky_header.segments.iter() {
+						segment
+							.shaped
+							.paint(
+								text_origin + point(segment.x_offset, px(0.)),
+								layout.line_height,
+								TextAlign::Left,
+								None,
+								window,
+								cx,
+							)
+							.expect("Failed to paint sticky header");
+					}
+				},
+			);
+		});
+
+		// Clicking the header jumps to the definition
+		let row = sticky_header.row;
+		let hitbox = sticky_header.hitbox.clone();
+		let diff_pane = self.diff_pane.clone();
+		window.on_mouse_event(move |event: &MouseDownEvent, phase, window, cx| {
+			if phase == DispatchPhase::Bubble
+				&& event.button == MouseButton::Left
+				&& hitbox.is_hovered(window)
+			{
+				diff_pane.update(cx, |diff_pane, cx| {
+					diff_pane.text_selection = None;
+					diff_pane.scroll_to(row, window, cx);
+					cx.notify();
+				});
+				cx.stop_propagation();
+			}
+		});
+	}
+
+	/// Draws whitespace glyphs over a row's text. Tab arrows sit at the start of the tab, dots are
+	/// centred in their space.
+	fn paint_whitespace(
+		whitespace: &WhitespaceLayout,
+		glyphs: &WhitespaceGlyphs,
+		origin: Point<Pixels>,
+		line_height: Pixels,
+		window: &mut Window,
+		cx: &mut App,
+	) {
+		let mut paint_glyph = |glyph: &ShapedLine, x: Pixels| {
+			glyph
+				.paint(
+					origin + point(x, px(0.)),
+					line_height,
+					TextAlign::Left,
+					None,
+					window,
+					cx,
+				)
+				.expect("Failed to paint whitespace");
+		};
+
+		for tab in &whitespace.tabs {
+			paint_glyph(&glyphs.tab, tab.start);
+		}
+		for space in &whitespace.spaces {
+			let x = (space.start + space.end - glyphs.space.width) / 2.;
+			paint_glyph(&glyphs.space, x);
+		}
+		if let Some(x) = whitespace.carriage_return {
+			paint_glyph(&glyphs.carriage_return, x);
+		}
+	}
+
 	fn register_actions(&self, window: &mut Window, cx: &mut App) {
 		let view = &self.diff_pane;
 
 		// TODO: Maybe move this out to HxDiff.
 		register_action(view, window, cx, DiffPane::next_difference);
 		register_action(view, window, cx, DiffPane::previous_difference);
+		register_action(view, window, cx, DiffPane::cycle_line_numbers);
+		register_action(view, window, cx, DiffPane::toggle_soft_wrap);
+		register_action(view, window, cx, DiffPane::toggle_whitespace);
+		register_action(view, window, cx, DiffPane::move_up);
+		register_action(view, window, cx, DiffPane::move_down);
+		register_action(view, window, cx, DiffPane::page_up);
+		register_action(view, window, cx, DiffPane::page_down);
+		register_action(view, window, cx, DiffPane::move_to_beginning);
+		register_action(view, window, cx, DiffPane::move_to_end);
+		register_action(view, window, cx, DiffPane::center_selection);
+		register_action(view, window, cx, DiffPane::deploy_go_to_line);
+		register_action(view, window, cx, DiffPane::copy);
+		register_action(view, window, cx, DiffPane::copy_new_side);
+		register_action(view, window, cx, DiffPane::copy_old_side);
+		register_action(view, window, cx, DiffPane::copy_as_patch);
+		register_action(view, window, cx, DiffPane::backspace);
+		register_action(view, window, cx, DiffPane::delete);
+		register_action(view, window, cx, DiffPane::newline);
+		register_action(view, window, cx, DiffPane::tab);
+		register_action(view, window, cx, DiffPane::move_left);
+		register_action(view, window, cx, DiffPane::move_right);
+		register_action(view, window, cx, DiffPane::move_to_line_start);
+		register_action(view, window, cx, DiffPane::move_to_line_end);
+		register_action(view, window, cx, DiffPane::undo);
+		register_action(view, window, cx, DiffPane::redo);
+		register_action(view, window, cx, DiffPane::save);
+		register_action(view, window, cx, DiffPane::open_in_editor);
+		register_action(view, window, cx, DiffPane::deploy_language_menu);
 	}
 }
 
@@ -538,7 +1157,13 @@ impl Element for DiffElement {
 	) -> (gpui::LayoutId, ()) {
 		let mut style = Style::default();
 		style.size.width = relative(1.).into();
-		style.size.height = relative(1.).into();
+		let diff_pane = self.diff_pane.read(cx);
+		style.size.height = if diff_pane.fit_to_rows {
+			let row_count = diff_pane.display_map.len() as f32;
+			(row_count * buffer_line_height(window, cx)).into()
+		} else {
+			relative(1.).into()
+		};
 		let layout_id = window.request_layout(style, None, cx);
 
 		(layout_id, ())
@@ -557,15 +1182,48 @@ impl Element for DiffElement {
 		let settings = ThemeSettings::get_global(cx);
 		let buffer_font = settings.buffer_font.clone();
 		let font_size = settings.buffer_font_size(cx);
-		let line_height = relative(settings.buffer_line_height.value());
-		let line_height = line_height
-			.to_pixels(font_size.into(), window.rem_size())
-			.round();
+		let line_height = buffer_line_height(window, cx);
+
+		// A pane fitting its rows is as tall as the whole file, so only the part the review stream
+		// shows is laid out, as if it were a viewport scrolled down to there
+		let fit_to_rows = self.diff_pane.read(cx).fit_to_rows;
+		let laid_out_rows = (bounds.size.height / line_height).round() as usize;
+		let full_bounds = bounds;
+		let bounds = if fit_to_rows {
+			bounds.intersect(&window.content_mask().bounds)
+		} else {
+			bounds
+		};
+
+		// The display rows depend on the width available for wrapping, so settle them first
+		self.diff_pane.update(cx, |diff_pane, cx| {
+			if fit_to_rows {
+				diff_pane.scroll_y = ((bounds.top() - full_bounds.top()) / line_height).max(0.);
+			}
+			diff_pane.last_bounds = Some(bounds);
+			diff_pane.update_display_map(cx);
+			// Rewrapping changed the height the element asked for
+			if fit_to_rows && diff_pane.display_map.len() != laid_out_rows {
+				cx.notify();
+			}
+			// The widest line changes with the document and the pane width
+			diff_pane.set_scroll_x(diff_pane.scroll_x, cx);
+
+			if let Some((position, horizontal)) = diff_pane.pending_autoscroll.take() {
+				diff_pane.autoscroll(position, horizontal, window, cx);
+			}
+		});
 
-		let diff_lines = &self.diff_pane.read(cx).diff_lines.clone(); // TODO: How to not clone?
+		let display_map = self.diff_pane.read(cx).display_map.clone();
+		let document = display_map.document().clone();
 		let scroll_y = self.diff_pane.read(cx).scroll_y;
+		let scroll_x = self.diff_pane.read(cx).scroll_x;
 		let selection = self.diff_pane.read(cx).selection;
+		let text_selection = self.diff_pane.read(cx).text_selection.clone();
 		let tab_size = self.diff_pane.read(cx).tab_size;
+		let show_whitespace = self.diff_pane.read(cx).show_whitespace;
+		let indent_style = self.diff_pane.read(cx).indent_style;
+		let caret = self.diff_pane.read(cx).caret();
 
 		let char_advance = {
 			let font_id = cx.text_system().resolve_font(&buffer_font);
@@ -577,20 +1235,51 @@ impl Element for DiffElement {
 
 		let focus_handle = self.diff_pane.focus_handle(cx);
 		window.set_focus_handle(&focus_handle, cx);
+		// The caret only shows where typing would go
+		let caret = caret.filter(|_| focus_handle.is_focused(window));
+		let caret_display_row = caret.map(|caret| display_map.display_row_for_position(caret));
 
 		let start_row = scroll_y as usize;
 		let height_in_lines = bounds.size.height / line_height;
 		let max_row = std::cmp::min(
 			(scroll_y + height_in_lines).ceil() as usize,
-			diff_lines.len(),
+			display_map.len(),
 		);
+		let visible_rows = display_map.display_rows(start_row..max_row);
+		// Document rows touched by the visible display rows
+		let visible_document_rows = match (visible_rows.first(), visible_rows.last()) {
+			(Some(first), Some(last)) => first.row..last.row + 1,
+			_ => 0..0,
+		};
+
+		self.diff_pane.update(cx, |diff_pane, cx| {
+			diff_pane.highlight_through(visible_document_rows.end, cx)
+		});
+		let highlights = self.diff_pane.read(cx).highlight_snapshot();
+
+		let (visible_matches, search_match_rows) = {
+			let search_matches = self.diff_pane.read(cx).search_matches();
+			let first =
+				search_matches.partition_point(|found| found.row < visible_document_rows.start);
+			let visible_matches = search_matches[first..]
+				.iter()
+				.take_while(|found| found.row < visible_document_rows.end)
+				.cloned()
+				.collect::<Vec<_>>();
+			let mut rows = search_matches
+				.iter()
+				.map(|found| display_map.display_row_for(found.row))
+				.collect::<Vec<_>>();
+			rows.dedup();
+			(visible_matches, rows)
+		};
 
 		let gutter_dimensions = self.diff_pane.read(cx).get_gutter_dimensions(cx);
 		let gutter_bounds = Bounds {
 			origin: bounds.origin,
 			size: size(gutter_dimensions.width, bounds.size.height),
 		};
-		// let gutter_hitbox = cx.insert_hitbox(gutter_bounds, false);
+		let gutter_hitbox = window.insert_hitbox(gutter_bounds, HitboxBehavior::Normal);
 		let text_hitbox = window.insert_hitbox(
 			Bounds {
 				origin: gutter_bounds.top_right(),
@@ -602,72 +1291,240 @@ impl Element for DiffElement {
 			HitboxBehavior::Normal,
 		);
 
-		let line_numbers = self.layout_line_numbers(start_row..max_row, diff_lines, window, cx);
-		let total_rows = diff_lines.len();
+		let line_cache = self.diff_pane.read(cx).line_cache.clone();
+		let mut line_cache = line_cache.borrow_mut();
+		line_cache.validate(ShapeParams {
+			font: buffer_font.clone(),
+			font_size,
+			tab_size,
+			theme_generation: ThemeGeneration::get(cx),
+		});
 
-		let scrollbar_layout = self.layout_scrollbar(
-			total_rows as f32,
-			bounds,
-			point(0., scroll_y),
-			height_in_lines,
-			window,
-			cx,
-		);
+		let line_numbers =
+			self.layout_line_numbers(visible_rows, &display_map, &mut line_cache, window, cx);
+
+		// Rows without syntax highlighting, like hunk headers, are drawn in the fallback colour
+		let shape_row = |row: usize, fallback_color: Hsla, window: &mut Window| {
+			let text = document.line_text(row);
+			let fallback_runs;
+			let mut highlight_runs = highlights.line_highlights(&document, row);
+			if highlight_runs.is_empty() {
+				fallback_runs = [HighlightRun {
+					byte_len: text.len(),
+					color: fallback_color,
+				}];
+				highlight_runs = &fallback_runs;
+			}
 
-		for i in start_row..max_row {
-			let diff_line = &diff_lines[i];
+			build_line_segments(text, highlight_runs, tab_size, &buffer_font, char_advance)
+				.into_iter()
+				.map(|(seg_text, runs, x_offset, byte_start)| {
+					let shaped = window
+						.text_system()
+						.shape_line(seg_text, font_size, &runs, None);
+					LineSegment {
+						shaped,
+						x_offset,
+						byte_start,
+					}
+				})
+				.collect::<Rc<[LineSegment]>>()
+		};
+		let total_rows = display_map.len();
+
+		// The review stream has its own scrollbar
+		let scrollbar_layout = if fit_to_rows {
+			None
+		} else {
+			self.layout_scrollbar(
+				total_rows as f32,
+				bounds,
+				point(0., scroll_y),
+				height_in_lines,
+				window,
+				cx,
+			)
+		};
+
+		let max_scroll_x = self.diff_pane.read(cx).max_scroll_x(cx);
+		let horizontal_scrollbar_layout =
+			self.layout_horizontal_scrollbar(text_hitbox.bounds, scroll_x, max_scroll_x, window);
+
+		for (display_ix, display_row) in (start_row..).zip(visible_rows) {
+			let i = display_row.row;
+			let diff_line = &document.lines()[i];
 			let is_active = Some(i) == selection;
 
-			let fallback_color = match diff_line.diff_type {
-				DiffType::_Header => opaque_grey(0.5, 1.0),
-				DiffType::Normal => cx.theme().colors().editor_foreground,
-				DiffType::Added => cx.theme().status().created,
-				DiffType::Removed => cx.theme().status().deleted,
-			};
+			let fallback_color = fallback_color(diff_line.diff_type, cx);
 
 			let background_color = match (is_active, diff_line.diff_type) {
-				(_, DiffType::_Header) => cx.theme().colors().editor_background,
+				(_, DiffType::Header) => cx.theme().colors().editor_subheader_background,
 				(false, DiffType::Normal) => cx.theme().colors().editor_background,
 				(true, DiffType::Normal) => cx.theme().colors().editor_active_line_background,
 				(_, DiffType::Added) => cx.theme().status().created_background,
 				(_, DiffType::Removed) => cx.theme().status().deleted_background,
 			};
 
-			let segments = build_line_segments(
-				&diff_line.text,
-				&diff_line.highlight_runs,
-				tab_size,
-				&buffer_font,
-				char_advance,
-			);
+			let shaped_segments = line_cache
+				.rows
+				.get_or_insert_with(i, || shape_row(i, fallback_color, window));
+
+			// Selections and matches are laid out for the whole line. Wrapped rows show a slice
+			// of it, and each row's mask clips away the rest.
+			let selection_range = text_selection
+				.as_ref()
+				.and_then(|text_selection| text_selection.columns_for_row(&document, i))
+				.map(|(columns, continues)| {
+					let start = x_for_column(&shaped_segments, columns.start);
+					let mut end = x_for_column(&shaped_segments, columns.end);
+					// Show that the line break is part of the selection
+					if continues {
+						end += char_advance;
+					}
+					start..end
+				});
 
-			let shaped_segments: Vec<(ShapedLine, Pixels)> = segments
-				.into_iter()
-				.map(|(seg_text, runs, x_offset)| {
-					let shaped = window
-						.text_system()
-						.shape_line(seg_text, font_size, &runs, None);
-					(shaped, x_offset)
+			let search_matches = visible_matches
+				.iter()
+				.filter(|found| found.row == i)
+				.map(|found| {
+					x_for_column(&shaped_segments, found.columns.start)
+						..x_for_column(&shaped_segments, found.columns.end)
 				})
 				.collect();
-			lines.push((shaped_segments, background_color))
+
+			let whitespace = show_whitespace.then(|| {
+				let text = document.line_text(i);
+				let whitespace = find_whitespace(text, indent_style);
+				let extent = |column: usize| {
+					x_for_column(&shaped_segments, column)
+						..x_for_column(&shaped_segments, column + 1)
+				};
+				let span = |columns: Range<usize>| {
+					x_for_column(&shaped_segments, columns.start)
+						..x_for_column(&shaped_segments, columns.end)
+				};
+				WhitespaceLayout {
+					tabs: whitespace.tabs.into_iter().map(extent).collect(),
+					spaces: whitespace.spaces.into_iter().map(extent).collect(),
+					highlights: whitespace
+						.wrong_indent
+						.into_iter()
+						.chain(whitespace.trailing)
+						.map(span)
+						.collect(),
+					carriage_return: document
+						.line_has_cr(i)
+						.then(|| x_for_column(&shaped_segments, text.len())),
+				}
+			});
+
+			let caret = caret
+				.filter(|_| caret_display_row == Some(display_ix))
+				.map(|caret| x_for_column(&shaped_segments, caret.column));
+
+			lines.push(RowLayout {
+				display_row: display_row.clone(),
+				x_shift: x_for_column(&shaped_segments, display_row.columns.start),
+				x_end: display_map
+					.continues_on_next_row(display_ix)
+					.then(|| x_for_column(&shaped_segments, display_row.columns.end)),
+				segments: shaped_segments,
+				background: background_color,
+				selection: selection_range,
+				search_matches,
+				whitespace,
+				caret,
+			})
 		}
 
-		self.diff_pane.update(cx, |diff_pane, _cx| {
-			diff_pane.last_bounds = Some(bounds);
+		// Name the definition the viewport is in, unless its first line is still visible. The
+		// review stream pins file headers there instead.
+		let sticky_header = visible_rows
+			.first()
+			.filter(|_| !fit_to_rows)
+			.and_then(|top_row| {
+				highlights
+					.enclosing_definition(&document, top_row.row)
+					.filter(|row| *row < top_row.row)
+			})
+			.map(|row| {
+				let fallback_color = fallback_color(document.lines()[row].diff_type, cx);
+				let segments = line_cache
+					.rows
+					.get_or_insert_with(row, || shape_row(row, fallback_color, window));
+				let hitbox = window.insert_hitbox(
+					Bounds {
+						origin: bounds.origin,
+						size: size(bounds.size.width - Self::SCROLLBAR_WIDTH, line_height),
+					},
+					HitboxBehavior::Normal,
+				);
+				StickyHeader {
+					row,
+					segments,
+					hitbox,
+				}
+			});
+
+		// Keep rows within a few pages of the viewport so long scrolls don't grow the cache forever
+		let keep_margin = (height_in_lines.ceil() as usize) * 4;
+		let keep_rows = visible_document_rows.start.saturating_sub(keep_margin)
+			..visible_document_rows.end + keep_margin;
+		line_cache
+			.rows
+			.trim(ShapedLineCache::MAX_CACHED_ROWS, |row| {
+				keep_rows.contains(row)
+			});
+		drop(line_cache);
+
+		let whitespace_glyphs = show_whitespace.then(|| {
+			let color = cx.theme().colors().editor_invisible;
+			let mut shape_glyph = |glyph: &'static str| {
+				let run = TextRun {
+					len: glyph.len(),
+					font: buffer_font.clone(),
+					color,
+					background_color: None,
+					underline: None,
+					strikethrough: None,
+				};
+				window
+					.text_system()
+					.shape_line(glyph.into(), font_size, &[run], None)
+			};
+			WhitespaceGlyphs {
+				tab: shape_glyph("→"),
+				space: shape_glyph("·"),
+				carriage_return: shape_glyph("␍"),
+			}
 		});
 
-		let diff_regions = self.compute_diff_regions(diff_lines);
+		let position_map = Rc::new(PositionMap {
+			text_bounds: text_hitbox.bounds,
+			line_height,
+			scroll_y,
+			scroll_x,
+			rows: lines.iter().map(|row| row.segments.clone()).collect(),
+			display_map: display_map.clone(),
+		});
 
 		DiffLayout {
+			bounds,
 			lines,
-			// gutter_hitbox,
+			gutter_hitbox,
 			gutter_dimensions,
 			scrollbar_layout,
+			horizontal_scrollbar_layout,
 			text_hitbox,
 			line_height,
+			scroll_x,
 			line_numbers,
-			diff_regions,
+			position_map,
+			display_map,
+			search_match_rows,
+			whitespace_glyphs,
+			sticky_header,
 		}
 	}
 
@@ -675,12 +1532,13 @@ impl Element for DiffElement {
 		&mut self,
 		_: Option<&GlobalElementId>,
 		__inspector_id: Option<&gpui::InspectorElementId>,
-		bounds: Bounds<gpui::Pixels>,
+		_: Bounds<gpui::Pixels>,
 		_: &mut Self::RequestLayoutState,
 		layout: &mut Self::PrepaintState,
 		window: &mut Window,
 		cx: &mut App,
 	) {
+		let bounds = layout.bounds;
 		self.paint_mouse_listeners(layout, bounds, window, cx);
 
 		// I guess GPUI registers action on every 'frame'... weird.
@@ -694,11 +1552,10 @@ impl Element for DiffElement {
 		let selection = self.diff_pane.read(cx).selection;
 		let active_line_background = cx.theme().colors().editor_active_line_background;
 
-		for (i, line_number) in layout.line_numbers.iter().enumerate() {
+		for (i, (line_numbers, row)) in layout.line_numbers.iter().zip(&layout.lines).enumerate() {
 			let y = i as f32 * layout.line_height - (scroll_top % layout.line_height);
-			let row = scroll_y as usize + i;
 
-			if Some(row) == selection {
+			if Some(row.display_row.row) == selection {
 				let bounds = Bounds {
 					origin: bounds.origin + point(px(0.), y),
 					size: size(layout.gutter_dimensions.width, layout.line_height),
@@ -706,48 +1563,145 @@ impl Element for DiffElement {
 				window.paint_quad(fill(bounds, active_line_background));
 			}
 
-			let origin = bounds.origin
-				+ point(
-					layout.gutter_dimensions.width
-						- layout.gutter_dimensions.right_padding
-						- line_number.width,
-					y,
-				);
-			line_number
-				.paint(
-					origin,
-					layout.line_height,
-					TextAlign::Left,
-					None,
-					window,
-					cx,
-				)
-				.expect("Failed to paint line number");
-		}
-
-		for (i, (segments, line_bg)) in layout.lines.iter().enumerate() {
-			let y = i as f32 * layout.line_height - (scroll_top % layout.line_height);
+			let columns = [
+				(
+					&line_numbers.old,
+					layout.gutter_dimensions.old_column_right(),
+				),
+				(
+					&line_numbers.new,
+					layout.gutter_dimensions.new_column_right(),
+				),
+			];
 
-			let origin = bounds.origin + point(layout.gutter_dimensions.width, y);
-			let size = size(bounds.size.width, layout.line_height);
-			window.paint_quad(fill(Bounds { origin, size }, *line_bg));
+			for (line_number, column_right) in columns {
+				let Some(line_number) = line_number else {
+					continue;
+				};
 
-			for (shaped_segment, x_offset) in segments {
-				let seg_origin = origin + point(*x_offset, px(0.0));
-				shaped_segment
+				let origin = bounds.origin + point(column_right - line_number.width, y);
+				line_number
 					.paint(
-						seg_origin,
+						origin,
 						layout.line_height,
 						TextAlign::Left,
 						None,
 						window,
 						cx,
 					)
-					.expect("Failed to paint line segment");
+					.expect("Failed to paint line number");
 			}
 		}
 
+		let selection_background = cx.theme().players().local().selection;
+		let search_match_background = cx.theme().colors().search_match_background;
+		let whitespace_background = cx.theme().status().warning_background;
+		let caret_color = cx.theme().players().local().cursor;
+
+		// Text scrolled or wrapped out of view must not spill into the gutter
+		let text_bounds = layout.text_hitbox.bounds;
+		window.with_content_mask(
+			Some(ContentMask {
+				bounds: text_bounds,
+			}),
+			|window| {
+				for (i, row) in layout.lines.iter().enumerate() {
+					let y = i as f32 * layout.line_height - (scroll_top % layout.line_height);
+
+					let row_origin = text_bounds.origin + point(px(0.), y);
+					let line_size = size(text_bounds.size.width, layout.line_height);
+					window.paint_quad(fill(
+						Bounds {
+							origin: row_origin,
+							size: line_size,
+						},
+						row.background,
+					));
+
+					let origin = row_origin - point(layout.scroll_x + row.x_shift, px(0.));
+
+					// A wrapped line is shaped whole, so only the row's own slice of it is painted.
+					// Wrap points fall at word boundaries, often short of the wrap width, where
+					// the next row's text would otherwise show.
+					let row_left = (origin.x + row.x_shift).max(text_bounds.left());
+					let row_right = row
+						.x_end
+						.map_or(text_bounds.right(), |x_end| origin.x + x_end);
+					let row_mask = Bounds::from_corners(
+						point(row_left, row_origin.y),
+						point(row_right.max(row_left), row_origin.y + layout.line_height),
+					);
+					window.with_content_mask(Some(ContentMask { bounds: row_mask }), |window| {
+						for highlight in row.whitespace.iter().flat_map(|ws| &ws.highlights) {
+							let highlight_bounds = Bounds {
+								origin: origin + point(highlight.start, px(0.)),
+								size: size(highlight.end - highlight.start, layout.line_height),
+							};
+							window.paint_quad(fill(highlight_bounds, whitespace_background));
+						}
+
+						for search_match in &row.search_matches {
+							let match_bounds = Bounds {
+								origin: origin + point(search_match.start, px(0.)),
+								size: size(
+									search_match.end - search_match.start,
+									layout.line_height,
+								),
+							};
+							window.paint_quad(fill(match_bounds, search_match_background));
+						}
+
+						if let Some(selection) = &row.selection {
+							let selection_bounds = Bounds {
+								origin: origin + point(selection.start, px(0.)),
+								size: size(selection.end - selection.start, layout.line_height),
+							};
+							window.paint_quad(fill(selection_bounds, selection_background));
+						}
+
+						for segment in row.segments.iter() {
+							let seg_origin = origin + point(segment.x_offset, px(0.0));
+							segment
+								.shaped
+								.paint(
+									seg_origin,
+									layout.line_height,
+									TextAlign::Left,
+									None,
+									window,
+									cx,
+								)
+								.expect("Failed to paint line segment");
+						}
+
+						if let (Some(whitespace), Some(glyphs)) =
+							(&row.whitespace, &layout.whitespace_glyphs)
+						{
+							Self::paint_whitespace(
+								whitespace,
+								glyphs,
+								origin,
+								layout.line_height,
+								window,
+								cx,
+							);
+						}
+
+						if let Some(caret) = row.caret {
+							let caret_bounds = Bounds {
+								origin: origin + point(caret, px(0.)),
+								size: size(px(2.), layout.line_height),
+							};
+							window.paint_quad(fill(caret_bounds, caret_color));
+						}
+					});
+				}
+			},
+		);
+
+		self.paint_sticky_header(layout, window, cx);
 		self.paint_scrollbar(layout, window, cx);
+		self.paint_horizontal_scrollbar(layout, window, cx);
 	}
 }
 
@@ -0,0 +1,209 @@
+// Mapping between rows of a DiffDocument and the rows shown on screen, which differ when long
+// lines are soft wrapped
+use super::document::DiffDocument;
+use super::selection::TextPosition;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// One row as laid out on screen: a byte range of a document row. Without soft wrap every
+/// document row maps to exactly one display row covering all of it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DisplayRow {
+	pub row: usize,
+	pub columns: Range<usize>,
+}
+
+impl DisplayRow {
+	/// Whether this row continues a line wrapped from the display row above
+	pub fn is_continuation(&self) -> bool {
+		self.columns.start > 0
+	}
+}
+
+/// Display rows for a document at a given wrap width. Widths are measured in columns, with tabs
+/// expanded to the next tab stop, which matches the monospace buffer font.
+pub struct DisplayMap {
+	document: Arc<DiffDocument>,
+	tab_size: usize,
+	wrap_column: Option<usize>,
+	display_rows: Vec<DisplayRow>,
+	/// Index into `display_rows` of the first display row of each document row
+	first_display_rows: Vec<usize>,
+	/// Width of the widest document row, in columns
+	max_columns: usize,
+}
+
+impl Default for DisplayMap {
+	fn default() -> Self {
+		Self::new(Arc::default(), 4, None)
+	}
+}
+
+impl DisplayMap {
+	pub fn new(document: Arc<DiffDocument>, tab_size: usize, wrap_column: Option<usize>) -> Self {
+		let tab_size = tab_size.max(1);
+		let mut display_rows = Vec::with_capacity(document.len());
+		let mut first_display_rows = Vec::with_capacity(document.len());
+		let mut max_columns = 0;
+
+		for row in 0..document.len() {
+			first_display_rows.push(display_rows.len());
+			let text = document.line_text(row);
+
+			let mut start = 0;
+			let mut column = 0;
+			let mut start_column = 0;
+			// Position just after the last whitespace on the current display row, where wrapping
+			// doesn't split a word
+			let mut word_break: Option<(usize, usize)> = None;
+
+			for (ix, ch) in text.char_indices() {
+				let next_column = next_column(ch, column, tab_size);
+
+				if let Some(wrap_column) = wrap_column {
+					if next_column - start_column > wrap_column.max(1) && ix > start {
+						let (end, end_column) = word_break
+							.filter(|(end, _)| *end > start)
+							.unwrap_or((ix, column));
+						display_rows.push(DisplayRow {
+							row,
+							columns: start..end,
+						});
+						start = end;
+						start_column = end_column;
+						word_break = None;
+					}
+				}
+
+				if ch.is_whitespace() {
+					word_break = Some((ix + ch.len_utf8(), next_column));
+				}
+				column = next_column;
+			}
+
+			display_rows.push(DisplayRow {
+				row,
+				columns: start..text.len(),
+			});
+			max_columns = max_columns.max(column);
+		}
+
+		Self {
+			document,
+			tab_size,
+			wrap_column,
+			display_rows,
+			first_display_rows,
+			max_columns,
+		}
+	}
+
+	/// Whether the map was built for this document and layout, or needs rebuilding
+	pub fn is_current(
+		&self,
+		document: &Arc<DiffDocument>,
+		tab_size: usize,
+		wrap_column: Option<usize>,
+	) -> bool {
+		Arc::ptr_eq(&self.document, document)
+			&& self.tab_size == tab_size.max(1)
+			&& self.wrap_column == wrap_column
+	}
+
+	pub fn document(&self) -> &Arc<DiffDocument> {
+		&self.document
+	}
+
+	pub fn len(&self) -> usize {
+		self.display_rows.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.display_rows.is_empty()
+	}
+
+	pub fn max_columns(&self) -> usize {
+		self.max_columns
+	}
+
+	pub fn display_row(&self, display_row: usize) -> Option<&DisplayRow> {
+		self.display_rows.get(display_row)
+	}
+
+	/// Whether the document row shown on `display_row` wraps onto the display row after it
+	pub fn continues_on_next_row(&self, display_row: usize) -> bool {
+		match (
+			self.display_rows.get(display_row),
+			self.display_rows.get(display_row + 1),
+		) {
+			(Some(row), Some(next)) => next.row == row.row,
+			_ => false,
+		}
+	}
+
+	pub fn display_rows(&self, range: Range<usize>) -> &[DisplayRow] {
+		let end = range.end.min(self.display_rows.len());
+		&self.display_rows[range.start.min(end)..end]
+	}
+
+	/// First display row of a document row. Rows past the end map to the display row count.
+	pub fn display_row_for(&self, row: usize) -> usize {
+		self.first_display_rows
+			.get(row)
+			.copied()
+			.unwrap_or(self.display_rows.len())
+	}
+
+	/// Display row which shows `position`
+	pub fn display_row_for_position(&self, position: TextPosition) -> usize {
+		let first = self.display_row_for(position.row);
+		let last = self.display_row_for(position.row + 1);
+		let wrapped = self.display_rows(first..last);
+		let ix =
+			wrapped.partition_point(|display_row| display_row.columns.start <= position.column);
+		first + ix.saturating_sub(1)
+	}
+
+	/// Column of `position` within its document row, with tabs expanded
+	pub fn column_for_position(&self, position: TextPosition) -> usize {
+		let text = self.document.line_text(position.row);
+		text.char_indices()
+			.take_while(|(ix, _)| *ix < position.column)
+			.fold(0, |column, (_, ch)| next_column(ch, column, self.tab_size))
+	}
+}
+
+fn next_column(ch: char, column: usize, tab_size: usize) -> usize {
+	if ch == '\t' {
+		(column / tab_size + 1) * tab_size
+	} else {
+		column + 1
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::document::DiffType;
+	use super::*;
+
+	#[test]
+	fn wraps_at_a_word_boundary_short_of_the_wrap_width() {
+		let document = Arc::new(DiffDocument::new("", "aaaa bbbbbbb\n", 3));
+		let row = document
+			.lines()
+			.iter()
+			.position(|line| line.diff_type == DiffType::Added)
+			.unwrap();
+		let display_map = DisplayMap::new(document, 4, Some(8));
+
+		let first = display_map.display_row_for(row);
+		let rows = display_map.display_rows(first..display_map.display_row_for(row + 1));
+		// The break after the space ends the first row three columns before the wrap width, so
+		// the row is clipped there rather than at the width
+		assert_eq!(rows.len(), 2);
+		assert_eq!(rows[0].columns, 0..5);
+		assert_eq!(rows[1].columns, 5..12);
+		assert!(display_map.continues_on_next_row(first));
+		assert!(!display_map.continues_on_next_row(first + 1));
+	}
+}
@@ -0,0 +1,598 @@
+// Immutable model of a single file diff, shared between the DiffPane and its element
+use super::selection::TextPosition;
+use similar::{ChangeTag, TextDiff};
+use std::ops::Range;
+use std::sync::Arc;
+
+#[derive(Clone, PartialEq, Copy, Debug)]
+pub enum DiffType {
+	Header,
+	Normal,
+	Added,
+	Removed,
+}
+
+impl DiffType {
+	/// Whether the row is an addition or removal, rather than context or a header
+	pub fn is_change(self) -> bool {
+		matches!(self, Self::Added | Self::Removed)
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Side {
+	Left,
+	#[default]
+	Right,
+}
+
+#[derive(Clone, Debug)]
+pub struct DiffLine {
+	pub diff_type: DiffType,
+	pub old_index: Option<usize>,
+	pub new_index: Option<usize>,
+}
+
+impl DiffLine {
+	/// Which side of the diff this line's text lives on
+	pub fn side(&self) -> Side {
+		match self.diff_type {
+			DiffType::Removed => Side::Left,
+			_ => Side::Right,
+		}
+	}
+
+	/// Index of the line within the side returned by `side()`
+	pub fn side_index(&self) -> Option<usize> {
+		match self.side() {
+			Side::Left => self.old_index,
+			Side::Right => self.new_index,
+		}
+	}
+}
+
+/// The `@@ -a,b +c,d @@` row in front of a hunk, the changes plus a few lines of context around
+/// them, as `git diff` would print it
+#[derive(Clone, Debug)]
+pub struct Hunk {
+	pub row: usize,
+	pub text: String,
+	old_range: Range<usize>,
+	new_range: Range<usize>,
+}
+
+/// Definition lines found by syntax highlighting in one side's file, which name the hunks in
+/// place of git's funcname rule
+#[derive(Clone, Copy)]
+pub struct SyntaxDefinitions<'a> {
+	/// In ascending order
+	pub lines: &'a [usize],
+	/// Lines highlighted so far, past which the definitions aren't known yet
+	pub highlighted_lines: usize,
+}
+
+impl SyntaxDefinitions<'_> {
+	/// Last definition line at or before `index`, or `None` when the syntax doesn't cover it.
+	/// A syntax naming no definitions at all, like plain text, covers nothing.
+	fn definition_at(&self, index: usize) -> Option<Option<usize>> {
+		if self.lines.is_empty() || index >= self.highlighted_lines {
+			return None;
+		}
+		let ix = self.lines.partition_point(|line| *line <= index);
+		Some(ix.checked_sub(1).map(|ix| self.lines[ix]))
+	}
+}
+
+/// A contiguous run of added or removed rows, used for the scrollbar markers
+#[derive(Clone, Debug)]
+pub struct DiffRegion {
+	pub rows: Range<usize>,
+	pub diff_type: DiffType,
+}
+
+#[derive(Clone)]
+struct SideContent {
+	/// Shared with the syntax highlighting running in the background
+	text: Arc<str>,
+	/// Byte range of each line within `text`, excluding the line terminator
+	line_ranges: Vec<Range<usize>>,
+	/// Lines which git's default funcname rule takes for definitions, in ascending order
+	definitions: Vec<usize>,
+	/// Row showing each line
+	rows: Vec<usize>,
+}
+
+impl SideContent {
+	fn new(text: Arc<str>, line_ranges: Vec<Range<usize>>) -> Self {
+		// Syntax is only known for the lines highlighted so far, so past them the document goes by
+		// git's default funcname rule: lines starting with a letter, `_` or `$`
+		let definitions = line_ranges
+			.iter()
+			.enumerate()
+			.filter(|(_, range)| {
+				text[range.start..range.end]
+					.starts_with(|ch: char| ch.is_alphabetic() || ch == '_' || ch == '$')
+			})
+			.map(|(ix, _)| ix)
+			.collect();
+
+		Self {
+			text,
+			line_ranges,
+			definitions,
+			rows: Vec::new(),
+		}
+	}
+
+	fn line_text(&self, index: usize) -> &str {
+		self.line_ranges
+			.get(index)
+			.map(|range| &self.text[range.clone()])
+			.unwrap_or("")
+	}
+
+	/// Whether the line ends in a carriage return, which `line_text` leaves out
+	fn line_has_cr(&self, index: usize) -> bool {
+		self.line_ranges
+			.get(index)
+			.is_some_and(|range| self.text[range.end..].starts_with('\r'))
+	}
+
+	/// Last definition line at or before `index`
+	fn definition_at(&self, index: usize) -> Option<usize> {
+		let ix = self.definitions.partition_point(|line| *line <= index);
+		ix.checked_sub(1).map(|ix| self.definitions[ix])
+	}
+}
+
+/// Both sides of a diff along with the computed row list. Built once when a file is opened and
+/// then shared via `Arc`, so painting a frame never has to copy or rescan the whole file.
+#[derive(Clone)]
+pub struct DiffDocument {
+	left: SideContent,
+	right: SideContent,
+	lines: Vec<DiffLine>,
+	hunks: Vec<Hunk>,
+	regions: Vec<DiffRegion>,
+	first_change: Option<usize>,
+	/// Lines of unchanged text around the changes of each hunk. Changes closer together than
+	/// twice this share a hunk; nothing is left out of the document.
+	context_lines: usize,
+}
+
+impl Default for DiffDocument {
+	fn default() -> Self {
+		Self::new(String::new(), String::new(), HUNK_CONTEXT_LINES)
+	}
+}
+
+/// Lines of context git puts around the changes of a hunk by default
+const HUNK_CONTEXT_LINES: usize = 3;
+/// Longest function name git puts in a hunk header, in bytes
+const HUNK_FUNCNAME_LEN: usize = 80;
+
+/// Formats one side of a hunk header. Like git, the count is left out when it is one, and an
+/// empty range starts at the line before it.
+fn hunk_range(start: usize, len: usize) -> String {
+	match len {
+		0 => format!("{},0", start),
+		1 => format!("{}", start + 1),
+		_ => format!("{},{}", start + 1, len),
+	}
+}
+
+/// Converts the line slices produced by `similar` into byte ranges of the original text. The
+/// slices are contiguous, so offsets are simply the running sum of their lengths.
+fn line_ranges(lines: &[&str]) -> Vec<Range<usize>> {
+	let mut offset = 0;
+	lines
+		.iter()
+		.map(|line| {
+			let start = offset;
+			offset += line.len();
+			let content_len = line.trim_end_matches(['\n', '\r']).len();
+			start..start + content_len
+		})
+		.collect()
+}
+
+fn compute_regions(lines: &[DiffLine]) -> Vec<DiffRegion> {
+	let mut regions = Vec::new();
+	let mut last_diff_index = 0;
+	let mut last_diff_type = DiffType::Normal;
+	for (ix, line) in lines.iter().enumerate() {
+		// Headers belong to the unchanged text around them
+		let diff_type = match line.diff_type {
+			DiffType::Header => DiffType::Normal,
+			diff_type => diff_type,
+		};
+		if diff_type != last_diff_type {
+			if last_diff_type != DiffType::Normal {
+				regions.push(DiffRegion {
+					rows: last_diff_index..ix,
+					diff_type: last_diff_type,
+				});
+			}
+			last_diff_index = ix;
+			last_diff_type = diff_type;
+		}
+	}
+
+	if last_diff_type != DiffType::Normal {
+		regions.push(DiffRegion {
+			rows: last_diff_index..lines.len(),
+			diff_type: last_diff_type,
+		});
+	}
+
+	regions
+}
+
+impl DiffDocument {
+	pub fn new(
+		left_text: impl Into<Arc<str>>,
+		right_text: impl Into<Arc<str>>,
+		context_lines: usize,
+	) -> Self {
+		let (left_text, right_text) = (left_text.into(), right_text.into());
+		let diff = TextDiff::from_lines(&*left_text, &*right_text);
+
+		let left_line_ranges = line_ranges(diff.old_slices());
+		let right_line_ranges = line_ranges(diff.new_slices());
+		let hunk_ranges = diff
+			.grouped_ops(context_lines)
+			.iter()
+			.filter_map(|ops| {
+				let (first, last) = (ops.first()?, ops.last()?);
+				Some((
+					first.old_range().start..last.old_range().end,
+					first.new_range().start..last.new_range().end,
+				))
+			})
+			.collect::<Vec<_>>();
+		let mut hunk_ranges = hunk_ranges.into_iter().peekable();
+
+		let mut lines = Vec::new();
+		let mut hunks = Vec::new();
+		let mut first_change = None;
+		// Lines of each file before the current change
+		let (mut old_line, mut new_line) = (0, 0);
+
+		for change in diff.iter_all_changes() {
+			if let Some((old_range, new_range)) =
+				hunk_ranges.next_if(|(old, new)| (old.start, new.start) == (old_line, new_line))
+			{
+				hunks.push((lines.len(), old_range, new_range));
+				lines.push(DiffLine {
+					diff_type: DiffType::Header,
+					old_index: None,
+					new_index: None,
+				});
+			}
+			old_line += change.old_index().is_some() as usize;
+			new_line += change.new_index().is_some() as usize;
+
+			let diff_type = match change.tag() {
+				ChangeTag::Delete => DiffType::Removed,
+				ChangeTag::Insert => DiffType::Added,
+				ChangeTag::Equal => DiffType::Normal,
+			};
+
+			if first_change.is_none() && change.tag() != ChangeTag::Equal {
+				first_change = Some(lines.len());
+			}
+
+			lines.push(DiffLine {
+				diff_type,
+				old_index: change.old_index(),
+				new_index: change.new_index(),
+			});
+		}
+
+		let regions = compute_regions(&lines);
+
+		let mut left = SideContent::new(left_text, left_line_ranges);
+		let mut right = SideContent::new(right_text, right_line_ranges);
+		left.rows = vec![0; left.line_ranges.len()];
+		right.rows = vec![0; right.line_ranges.len()];
+		for (row, line) in lines.iter().enumerate() {
+			if let Some(index) = line.old_index {
+				left.rows[index] = row;
+			}
+			if let Some(index) = line.new_index {
+				right.rows[index] = row;
+			}
+		}
+
+		let hunks = hunks
+			.into_iter()
+			.map(|(row, old_range, new_range)| Hunk {
+				row,
+				text: String::new(),
+				old_range,
+				new_range,
+			})
+			.collect();
+
+		let mut document = Self {
+			left,
+			right,
+			lines,
+			hunks,
+			regions,
+			first_change,
+			context_lines,
+		};
+		let no_syntax = SyntaxDefinitions {
+			lines: &[],
+			highlighted_lines: 0,
+		};
+		document.hunks = document.named_hunks(no_syntax, no_syntax);
+		document
+	}
+
+	/// Hunks with their headers. Like git, each hunk is named after the last definition before
+	/// it in the old file, and new files, which have nothing there, use their own text. Lines
+	/// covered by `left` and `right` go by their syntax, the rest by git's funcname rule.
+	fn named_hunks(&self, left: SyntaxDefinitions, right: SyntaxDefinitions) -> Vec<Hunk> {
+		let definition = |side: Side, syntax: SyntaxDefinitions, start: usize| {
+			let content = self.side(side);
+			let index = start.checked_sub(1)?;
+			let line = syntax
+				.definition_at(index)
+				.unwrap_or_else(|| content.definition_at(index))?;
+			Some(content.line_text(line))
+		};
+
+		self.hunks
+			.iter()
+			.map(|hunk| {
+				let (old_range, new_range) = (&hunk.old_range, &hunk.new_range);
+				let funcname = definition(Side::Left, left, old_range.start)
+					.or_else(|| definition(Side::Right, right, new_range.start))
+					.map(|text| {
+						let text = text.trim_end();
+						let end = (0..=HUNK_FUNCNAME_LEN.min(text.len()))
+							.rev()
+							.find(|end| text.is_char_boundary(*end))
+							.unwrap_or(0);
+						format!(" {}", &text[..end])
+					})
+					.unwrap_or_default();
+
+				let text = format!(
+					"@@ -{} +{} @@{}",
+					hunk_range(old_range.start, old_range.len()),
+					hunk_range(new_range.start, new_range.len()),
+					funcname
+				);
+				Hunk {
+					text,
+					..hunk.clone()
+				}
+			})
+			.collect()
+	}
+
+	/// The same diff with its hunks named after the definitions syntax highlighting found, or
+	/// `None` when that doesn't change any of their headers
+	pub fn with_syntax_definitions(
+		&self,
+		left: SyntaxDefinitions,
+		right: SyntaxDefinitions,
+	) -> Option<Self> {
+		let hunks = self.named_hunks(left, right);
+		let is_changed = hunks
+			.iter()
+			.zip(&self.hunks)
+			.any(|(hunk, old_hunk)| hunk.text != old_hunk.text);
+		is_changed.then(|| Self {
+			hunks,
+			..self.clone()
+		})
+	}
+
+	pub fn lines(&self) -> &[DiffLine] {
+		&self.lines
+	}
+
+	pub fn len(&self) -> usize {
+		self.lines.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.lines.is_empty()
+	}
+
+	/// Row showing the line at `index` in the given side's file. Unchanged lines belong to both.
+	pub fn row_for_line(&self, side: Side, index: usize) -> Option<usize> {
+		self.side(side).rows.get(index).copied()
+	}
+
+	pub fn hunks(&self) -> &[Hunk] {
+		&self.hunks
+	}
+
+	/// Row of the definition which `row` is part of: the last definition line at or before it,
+	/// in whichever file the row's text comes from. Header rows count as the row below them.
+	pub fn enclosing_definition(&self, row: usize) -> Option<usize> {
+		let line = self.lines[row..]
+			.iter()
+			.find(|line| line.diff_type != DiffType::Header)?;
+		let side = self.side(line.side());
+		let index = side.definition_at(line.side_index()?)?;
+		side.rows.get(index).copied()
+	}
+
+	/// Number of lines in the old (left) file
+	pub fn left_line_count(&self) -> usize {
+		self.left.line_ranges.len()
+	}
+
+	/// Number of lines in the new (right) file
+	pub fn right_line_count(&self) -> usize {
+		self.right.line_ranges.len()
+	}
+
+	pub fn regions(&self) -> &[DiffRegion] {
+		&self.regions
+	}
+
+	pub fn first_change(&self) -> Option<usize> {
+		self.first_change
+	}
+
+	/// Line of the new file shown at `row`. Rows without one, like removed lines, give the closest
+	/// line of the new file, preferring the one below, which took the removed text's place.
+	pub fn nearest_right_line(&self, row: usize) -> Option<usize> {
+		let line = self.lines.get(row)?;
+		if let Some(index) = line.new_index {
+			return Some(index);
+		}
+
+		let below = self.lines[row..]
+			.iter()
+			.enumerate()
+			.find_map(|(distance, line)| Some((distance, line.new_index?)));
+		let above = self.lines[..row]
+			.iter()
+			.rev()
+			.enumerate()
+			.find_map(|(distance, line)| Some((distance + 1, line.new_index?)));
+		match (above, below) {
+			(Some((above_distance, above)), Some((below_distance, below))) => {
+				Some(if above_distance < below_distance {
+					above
+				} else {
+					below
+				})
+			}
+			(above, below) => above.or(below).map(|(_, index)| index),
+		}
+	}
+
+	/// Number of added and removed lines
+	pub fn change_counts(&self) -> (usize, usize) {
+		self.lines
+			.iter()
+			.fold((0, 0), |(added, removed), line| match line.diff_type {
+				DiffType::Added => (added + 1, removed),
+				DiffType::Removed => (added, removed + 1),
+				_ => (added, removed),
+			})
+	}
+
+	fn side(&self, side: Side) -> &SideContent {
+		match side {
+			Side::Left => &self.left,
+			Side::Right => &self.right,
+		}
+	}
+
+	/// Text of the given row, without its line terminator
+	pub fn line_text(&self, row: usize) -> &str {
+		let line = &self.lines[row];
+		if line.diff_type == DiffType::Header {
+			return self
+				.hunks
+				.binary_search_by_key(&row, |hunk| hunk.row)
+				.map(|ix| self.hunks[ix].text.as_str())
+				.unwrap_or("");
+		}
+		line.side_index()
+			.map(|index| self.side(line.side()).line_text(index))
+			.unwrap_or("")
+	}
+
+	/// Whether the row's line ends in `\r\n` rather than `\n`
+	pub fn line_has_cr(&self, row: usize) -> bool {
+		let line = &self.lines[row];
+		line.side_index()
+			.is_some_and(|index| self.side(line.side()).line_has_cr(index))
+	}
+
+	/// The same diff against a new version of the right file, as it is being edited
+	pub fn with_right_text(&self, right_text: String) -> Self {
+		Self::new(self.left.text.clone(), right_text, self.context_lines)
+	}
+
+	/// The same texts, with the changes grouped into hunks by another number of lines of context
+	pub fn with_context_lines(&self, context_lines: usize) -> Self {
+		Self::new(
+			self.left.text.clone(),
+			self.right.text.clone(),
+			context_lines,
+		)
+	}
+
+	pub fn context_lines(&self) -> usize {
+		self.context_lines
+	}
+
+	/// Whole text of one side's file
+	pub fn text(&self, side: Side) -> &Arc<str> {
+		&self.side(side).text
+	}
+
+	/// Number of lines in one side's file
+	pub fn line_count(&self, side: Side) -> usize {
+		self.side(side).line_ranges.len()
+	}
+
+	/// Byte offset of `position` within the right file's text, or `None` for rows which only
+	/// exist in the old file
+	pub fn right_offset(&self, position: TextPosition) -> Option<usize> {
+		let index = self.lines.get(position.row)?.new_index?;
+		let range = self.right.line_ranges.get(index)?;
+		Some(range.start + position.column.min(range.len()))
+	}
+
+	/// Position of a byte offset within the right file's text. Offsets inside a line terminator
+	/// map to the end of that line.
+	pub fn position_for_right_offset(&self, offset: usize) -> TextPosition {
+		let line_ranges = &self.right.line_ranges;
+		let index = line_ranges
+			.partition_point(|range| range.start <= offset)
+			.saturating_sub(1);
+		let Some(range) = line_ranges.get(index) else {
+			return TextPosition::default();
+		};
+
+		let column = offset.saturating_sub(range.start).min(range.len());
+		let row = self.row_for_line(Side::Right, index).unwrap_or(0);
+		TextPosition::new(row, column)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const OLD: &str = "fn main() {\n}\n\nimpl Foo {\n\tfn bar() {\n\t\tone();\n\t\ttwo();\n\t\tthree();\n\t\tfour();\n\t\tfive();\n\t}\n}\n";
+
+	fn header(document: &DiffDocument) -> &str {
+		document.line_text(document.hunks()[0].row)
+	}
+
+	#[test]
+	fn hunk_headers_are_named_by_syntax_definitions_once_highlighted() {
+		let document = DiffDocument::new(OLD, OLD.replace("five", "six"), 3);
+		// Git's rule only takes lines starting with a letter, so the indented method is missed
+		assert_eq!(header(&document), "@@ -7,6 +7,6 @@ impl Foo {");
+
+		let definitions = [0, 3, 4];
+		let highlighted = |highlighted_lines| SyntaxDefinitions {
+			lines: &definitions,
+			highlighted_lines,
+		};
+		let named = document
+			.with_syntax_definitions(highlighted(12), highlighted(12))
+			.unwrap();
+		assert_eq!(header(&named), "@@ -7,6 +7,6 @@ \tfn bar() {");
+
+		// Highlighting which hasn't reached the hunk yet leaves the header as it was
+		assert!(document
+			.with_syntax_definitions(highlighted(5), highlighted(5))
+			.is_none());
+	}
+}
@@ -0,0 +1,239 @@
+// The text of a working tree file being edited in the diff, with its undo history
+use std::collections::BTreeSet;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+/// Working tree files open for editing. A file is only edited in one pane at a time, as two
+/// buffers of it would each save over the other's edits.
+static OPEN_FILES: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+/// One reversible change: `old_text` at `offset` was replaced by `new_text`
+#[derive(Clone, Debug)]
+struct Edit {
+	offset: usize,
+	old_text: String,
+	new_text: String,
+}
+
+impl Edit {
+	/// Whether `next` continues typing or deleting where this edit left off, so that undo takes
+	/// back a whole word rather than a single character
+	fn can_merge(&self, next: &Edit) -> bool {
+		let is_typing = |edit: &Edit| edit.old_text.is_empty() && !edit.new_text.contains('\n');
+		let is_deleting = |edit: &Edit| edit.new_text.is_empty() && !edit.old_text.contains('\n');
+
+		if is_typing(self) && is_typing(next) {
+			next.offset == self.offset + self.new_text.len()
+		} else if is_deleting(self) && is_deleting(next) {
+			// Backspace moves the offset back, delete keeps it in place
+			next.offset + next.old_text.len() == self.offset || next.offset == self.offset
+		} else {
+			false
+		}
+	}
+
+	fn merge(&mut self, next: Edit) {
+		if next.new_text.is_empty() && next.offset < self.offset {
+			self.old_text.insert_str(0, &next.old_text);
+			self.offset = next.offset;
+		} else {
+			self.old_text.push_str(&next.old_text);
+			self.new_text.push_str(&next.new_text);
+		}
+	}
+}
+
+pub struct EditBuffer {
+	path: PathBuf,
+	text: String,
+	/// Contents of the file on disk, as last loaded or saved
+	saved_text: String,
+	undo_stack: Vec<Edit>,
+	redo_stack: Vec<Edit>,
+}
+
+impl EditBuffer {
+	/// Opens the file for editing, unless another buffer already has it open
+	pub fn open(path: PathBuf, text: String) -> Option<Self> {
+		let mut open_files = OPEN_FILES.lock().unwrap_or_else(PoisonError::into_inner);
+		if !open_files.insert(path.clone()) {
+			return None;
+		}
+		Some(Self {
+			path,
+			saved_text: text.clone(),
+			text,
+			undo_stack: Vec::new(),
+			redo_stack: Vec::new(),
+		})
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	pub fn text(&self) -> &str {
+		&self.text
+	}
+
+	pub fn is_dirty(&self) -> bool {
+		self.text != self.saved_text
+	}
+
+	/// Replaces `range` with `new_text` and returns the offset just after the inserted text
+	pub fn edit(&mut self, range: Range<usize>, new_text: &str) -> usize {
+		let edit = Edit {
+			offset: range.start,
+			old_text: self.text[range.clone()].to_string(),
+			new_text: new_text.to_string(),
+		};
+		self.text.replace_range(range, new_text);
+		self.redo_stack.clear();
+
+		let cursor = edit.offset + edit.new_text.len();
+		match self.undo_stack.last_mut() {
+			Some(last) if last.can_merge(&edit) => last.merge(edit),
+			_ => self.undo_stack.push(edit),
+		}
+		cursor
+	}
+
+	/// Reverts the last edit and returns the offset where it was made
+	pub fn undo(&mut self) -> Option<usize> {
+		let edit = self.undo_stack.pop()?;
+		let range = edit.offset..edit.offset + edit.new_text.len();
+		self.text.replace_range(range, &edit.old_text);
+		let cursor = edit.offset + edit.old_text.len();
+		self.redo_stack.push(edit);
+		Some(cursor)
+	}
+
+	/// Reapplies the last undone edit and returns the offset just after it
+	pub fn redo(&mut self) -> Option<usize> {
+		let edit = self.redo_stack.pop()?;
+		let range = edit.offset..edit.offset + edit.old_text.len();
+		self.text.replace_range(range, &edit.new_text);
+		let cursor = edit.offset + edit.new_text.len();
+		self.undo_stack.push(edit);
+		Some(cursor)
+	}
+
+	pub fn save(&mut self) -> std::io::Result<()> {
+		std::fs::write(&self.path, &self.text)?;
+		self.saved_text = self.text.clone();
+		Ok(())
+	}
+}
+
+impl Drop for EditBuffer {
+	fn drop(&mut self) {
+		OPEN_FILES
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.remove(&self.path);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A buffer of a file named after the test, as each file can only be open once
+	fn buffer(name: &str, text: &str) -> EditBuffer {
+		EditBuffer::open(PathBuf::from(name), text.to_string()).unwrap()
+	}
+
+	#[test]
+	fn consecutive_typing_is_undone_at_once() {
+		let mut buffer = buffer("typing", "");
+		assert_eq!(buffer.edit(0..0, "a"), 1);
+		assert_eq!(buffer.edit(1..1, "b"), 2);
+		assert_eq!(buffer.edit(2..2, "c"), 3);
+		assert_eq!(buffer.text(), "abc");
+
+		assert_eq!(buffer.undo(), Some(0));
+		assert_eq!(buffer.text(), "");
+		assert_eq!(buffer.undo(), None);
+		assert_eq!(buffer.redo(), Some(3));
+		assert_eq!(buffer.text(), "abc");
+		assert_eq!(buffer.redo(), None);
+	}
+
+	#[test]
+	fn newlines_and_jumps_start_another_undo_step() {
+		let mut buffer = buffer("jumps", "");
+		buffer.edit(0..0, "a");
+		buffer.edit(1..1, "\n");
+		buffer.edit(2..2, "b");
+		// Typing somewhere else than where the last edit left off
+		buffer.edit(0..0, "c");
+		assert_eq!(buffer.text(), "ca\nb");
+
+		assert_eq!(buffer.undo(), Some(0));
+		assert_eq!(buffer.text(), "a\nb");
+		assert_eq!(buffer.undo(), Some(2));
+		assert_eq!(buffer.text(), "a\n");
+		assert_eq!(buffer.undo(), Some(1));
+		assert_eq!(buffer.text(), "a");
+		assert_eq!(buffer.undo(), Some(0));
+		assert_eq!(buffer.text(), "");
+	}
+
+	#[test]
+	fn backspaces_and_deletes_merge_into_one_step() {
+		let mut buffer = buffer("deleting", "abcd");
+		assert_eq!(buffer.edit(3..4, ""), 3);
+		assert_eq!(buffer.edit(2..3, ""), 2);
+		assert_eq!(buffer.text(), "ab");
+		assert_eq!(buffer.undo(), Some(4));
+		assert_eq!(buffer.text(), "abcd");
+
+		buffer.edit(0..1, "");
+		buffer.edit(0..1, "");
+		assert_eq!(buffer.text(), "cd");
+		assert_eq!(buffer.undo(), Some(2));
+		assert_eq!(buffer.text(), "abcd");
+	}
+
+	#[test]
+	fn editing_clears_the_redo_stack() {
+		let mut buffer = buffer("redo", "one");
+		buffer.edit(3..3, " two");
+		buffer.undo();
+		assert_eq!(buffer.text(), "one");
+
+		buffer.edit(0..3, "three");
+		assert_eq!(buffer.redo(), None);
+		assert_eq!(buffer.text(), "three");
+	}
+
+	#[test]
+	fn dirty_until_the_text_is_back_to_what_was_saved() {
+		let path = std::env::temp_dir().join(format!("hx_diff_edit_buffer_{}", std::process::id()));
+		let mut buffer = EditBuffer::open(path.clone(), "text".to_string()).unwrap();
+		assert!(!buffer.is_dirty());
+
+		buffer.edit(4..4, "s");
+		assert!(buffer.is_dirty());
+		buffer.undo();
+		assert!(!buffer.is_dirty());
+
+		buffer.redo();
+		buffer.save().unwrap();
+		assert!(!buffer.is_dirty());
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "texts");
+		buffer.undo();
+		assert!(buffer.is_dirty());
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn a_file_is_open_in_one_buffer_at_a_time() {
+		let buffer = buffer("open_once", "text");
+		assert!(EditBuffer::open(PathBuf::from("open_once"), "text".to_string()).is_none());
+
+		drop(buffer);
+		assert!(EditBuffer::open(PathBuf::from("open_once"), "text".to_string()).is_some());
+	}
+}
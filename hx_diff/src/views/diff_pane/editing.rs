@@ -0,0 +1,449 @@
+// Editing the working tree file on the right side of the diff, which re-diffs in the background
+// once typing pauses
+use super::document::Side;
+use super::edit_buffer::EditBuffer;
+use super::selection::{TextPosition, TextSelection};
+use super::*;
+use std::ops::Range;
+use std::path::Path;
+use std::time::Duration;
+
+/// How long typing has to pause before the edited text is diffed again
+const REDIFF_DELAY: Duration = Duration::from_millis(30);
+
+/// An edit which hasn't been diffed yet
+pub(super) struct PendingRediff {
+	/// Where the caret goes, as a byte offset into the edited text
+	cursor: usize,
+	_task: Task<()>,
+}
+
+impl DiffPane {
+	/// Whether the open file has edits which haven't been saved
+	pub fn is_dirty(&self) -> bool {
+		self.edit_buffer.as_ref().is_some_and(EditBuffer::is_dirty)
+	}
+
+	/// Path of the file being edited, if the right side is the working tree
+	pub fn editing_path(&self) -> Option<&Path> {
+		self.edit_buffer
+			.as_ref()
+			.map(|edit_buffer| edit_buffer.path())
+	}
+
+	/// Where typed text goes: an empty text selection on a row of the working tree file
+	pub(super) fn caret(&self) -> Option<TextPosition> {
+		self.edit_buffer.as_ref()?;
+		let text_selection = self.text_selection.as_ref()?;
+		(text_selection.is_empty() && self.document.right_offset(text_selection.start).is_some())
+			.then_some(text_selection.start)
+	}
+
+	/// Byte range of the working tree file covered by the text selection. Both ends have to be
+	/// on rows of that file; rows which only exist in the old file are skipped over.
+	fn selected_right_range(&self) -> Option<Range<usize>> {
+		self.edit_buffer.as_ref()?;
+		// Rows don't have the pending edit yet, but the caret is known within the edited text
+		if let Some(pending) = &self.pending_rediff {
+			return Some(pending.cursor..pending.cursor);
+		}
+		let text_selection = self.text_selection.as_ref()?;
+		let start = self.document.right_offset(text_selection.start)?;
+		let end = self.document.right_offset(text_selection.end)?;
+		Some(start..end)
+	}
+
+	fn edit_right_text(
+		&mut self,
+		range: Range<usize>,
+		text: &str,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		let Some(edit_buffer) = self.edit_buffer.as_mut() else {
+			return;
+		};
+		let cursor = edit_buffer.edit(range, text);
+		self.schedule_rediff(cursor, window, cx);
+	}
+
+	/// Diffs the edited text in the background once typing pauses, then puts the caret at
+	/// `cursor`. Another edit before then starts the wait over.
+	fn schedule_rediff(&mut self, cursor: usize, window: &mut Window, cx: &mut Context<Self>) {
+		let task = cx.spawn_in(window, async move |this, cx| {
+			cx.background_executor().timer(REDIFF_DELAY).await;
+			// Read when the wait is over, in case the context lines changed meanwhile
+			let Ok(Some((document, right_text))) = this.update(cx, |this, _cx| {
+				let edit_buffer = this.edit_buffer.as_ref()?;
+				Some((this.document.clone(), edit_buffer.text().to_string()))
+			}) else {
+				return;
+			};
+
+			let document = cx
+				.background_spawn(async move { document.with_right_text(right_text) })
+				.await;
+			this.update_in(cx, |this, window, cx| {
+				if let Some(pending) = this.pending_rediff.take() {
+					this.show_rediff(document, pending.cursor, window, cx);
+				}
+			})
+			.ok();
+		});
+
+		self.pending_rediff = Some(PendingRediff {
+			cursor,
+			_task: task,
+		});
+		cx.notify();
+	}
+
+	/// Diffs a pending edit right away, for actions which go by the rows of the edited text.
+	/// Returns whether there was one.
+	pub(super) fn flush_rediff(&mut self, window: &mut Window, cx: &mut Context<Self>) -> bool {
+		let Some(pending) = self.pending_rediff.take() else {
+			return false;
+		};
+		self.rediff(pending.cursor, window, cx);
+		true
+	}
+
+	/// Rebuilds the diff against the edited text and puts the caret at `cursor`
+	fn rediff(&mut self, cursor: usize, window: &mut Window, cx: &mut Context<Self>) {
+		let Some(edit_buffer) = self.edit_buffer.as_ref() else {
+			return;
+		};
+
+		let document = self
+			.document
+			.with_right_text(edit_buffer.text().to_string());
+		self.show_rediff(document, cursor, window, cx);
+	}
+
+	fn show_rediff(
+		&mut self,
+		document: DiffDocument,
+		cursor: usize,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		let old_document = std::mem::replace(&mut self.document, Arc::new(document));
+		self.update_display_map(cx);
+		// Shaped rows are cached by row number, which now holds other text
+		self.line_cache.borrow_mut().clear_rows();
+		self.rehighlight_edited(old_document.text(Side::Right), cx);
+		self.refresh_search_matches();
+
+		let position = self.document.position_for_right_offset(cursor);
+		self.set_caret(position, window, cx);
+	}
+
+	fn set_caret(&mut self, position: TextPosition, window: &mut Window, cx: &mut Context<Self>) {
+		self.text_selection = Some(TextSelection::from_range(position, position));
+		self.scroll_to_position(position, window, cx);
+		cx.notify();
+	}
+
+	/// Moves the caret to the offset returned by `f`, given the caret's offset and the text
+	fn move_caret_by_offset(
+		&mut self,
+		f: impl FnOnce(usize, &str) -> usize,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		self.flush_rediff(window, cx);
+		let (Some(range), Some(edit_buffer)) = (self.selected_right_range(), &self.edit_buffer)
+		else {
+			return;
+		};
+		let offset = f(range.start, edit_buffer.text());
+		let position = self.document.position_for_right_offset(offset);
+		self.set_caret(position, window, cx);
+	}
+
+	/// Moves the caret to the nearest row of the working tree file above or below, keeping its
+	/// column where the line is long enough. Returns false when there is no caret to move.
+	pub(super) fn move_caret_vertically(
+		&mut self,
+		direction: MoveDirection,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) -> bool {
+		self.flush_rediff(window, cx);
+		let Some(caret) = self.caret() else {
+			return false;
+		};
+
+		let lines = self.document.lines();
+		let target = match direction {
+			MoveDirection::Up => (0..caret.row)
+				.rev()
+				.find(|row| lines[*row].new_index.is_some()),
+			MoveDirection::Down => {
+				(caret.row + 1..lines.len()).find(|row| lines[*row].new_index.is_some())
+			}
+		};
+
+		if let Some(row) = target {
+			let column = caret.column.min(self.document.line_text(row).len());
+			self.selection = Some(row);
+			self.set_caret(TextPosition::new(row, column), window, cx);
+		}
+		true
+	}
+
+	/// Inserts typed characters at the caret, replacing any selected text
+	pub(super) fn handle_key_down(
+		&mut self,
+		event: &KeyDownEvent,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		let keystroke = &event.keystroke;
+		let modifiers = keystroke.modifiers;
+		if modifiers.control || modifiers.platform || modifiers.function {
+			return;
+		}
+
+		let Some(key_char) = keystroke
+			.key_char
+			.as_ref()
+			.filter(|key_char| !key_char.chars().any(char::is_control))
+		else {
+			return;
+		};
+
+		if let Some(range) = self.selected_right_range() {
+			self.edit_right_text(range, key_char, window, cx);
+			cx.stop_propagation();
+		}
+	}
+
+	pub(super) fn backspace(&mut self, _: &Backspace, window: &mut Window, cx: &mut Context<Self>) {
+		let (Some(mut range), Some(edit_buffer)) = (self.selected_right_range(), &self.edit_buffer)
+		else {
+			return;
+		};
+
+		if range.is_empty() {
+			let text = &edit_buffer.text()[..range.start];
+			let len = match text.chars().next_back() {
+				Some('\n') if text.ends_with("\r\n") => 2,
+				Some(ch) => ch.len_utf8(),
+				None => return,
+			};
+			range.start -= len;
+		}
+		self.edit_right_text(range, "", window, cx);
+	}
+
+	pub(super) fn delete(&mut self, _: &Delete, window: &mut Window, cx: &mut Context<Self>) {
+		let (Some(mut range), Some(edit_buffer)) = (self.selected_right_range(), &self.edit_buffer)
+		else {
+			return;
+		};
+
+		if range.is_empty() {
+			let text = &edit_buffer.text()[range.end..];
+			let len = match text.chars().next() {
+				Some('\r') if text.starts_with("\r\n") => 2,
+				Some(ch) => ch.len_utf8(),
+				None => return,
+			};
+			range.end += len;
+		}
+		self.edit_right_text(range, "", window, cx);
+	}
+
+	/// Breaks the line at the caret, keeping the line's terminator and indentation
+	pub(super) fn newline(&mut self, _: &Newline, window: &mut Window, cx: &mut Context<Self>) {
+		self.flush_rediff(window, cx);
+		let (Some(range), Some(text_selection)) =
+			(self.selected_right_range(), &self.text_selection)
+		else {
+			return;
+		};
+
+		let row = text_selection.start.row;
+		let line = self.document.line_text(row);
+		let indent_len = line.len() - line.trim_start_matches([' ', '\t']).len();
+		let indent = &line[..indent_len.min(text_selection.start.column)];
+		let line_ending = if self.document.line_has_cr(row) {
+			"\r\n"
+		} else {
+			"\n"
+		};
+
+		let text = format!("{}{}", line_ending, indent);
+		self.edit_right_text(range, &text, window, cx);
+	}
+
+	/// Inserts a tab, or spaces up to the next tab stop when the file is indented with spaces
+	pub(super) fn tab(&mut self, _: &Tab, window: &mut Window, cx: &mut Context<Self>) {
+		self.flush_rediff(window, cx);
+		let (Some(range), Some(caret)) = (
+			self.selected_right_range(),
+			self.text_selection
+				.as_ref()
+				.map(|selection| selection.start),
+		) else {
+			return;
+		};
+
+		let text = match self.indent_style {
+			Some(IndentStyle::Space) => {
+				let column = self.display_map.column_for_position(caret);
+				" ".repeat(self.tab_size - column % self.tab_size)
+			}
+			_ => "\t".to_string(),
+		};
+		self.edit_right_text(range, &text, window, cx);
+	}
+
+	pub(super) fn move_left(&mut self, _: &MoveLeft, window: &mut Window, cx: &mut Context<Self>) {
+		let collapse = self
+			.text_selection
+			.as_ref()
+			.is_some_and(|selection| !selection.is_empty());
+		self.move_caret_by_offset(
+			|offset, text| {
+				if collapse {
+					return offset;
+				}
+				let text = &text[..offset];
+				match text.chars().next_back() {
+					Some('\n') if text.ends_with("\r\n") => offset - 2,
+					Some(ch) => offset - ch.len_utf8(),
+					None => offset,
+				}
+			},
+			window,
+			cx,
+		);
+	}
+
+	pub(super) fn move_right(
+		&mut self,
+		_: &MoveRight,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		let Some(range) = self.selected_right_range() else {
+			return;
+		};
+		let end = range.end;
+		let collapse = !range.is_empty();
+		self.move_caret_by_offset(
+			|_, text| {
+				if collapse {
+					return end;
+				}
+				let text = &text[end..];
+				match text.chars().next() {
+					Some('\r') if text.starts_with("\r\n") => end + 2,
+					Some(ch) => end + ch.len_utf8(),
+					None => end,
+				}
+			},
+			window,
+			cx,
+		);
+	}
+
+	pub(super) fn move_to_line_start(
+		&mut self,
+		_: &MoveToLineStart,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		self.flush_rediff(window, cx);
+		if let Some(caret) = self
+			.text_selection
+			.as_ref()
+			.map(|selection| selection.start)
+		{
+			if self.selected_right_range().is_some() {
+				self.set_caret(TextPosition::new(caret.row, 0), window, cx);
+			}
+		}
+	}
+
+	pub(super) fn move_to_line_end(
+		&mut self,
+		_: &MoveToLineEnd,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		self.flush_rediff(window, cx);
+		if let Some(caret) = self.text_selection.as_ref().map(|selection| selection.end) {
+			if self.selected_right_range().is_some() {
+				let column = self.document.line_text(caret.row).len();
+				self.set_caret(TextPosition::new(caret.row, column), window, cx);
+			}
+		}
+	}
+
+	pub(super) fn undo(&mut self, _: &Undo, window: &mut Window, cx: &mut Context<Self>) {
+		if let Some(cursor) = self.edit_buffer.as_mut().and_then(EditBuffer::undo) {
+			self.schedule_rediff(cursor, window, cx);
+		}
+	}
+
+	pub(super) fn redo(&mut self, _: &Redo, window: &mut Window, cx: &mut Context<Self>) {
+		if let Some(cursor) = self.edit_buffer.as_mut().and_then(EditBuffer::redo) {
+			self.schedule_rediff(cursor, window, cx);
+		}
+	}
+
+	/// Writes the edited file back to the working tree
+	pub fn save_file(&mut self, cx: &mut Context<Self>) -> std::io::Result<()> {
+		if let Some(edit_buffer) = self.edit_buffer.as_mut() {
+			edit_buffer.save()?;
+			cx.notify();
+		}
+		Ok(())
+	}
+
+	pub(super) fn save(&mut self, _: &Save, window: &mut Window, cx: &mut Context<Self>) {
+		if let Err(error) = self.save_file(cx) {
+			let _ = window.prompt(
+				PromptLevel::Critical,
+				"Failed to save the file",
+				Some(&error.to_string()),
+				&["OK"],
+				cx,
+			);
+		}
+	}
+
+	/// Bar above the diff naming the file being edited, with a dot while it has unsaved changes
+	pub(super) fn render_edit_bar(&self, cx: &Context<Self>) -> Option<impl IntoElement> {
+		let edit_buffer = self.edit_buffer.as_ref()?;
+		let colors = cx.theme().colors();
+		let is_dirty = edit_buffer.is_dirty();
+
+		Some(
+			div()
+				.flex()
+				.flex_row()
+				.items_center()
+				.gap_2()
+				.px_2()
+				.py_1()
+				.border_b_1()
+				.border_color(colors.border)
+				.bg(colors.toolbar_background)
+				.text_sm()
+				.child(
+					div()
+						.text_color(colors.text)
+						.child(crate::common::clean_path_display(edit_buffer.path())),
+				)
+				.child(div().text_color(colors.text_muted).child(if is_dirty {
+					"● Modified"
+				} else {
+					"Editable"
+				})),
+		)
+	}
+}
@@ -0,0 +1,336 @@
+// The find bar shown above the diff, and the search state behind it
+use super::search::{build_query, find_matches, SearchMatch, SearchOptions, SearchScope};
+use super::selection::{TextPosition, TextSelection};
+use super::*;
+
+#[derive(Default)]
+pub struct SearchState {
+	pub is_open: bool,
+	pub query: String,
+	pub options: SearchOptions,
+	/// Matches in row order
+	pub matches: Vec<SearchMatch>,
+	pub active_match: Option<usize>,
+	pub error: Option<SharedString>,
+}
+
+/// Applies a key press to a single line text field: backspace deletes the last character and
+/// printable keys are appended. Returns whether the text changed.
+pub(crate) fn edit_text_field(text: &mut String, event: &KeyDownEvent) -> bool {
+	let keystroke = &event.keystroke;
+	let modifiers = keystroke.modifiers;
+	if modifiers.control || modifiers.platform || modifiers.function {
+		return false;
+	}
+
+	if keystroke.key == "backspace" {
+		return text.pop().is_some();
+	}
+
+	match keystroke
+		.key_char
+		.as_ref()
+		.filter(|key_char| !key_char.chars().any(char::is_control))
+	{
+		Some(key_char) => {
+			text.push_str(key_char);
+			true
+		}
+		None => false,
+	}
+}
+
+impl DiffPane {
+	/// Matches to highlight, empty while the find bar is closed
+	pub(super) fn search_matches(&self) -> &[SearchMatch] {
+		if self.search.is_open {
+			&self.search.matches
+		} else {
+			&[]
+		}
+	}
+
+	pub(super) fn deploy_find(&mut self, _: &Find, window: &mut Window, cx: &mut Context<Self>) {
+		// Seed the query with the selected text when it fits on one line
+		if let Some(text_selection) = &self.text_selection {
+			if !text_selection.is_empty() && text_selection.start.row == text_selection.end.row {
+				// The selection may be left over from before a re-diff
+				let text = self.document.line_text(text_selection.start.row);
+				let start = text.floor_char_boundary(text_selection.start.column);
+				let end = text.floor_char_boundary(text_selection.end.column);
+				self.search.query = text[start..end.max(start)].to_string();
+			}
+		}
+
+		self.search.is_open = true;
+		self.refresh_search_matches();
+		window.focus(&self.find_focus_handle);
+		cx.notify();
+	}
+
+	pub(super) fn dismiss_find(
+		&mut self,
+		_: &DismissFind,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		self.search.is_open = false;
+		cx.focus_self(window);
+		cx.notify();
+	}
+
+	/// Re-runs the query against the current document. The active match becomes the first one at
+	/// or after the selected row.
+	pub(super) fn refresh_search_matches(&mut self) {
+		self.search.matches.clear();
+		self.search.active_match = None;
+		self.search.error = None;
+
+		if !self.search.is_open || self.search.query.is_empty() {
+			return;
+		}
+
+		match build_query(&self.search.query, &self.search.options) {
+			Ok(query) => {
+				self.search.matches =
+					find_matches(&self.document, &query, self.search.options.scope);
+				if !self.search.matches.is_empty() {
+					let row = self.selection.unwrap_or(0);
+					let ix = self.search.matches.partition_point(|found| found.row < row);
+					self.search.active_match = Some(ix % self.search.matches.len());
+				}
+			}
+			Err(error) => {
+				self.search.error = Some(match error {
+					regex::Error::Syntax(_) => "Invalid regex".into(),
+					_ => "Query too large".into(),
+				});
+			}
+		}
+	}
+
+	fn update_search(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+		self.refresh_search_matches();
+		if let Some(ix) = self.search.active_match {
+			self.select_match(ix, window, cx);
+		}
+		cx.notify();
+	}
+
+	fn select_match(&mut self, ix: usize, window: &mut Window, cx: &mut Context<Self>) {
+		let Some(found) = self.search.matches.get(ix) else {
+			return;
+		};
+
+		self.search.active_match = Some(ix);
+		self.text_selection = Some(TextSelection::from_range(
+			TextPosition::new(found.row, found.columns.start),
+			TextPosition::new(found.row, found.columns.end),
+		));
+		self.scroll_to_position(
+			TextPosition::new(found.row, found.columns.start),
+			window,
+			cx,
+		);
+		cx.notify();
+	}
+
+	pub(super) fn select_next_match(
+		&mut self,
+		_: &SelectNextMatch,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		let count = self.search.matches.len();
+		if count > 0 {
+			let ix = self.search.active_match.map_or(0, |ix| (ix + 1) % count);
+			self.select_match(ix, window, cx);
+		}
+	}
+
+	pub(super) fn select_previous_match(
+		&mut self,
+		_: &SelectPreviousMatch,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		let count = self.search.matches.len();
+		if count > 0 {
+			let ix = self
+				.search
+				.active_match
+				.map_or(count - 1, |ix| (ix + count - 1) % count);
+			self.select_match(ix, window, cx);
+		}
+	}
+
+	/// Edits the query. Enter, shift-enter and escape arrive as actions through the FindBar key
+	/// context instead.
+	fn handle_query_key_down(
+		&mut self,
+		event: &KeyDownEvent,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		if edit_text_field(&mut self.search.query, event) {
+			cx.stop_propagation();
+			self.update_search(window, cx);
+		}
+	}
+
+	fn toggle_option(
+		&mut self,
+		toggle: impl FnOnce(&mut SearchOptions),
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		toggle(&mut self.search.options);
+		self.update_search(window, cx);
+		window.focus(&self.find_focus_handle);
+	}
+
+	fn render_toggle(
+		&self,
+		id: &'static str,
+		label: &'static str,
+		active: bool,
+		toggle: impl Fn(&mut SearchOptions) + 'static,
+		cx: &Context<Self>,
+	) -> impl IntoElement {
+		let colors = cx.theme().colors();
+		div()
+			.id(id)
+			.px_1()
+			.rounded_sm()
+			.text_sm()
+			.text_color(if active {
+				colors.text
+			} else {
+				colors.text_muted
+			})
+			.when(active, |div| div.bg(colors.element_selected))
+			.hover(|style| style.bg(colors.element_hover))
+			.child(label)
+			.on_click(cx.listener(move |this, _event: &ClickEvent, window, cx| {
+				this.toggle_option(&toggle, window, cx);
+			}))
+	}
+
+	pub(super) fn render_find_bar(&self, cx: &Context<Self>) -> impl IntoElement {
+		let options = self.search.options;
+		let colors = cx.theme().colors();
+
+		let status: SharedString = if let Some(error) = &self.search.error {
+			error.clone()
+		} else if self.search.query.is_empty() {
+			SharedString::default()
+		} else if self.search.matches.is_empty() {
+			"No results".into()
+		} else {
+			format!(
+				"{}/{}",
+				self.search.active_match.map_or(0, |ix| ix + 1),
+				self.search.matches.len()
+			)
+			.into()
+		};
+
+		let query_box = div()
+			.id("find-query")
+			.track_focus(&self.find_focus_handle)
+			.on_key_down(cx.listener(Self::handle_query_key_down))
+			.flex_grow()
+			.min_w(px(160.))
+			.px_2()
+			.border_1()
+			.rounded_sm()
+			.border_color(colors.border_focused)
+			.bg(colors.editor_background)
+			.text_sm()
+			.map(|div| {
+				if self.search.query.is_empty() {
+					div.text_color(colors.text_placeholder).child("Find")
+				} else {
+					div.text_color(colors.text).child(self.search.query.clone())
+				}
+			});
+
+		let scopes = [
+			("find-scope-all", "All", SearchScope::All),
+			("find-scope-added", "Added", SearchScope::Added),
+			("find-scope-removed", "Removed", SearchScope::Removed),
+			("find-scope-context", "Context", SearchScope::Context),
+		];
+
+		div()
+			.key_context("FindBar")
+			.on_action(cx.listener(Self::select_next_match))
+			.on_action(cx.listener(Self::select_previous_match))
+			.on_action(cx.listener(Self::dismiss_find))
+			.flex()
+			.flex_row()
+			.items_center()
+			.gap_1()
+			.px_2()
+			.py_1()
+			.border_b_1()
+			.border_color(colors.border)
+			.bg(colors.toolbar_background)
+			.child(query_box)
+			.child(self.render_toggle(
+				"find-case",
+				"Aa",
+				options.case_sensitive,
+				|options| options.case_sensitive = !options.case_sensitive,
+				cx,
+			))
+			.child(self.render_toggle(
+				"find-word",
+				"W",
+				options.whole_word,
+				|options| options.whole_word = !options.whole_word,
+				cx,
+			))
+			.child(self.render_toggle(
+				"find-regex",
+				".*",
+				options.regex,
+				|options| options.regex = !options.regex,
+				cx,
+			))
+			.child(div().w(px(1.)).h(px(16.)).mx_1().bg(colors.border))
+			.children(scopes.into_iter().map(|(id, label, scope)| {
+				self.render_toggle(
+					id,
+					label,
+					options.scope == scope,
+					move |options| options.scope = scope,
+					cx,
+				)
+			}))
+			.child(
+				div()
+					.min_w(px(72.))
+					.text_sm()
+					.text_color(if self.search.error.is_some() {
+						colors.text_accent
+					} else {
+						colors.text_muted
+					})
+					.child(status),
+			)
+			.child(
+				div()
+					.id("find-close")
+					.px_1()
+					.rounded_sm()
+					.text_sm()
+					.text_color(colors.text_muted)
+					.hover(|style| style.bg(colors.element_hover))
+					.child("×")
+					.on_click(cx.listener(|this, _event: &ClickEvent, window, cx| {
+						this.dismiss_find(&DismissFind, window, cx);
+					})),
+			)
+	}
+}
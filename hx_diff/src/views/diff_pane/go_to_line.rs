@@ -0,0 +1,175 @@
+// The go-to-line prompt, which jumps to a line number of either the old or the new file
+use super::document::Side;
+use super::find_bar::edit_text_field;
+use super::*;
+
+#[derive(Default)]
+pub struct GoToLineState {
+	pub is_open: bool,
+	pub query: String,
+	/// Whose line numbers the query refers to
+	pub side: Side,
+	pub error: Option<SharedString>,
+}
+
+impl DiffPane {
+	pub(super) fn deploy_go_to_line(
+		&mut self,
+		_: &GoToLine,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		self.go_to_line.is_open = true;
+		self.go_to_line.query.clear();
+		self.go_to_line.error = None;
+		window.focus(&self.go_to_line_focus_handle);
+		cx.notify();
+	}
+
+	pub(super) fn dismiss_go_to_line(
+		&mut self,
+		_: &DismissGoToLine,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		self.go_to_line.is_open = false;
+		cx.focus_self(window);
+		cx.notify();
+	}
+
+	pub(super) fn confirm_go_to_line(
+		&mut self,
+		_: &ConfirmGoToLine,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		let side = self.go_to_line.side;
+		let line_count = match side {
+			Side::Left => self.document.left_line_count(),
+			Side::Right => self.document.right_line_count(),
+		};
+
+		let Ok(line_number) = self.go_to_line.query.trim().parse::<usize>() else {
+			self.go_to_line.error = Some("Not a line number".into());
+			cx.notify();
+			return;
+		};
+
+		// Line numbers are 1-based; numbers past the end go to the last line
+		let index = line_number
+			.saturating_sub(1)
+			.min(line_count.saturating_sub(1));
+		let Some(row) = self.document.row_for_line(side, index) else {
+			self.go_to_line.error = Some("No lines on this side".into());
+			cx.notify();
+			return;
+		};
+
+		self.go_to_line.is_open = false;
+		self.text_selection = None;
+		self.scroll_to(row, window, cx);
+		self.center_selection(&CenterSelection, window, cx);
+		cx.focus_self(window);
+		cx.notify();
+	}
+
+	fn handle_go_to_line_key_down(
+		&mut self,
+		event: &KeyDownEvent,
+		_window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		if edit_text_field(&mut self.go_to_line.query, event) {
+			self.go_to_line.error = None;
+			cx.stop_propagation();
+			cx.notify();
+		}
+	}
+
+	fn render_side_toggle(
+		&self,
+		id: &'static str,
+		label: &'static str,
+		side: Side,
+		cx: &Context<Self>,
+	) -> impl IntoElement {
+		let colors = cx.theme().colors();
+		let active = self.go_to_line.side == side;
+		div()
+			.id(id)
+			.px_1()
+			.rounded_sm()
+			.text_sm()
+			.text_color(if active {
+				colors.text
+			} else {
+				colors.text_muted
+			})
+			.when(active, |div| div.bg(colors.element_selected))
+			.hover(|style| style.bg(colors.element_hover))
+			.child(label)
+			.on_click(cx.listener(move |this, _event: &ClickEvent, window, cx| {
+				this.go_to_line.side = side;
+				window.focus(&this.go_to_line_focus_handle);
+				cx.notify();
+			}))
+	}
+
+	pub(super) fn render_go_to_line(&self, cx: &Context<Self>) -> impl IntoElement {
+		let colors = cx.theme().colors();
+		let line_count = match self.go_to_line.side {
+			Side::Left => self.document.left_line_count(),
+			Side::Right => self.document.right_line_count(),
+		};
+
+		let query_box = div()
+			.id("go-to-line-query")
+			.track_focus(&self.go_to_line_focus_handle)
+			.on_key_down(cx.listener(Self::handle_go_to_line_key_down))
+			.w(px(120.))
+			.px_2()
+			.border_1()
+			.rounded_sm()
+			.border_color(colors.border_focused)
+			.bg(colors.editor_background)
+			.text_sm()
+			.map(|div| {
+				if self.go_to_line.query.is_empty() {
+					div.text_color(colors.text_placeholder)
+						.child(format!("1-{}", line_count.max(1)))
+				} else {
+					div.text_color(colors.text)
+						.child(self.go_to_line.query.clone())
+				}
+			});
+
+		div()
+			.key_context("GoToLine")
+			.on_action(cx.listener(Self::confirm_go_to_line))
+			.on_action(cx.listener(Self::dismiss_go_to_line))
+			.flex()
+			.flex_row()
+			.items_center()
+			.gap_1()
+			.px_2()
+			.py_1()
+			.border_b_1()
+			.border_color(colors.border)
+			.bg(colors.toolbar_background)
+			.child(
+				div()
+					.text_sm()
+					.text_color(colors.text_muted)
+					.child("Go to line"),
+			)
+			.child(query_box)
+			.child(self.render_side_toggle("go-to-line-old", "Old", Side::Left, cx))
+			.child(self.render_side_toggle("go-to-line-new", "New", Side::Right, cx))
+			.children(
+				self.go_to_line
+					.error
+					.clone()
+					.map(|error| div().text_sm().text_color(colors.text_accent).child(error)),
+			)
+	}
+}
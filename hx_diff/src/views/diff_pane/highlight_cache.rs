@@ -0,0 +1,516 @@
+// Highlights kept after switching away from a file, so going back to it, or to another diff of
+// the same blob, picks up where highlighting left off instead of starting over. They are kept in
+// memory while hx_diff runs. Files highlighted to the end are also written to the cache directory
+// for later sessions, without the checkpoints: resuming needs their parser state, which syntect
+// can't serialize, so an edited file is highlighted again from the top.
+use super::*;
+use crate::paths;
+use crate::syntax::{Checkpoint, HighlightRun, ThemeId};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Identifies a file's contents without reading them
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum ContentKey {
+	Blob(git::Sha1Hash),
+	/// Working tree files are taken to be unchanged while their size and mtime are
+	Working {
+		path: PathBuf,
+		modified: SystemTime,
+		len: u64,
+	},
+}
+
+impl ContentKey {
+	/// Key of the contents `DiffPane::get_file_contents` reads for the source, if they can be
+	/// identified
+	pub fn for_source(file_entry: &FileEntry, source: &FileSource) -> Option<Self> {
+		match source {
+			FileSource::Empty => None,
+			FileSource::Working => {
+				let metadata = std::fs::metadata(&file_entry.path).ok()?;
+				Some(Self::Working {
+					path: file_entry.path.clone(),
+					modified: metadata.modified().ok()?,
+					len: metadata.len(),
+				})
+			}
+			FileSource::Index(sha1) | FileSource::Head(sha1) | FileSource::Commit(sha1) => {
+				(!sha1.is_zero()).then_some(Self::Blob(*sha1))
+			}
+		}
+	}
+}
+
+/// The same contents highlight differently with another highlighter, syntax or theme. Switching
+/// back to a theme finds the highlights made before switching away.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct HighlightKey {
+	pub content: ContentKey,
+	pub backend: &'static str,
+	pub syntax: String,
+	pub theme: ThemeId,
+}
+
+/// Highlighting progress through a file, from the top
+#[derive(Clone)]
+pub struct CachedHighlights {
+	pub lines: Arc<Vec<Vec<HighlightRun>>>,
+	pub definitions: Arc<Vec<usize>>,
+	pub checkpoints: Vec<Checkpoint>,
+	pub end: Checkpoint,
+	/// Lines in the file, which are all highlighted once `lines` has as many
+	pub line_count: usize,
+}
+
+impl CachedHighlights {
+	/// Rough guess of a checkpoint's size, whose parser state holds a stack of contexts
+	const CHECKPOINT_SIZE: usize = 2048;
+
+	fn estimated_size(&self) -> usize {
+		let lines = self
+			.lines
+			.iter()
+			.map(|runs| size_of::<Vec<HighlightRun>>() + runs.len() * size_of::<HighlightRun>())
+			.sum::<usize>();
+		lines
+			+ self.definitions.len() * size_of::<usize>()
+			+ (self.checkpoints.len() + 1) * Self::CHECKPOINT_SIZE
+	}
+
+	fn is_complete(&self) -> bool {
+		self.lines.len() >= self.line_count
+	}
+}
+
+struct CacheEntry {
+	highlights: CachedHighlights,
+	size: usize,
+	last_used: u64,
+}
+
+/// Least recently used highlights are dropped once they take up more than the budget
+pub struct HighlightCache {
+	entries: HashMap<HighlightKey, CacheEntry>,
+	total_size: usize,
+	budget: usize,
+	/// Ticks on every use, to order the entries by
+	clock: u64,
+}
+
+impl Default for HighlightCache {
+	fn default() -> Self {
+		Self {
+			entries: HashMap::new(),
+			total_size: 0,
+			budget: Self::MEMORY_BUDGET,
+			clock: 0,
+		}
+	}
+}
+
+impl Global for HighlightCache {}
+
+impl HighlightCache {
+	const MEMORY_BUDGET: usize = 64 * 1024 * 1024;
+
+	/// The highlights kept in memory, or else written by an earlier session
+	pub fn get(key: &HighlightKey, cx: &mut App) -> Option<CachedHighlights> {
+		let cache = cx.default_global::<HighlightCache>();
+		if let Some(highlights) = cache.get_entry(key) {
+			return Some(highlights);
+		}
+
+		let highlights = read_file(key)?;
+		cache.insert_entry(key.clone(), highlights.clone());
+		Some(highlights)
+	}
+
+	/// Keeps the highlights, unless the cache already has as many lines of the same file. Those
+	/// of a whole file are written to disk too, in the background.
+	pub fn insert(key: HighlightKey, highlights: CachedHighlights, cx: &mut App) {
+		let cache = cx.default_global::<HighlightCache>();
+		if !cache.insert_entry(key.clone(), highlights.clone()) || !highlights.is_complete() {
+			return;
+		}
+		cx.background_executor()
+			.spawn(async move { write_file(&key, &highlights) })
+			.detach();
+	}
+
+	fn get_entry(&mut self, key: &HighlightKey) -> Option<CachedHighlights> {
+		self.clock += 1;
+		let entry = self.entries.get_mut(key)?;
+		entry.last_used = self.clock;
+		Some(entry.highlights.clone())
+	}
+
+	/// Whether the highlights were kept
+	fn insert_entry(&mut self, key: HighlightKey, highlights: CachedHighlights) -> bool {
+		let size = highlights.estimated_size();
+		if highlights.lines.is_empty() || size > self.budget {
+			return false;
+		}
+
+		self.clock += 1;
+		if let Some(entry) = self.entries.get_mut(&key) {
+			entry.last_used = self.clock;
+			if entry.highlights.lines.len() >= highlights.lines.len() {
+				return false;
+			}
+		}
+
+		let entry = CacheEntry {
+			highlights,
+			size,
+			last_used: self.clock,
+		};
+		if let Some(replaced) = self.entries.insert(key, entry) {
+			self.total_size -= replaced.size;
+		}
+		self.total_size += size;
+		self.evict();
+		true
+	}
+
+	fn evict(&mut self) {
+		while self.total_size > self.budget {
+			let Some(oldest) = self
+				.entries
+				.iter()
+				.min_by_key(|(_, entry)| entry.last_used)
+				.map(|(key, _)| key.clone())
+			else {
+				break;
+			};
+			if let Some(entry) = self.entries.remove(&oldest) {
+				self.total_size -= entry.size;
+			}
+		}
+	}
+}
+
+/// Most bytes of highlights kept in the cache directory before the least recently used files go
+const DISK_BUDGET: u64 = 256 * 1024 * 1024;
+/// Starts each file, with the version of its layout
+const FILE_MAGIC: &[u8] = b"hx_diff highlights 1\n";
+
+fn cache_file_dir() -> Option<PathBuf> {
+	paths::cache_dir().map(|dir| dir.join("highlights"))
+}
+
+/// Text identifying the key within its file, as file names are only a hash of it
+fn key_text(key: &HighlightKey) -> String {
+	format!("{:?}", key)
+}
+
+fn file_name(key: &HighlightKey) -> String {
+	let mut hasher = DefaultHasher::new();
+	key_text(key).hash(&mut hasher);
+	format!("{:016x}", hasher.finish())
+}
+
+/// The highlights of a whole file written by an earlier session. Reading one marks it as
+/// recently used.
+fn read_file(key: &HighlightKey) -> Option<CachedHighlights> {
+	let path = cache_file_dir()?.join(file_name(key));
+	let highlights = decode(&std::fs::read(&path).ok()?, &key_text(key))?;
+	if let Ok(file) = std::fs::File::options().write(true).open(&path) {
+		let _ = file.set_modified(SystemTime::now());
+	}
+	Some(highlights)
+}
+
+/// Failing only means highlighting the file again next time
+fn write_file(key: &HighlightKey, highlights: &CachedHighlights) {
+	let Some(dir) = cache_file_dir() else {
+		return;
+	};
+	let written = std::fs::create_dir_all(&dir)
+		.and_then(|_| std::fs::write(dir.join(file_name(key)), encode(&key_text(key), highlights)));
+	match written {
+		Ok(()) => trim_dir(&dir, DISK_BUDGET),
+		Err(error) => eprintln!(
+			"Warning: Couldn't cache highlights in {}: {}",
+			dir.display(),
+			error
+		),
+	}
+}
+
+/// Removes the least recently used files until the rest take up no more than `budget` bytes
+fn trim_dir(dir: &Path, budget: u64) {
+	let Ok(entries) = std::fs::read_dir(dir) else {
+		return;
+	};
+	let mut files = entries
+		.flatten()
+		.filter_map(|entry| {
+			let metadata = entry.metadata().ok()?;
+			Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+		})
+		.collect::<Vec<_>>();
+	let mut total_size = files.iter().map(|(_, len, _)| len).sum::<u64>();
+	files.sort();
+	for (_, len, path) in files {
+		if total_size <= budget {
+			break;
+		}
+		if std::fs::remove_file(path).is_ok() {
+			total_size -= len;
+		}
+	}
+}
+
+/// Lays out the lines and definitions in little-endian numbers, after the key they belong to
+fn encode(key_text: &str, highlights: &CachedHighlights) -> Vec<u8> {
+	fn push_number(bytes: &mut Vec<u8>, number: usize) {
+		bytes.extend_from_slice(&(number as u64).to_le_bytes());
+	}
+
+	let mut bytes = FILE_MAGIC.to_vec();
+	push_number(&mut bytes, key_text.len());
+	bytes.extend_from_slice(key_text.as_bytes());
+	push_number(&mut bytes, highlights.end.offset);
+	push_number(&mut bytes, highlights.definitions.len());
+	for definition in highlights.definitions.iter() {
+		push_number(&mut bytes, *definition);
+	}
+	push_number(&mut bytes, highlights.lines.len());
+	for runs in highlights.lines.iter() {
+		push_number(&mut bytes, runs.len());
+		for run in runs {
+			push_number(&mut bytes, run.byte_len);
+			for channel in [run.color.h, run.color.s, run.color.l, run.color.a] {
+				bytes.extend_from_slice(&channel.to_le_bytes());
+			}
+		}
+	}
+	bytes
+}
+
+/// Reads what `encode` wrote, from the front
+struct Decoder<'a> {
+	bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+	fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+		let (taken, rest) = self.bytes.split_first_chunk::<N>()?;
+		self.bytes = rest;
+		Some(*taken)
+	}
+
+	fn number(&mut self) -> Option<usize> {
+		usize::try_from(u64::from_le_bytes(self.take()?)).ok()
+	}
+
+	fn channel(&mut self) -> Option<f32> {
+		Some(f32::from_le_bytes(self.take()?))
+	}
+
+	fn text(&mut self) -> Option<&'a [u8]> {
+		let len = self.number()?;
+		let (text, rest) = self.bytes.split_at_checked(len)?;
+		self.bytes = rest;
+		Some(text)
+	}
+}
+
+/// The highlights `encode` laid out, if the bytes hold those of the key
+fn decode(bytes: &[u8], key_text: &str) -> Option<CachedHighlights> {
+	let mut decoder = Decoder {
+		bytes: bytes.strip_prefix(FILE_MAGIC)?,
+	};
+	if decoder.text()? != key_text.as_bytes() {
+		return None;
+	}
+
+	let end_offset = decoder.number()?;
+	let definitions = (0..decoder.number()?)
+		.map(|_| decoder.number())
+		.collect::<Option<Vec<_>>>()?;
+	let line_count = decoder.number()?;
+	let mut lines = Vec::new();
+	for _ in 0..line_count {
+		let runs = (0..decoder.number()?)
+			.map(|_| {
+				Some(HighlightRun {
+					byte_len: decoder.number()?,
+					color: Hsla {
+						h: decoder.channel()?,
+						s: decoder.channel()?,
+						l: decoder.channel()?,
+						a: decoder.channel()?,
+					},
+				})
+			})
+			.collect::<Option<Vec<_>>>()?;
+		lines.push(runs);
+	}
+
+	Some(CachedHighlights {
+		lines: Arc::new(lines),
+		definitions: Arc::new(definitions),
+		checkpoints: Vec::new(),
+		end: Checkpoint::stateless(line_count, end_offset),
+		line_count,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	fn key(content: u8) -> HighlightKey {
+		HighlightKey {
+			content: ContentKey::Blob(git::Sha1Hash::from_bytes(&[content; 40])),
+			backend: "syntect",
+			syntax: "Rust".to_string(),
+			theme: ThemeId::new("One Dark", "colours"),
+		}
+	}
+
+	fn highlights(line_count: usize) -> CachedHighlights {
+		let run = HighlightRun {
+			byte_len: 4,
+			color: Hsla {
+				h: 0.25,
+				s: 0.5,
+				l: 0.75,
+				a: 1.,
+			},
+		};
+		CachedHighlights {
+			lines: Arc::new(vec![vec![run]; line_count]),
+			definitions: Arc::new(vec![0]),
+			checkpoints: Vec::new(),
+			end: Checkpoint::stateless(line_count, line_count * 4),
+			line_count,
+		}
+	}
+
+	/// A cache with room for `count` entries of `highlights(1)`
+	fn cache_for(count: usize) -> HighlightCache {
+		HighlightCache {
+			budget: count * highlights(1).estimated_size(),
+			..HighlightCache::default()
+		}
+	}
+
+	#[test]
+	fn keys_differ_by_backend_syntax_and_theme() {
+		let mut cache = HighlightCache::default();
+		assert!(cache.insert_entry(key(b'a'), highlights(1)));
+
+		let others = [
+			key(b'b'),
+			HighlightKey {
+				backend: "tree-sitter",
+				..key(b'a')
+			},
+			HighlightKey {
+				syntax: "Python".to_string(),
+				..key(b'a')
+			},
+			HighlightKey {
+				theme: ThemeId::new("One Light", "colours"),
+				..key(b'a')
+			},
+			HighlightKey {
+				theme: ThemeId::new("One Dark", "edited colours"),
+				..key(b'a')
+			},
+		];
+		for other in &others {
+			assert!(cache.get_entry(other).is_none(), "{:?}", other);
+			assert_ne!(file_name(other), file_name(&key(b'a')));
+		}
+		assert!(cache.get_entry(&key(b'a')).is_some());
+	}
+
+	#[test]
+	fn least_recently_used_are_evicted_first() {
+		let mut cache = cache_for(3);
+		for content in [b'a', b'b', b'c'] {
+			assert!(cache.insert_entry(key(content), highlights(1)));
+		}
+		// Using `a` leaves `b` the least recently used
+		cache.get_entry(&key(b'a'));
+		cache.insert_entry(key(b'd'), highlights(1));
+
+		assert!(cache.get_entry(&key(b'b')).is_none());
+		for content in [b'a', b'c', b'd'] {
+			assert!(cache.get_entry(&key(content)).is_some());
+		}
+		assert_eq!(cache.total_size, cache.budget);
+	}
+
+	#[test]
+	fn budget_bounds_the_entries() {
+		assert_eq!(HighlightCache::default().budget, 64 * 1024 * 1024);
+
+		let mut cache = cache_for(2);
+		// Too big to keep at all, and evicting others wouldn't make room
+		assert!(!cache.insert_entry(key(b'a'), highlights(1000)));
+		assert!(cache.entries.is_empty());
+
+		// Longer highlights of the same file replace shorter ones, not the other way round
+		assert!(cache.insert_entry(key(b'a'), highlights(1)));
+		assert!(cache.insert_entry(key(b'a'), highlights(2)));
+		assert!(!cache.insert_entry(key(b'a'), highlights(1)));
+		assert_eq!(cache.get_entry(&key(b'a')).unwrap().lines.len(), 2);
+		assert_eq!(cache.total_size, highlights(2).estimated_size());
+		assert!(!cache.insert_entry(key(b'b'), highlights(0)));
+	}
+
+	#[test]
+	fn files_hold_the_highlights_of_their_key() {
+		let written = highlights(3);
+		let bytes = encode(&key_text(&key(b'a')), &written);
+
+		let read = decode(&bytes, &key_text(&key(b'a'))).unwrap();
+		assert_eq!(read.line_count, 3);
+		assert_eq!(read.definitions, written.definitions);
+		assert_eq!(read.end.line, 3);
+		assert_eq!(read.end.offset, 12);
+		assert!(read.checkpoints.is_empty());
+		let run = &read.lines[2][0];
+		assert_eq!(run.byte_len, 4);
+		assert_eq!(run.color, written.lines[2][0].color);
+
+		assert!(decode(&bytes, &key_text(&key(b'b'))).is_none());
+		assert!(decode(&bytes[..bytes.len() - 1], &key_text(&key(b'a'))).is_none());
+		assert!(decode(b"not highlights", &key_text(&key(b'a'))).is_none());
+	}
+
+	#[test]
+	fn least_recently_used_files_are_removed() {
+		let dir = std::env::temp_dir().join(format!("hx_diff_highlights_{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let now = SystemTime::now();
+		for (name, age) in [("new", 0), ("old", 20), ("middle", 10)] {
+			let path = dir.join(name);
+			std::fs::write(&path, [0; 100]).unwrap();
+			let file = std::fs::File::options().write(true).open(&path).unwrap();
+			file.set_modified(now - Duration::from_secs(age)).unwrap();
+		}
+
+		trim_dir(&dir, 250);
+		let mut left = std::fs::read_dir(&dir)
+			.unwrap()
+			.map(|entry| entry.unwrap().file_name())
+			.collect::<Vec<_>>();
+		left.sort();
+		assert_eq!(left, ["middle", "new"]);
+
+		trim_dir(&dir, 100);
+		assert!(!dir.join("middle").exists());
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
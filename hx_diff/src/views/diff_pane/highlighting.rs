@@ -0,0 +1,414 @@
+// Syntax highlighting run on a background thread a batch of lines at a time, and only as far
+// down each file as the rows shown so far
+use super::document::{Side, SyntaxDefinitions};
+use super::highlight_cache::{CachedHighlights, ContentKey, HighlightCache, HighlightKey};
+use super::*;
+use crate::syntax::{Checkpoint, HighlightBatch, HighlightContext, HighlightRun};
+use std::ops::Range;
+
+/// Lines highlighted past the last one shown, so scrolling finds them ready
+const LOOKAHEAD_LINES: usize = 500;
+/// Most lines highlighted by one batch, so jumping far down a file shows progress along the way
+const BATCH_LINES: usize = 5000;
+
+/// Highlights of one side's file, filled in from the top
+struct SideHighlights {
+	context: HighlightContext,
+	/// Where the highlights are kept when the file is closed, unless its text isn't known by a key
+	key: Option<HighlightKey>,
+	text: Arc<str>,
+	line_count: usize,
+	/// Shared with the element while it paints a frame
+	lines: Arc<Vec<Vec<HighlightRun>>>,
+	/// Lines naming a definition, among those highlighted
+	definitions: Arc<Vec<usize>>,
+	checkpoints: Vec<Checkpoint>,
+	/// State after the last highlighted line
+	end: Checkpoint,
+}
+
+impl SideHighlights {
+	/// Starts from the highlights cached under `key`, if there are any
+	fn new(
+		context: HighlightContext,
+		key: Option<HighlightKey>,
+		document: &DiffDocument,
+		side: Side,
+		cx: &mut App,
+	) -> Self {
+		let line_count = document.line_count(side);
+		// Highlights read from disk can't resume, so they have to cover as many lines
+		let cached = key
+			.as_ref()
+			.and_then(|key| HighlightCache::get(key, cx))
+			.filter(|cached| cached.line_count == line_count);
+		let (lines, definitions, checkpoints, end) = match cached {
+			Some(cached) => (
+				cached.lines,
+				cached.definitions,
+				cached.checkpoints,
+				cached.end,
+			),
+			None => (Arc::default(), Arc::default(), Vec::new(), context.start()),
+		};
+		Self {
+			context,
+			key,
+			text: document.text(side).clone(),
+			line_count,
+			lines,
+			definitions,
+			checkpoints,
+			end,
+		}
+	}
+
+	/// Keeps the highlights so far for the next time the same text is shown
+	fn save(self, cx: &mut App) {
+		let Some(key) = self.key else {
+			return;
+		};
+		let highlights = CachedHighlights {
+			lines: self.lines,
+			definitions: self.definitions,
+			checkpoints: self.checkpoints,
+			end: self.end,
+			line_count: self.line_count,
+		};
+		HighlightCache::insert(key, highlights, cx);
+	}
+
+	/// Lines which should be highlighted to show `shown_lines`, when highlighting should start
+	/// on them
+	fn wanted_lines(&self, shown_lines: usize) -> Option<usize> {
+		let wanted = (shown_lines + LOOKAHEAD_LINES).min(self.line_count);
+		// Wait for half the lookahead to be used up, rather than following every scrolled line
+		let threshold = (shown_lines + LOOKAHEAD_LINES / 2).min(self.line_count);
+		(self.lines.len() < threshold).then_some(wanted)
+	}
+
+	fn syntax_definitions(&self) -> SyntaxDefinitions<'_> {
+		SyntaxDefinitions {
+			lines: &self.definitions,
+			highlighted_lines: self.lines.len(),
+		}
+	}
+
+	fn apply(&mut self, batch: HighlightBatch) {
+		Arc::make_mut(&mut self.lines).extend(batch.lines);
+		Arc::make_mut(&mut self.definitions).extend(batch.definitions);
+		self.checkpoints.extend(batch.checkpoints);
+		self.end = batch.end;
+	}
+
+	/// Switches to a new version of the text which differs from `line` on. The lines before the
+	/// last checkpoint at or before it keep their highlights, and highlighting resumes from there.
+	fn edited_from(&mut self, line: usize, document: &DiffDocument, side: Side) {
+		let kept = self
+			.checkpoints
+			.partition_point(|checkpoint| checkpoint.line <= line);
+		self.checkpoints.truncate(kept);
+		// Highlighting records the checkpoint again as it passes
+		self.end = self
+			.checkpoints
+			.pop()
+			.unwrap_or_else(|| self.context.start());
+
+		let end_line = self.end.line;
+		Arc::make_mut(&mut self.lines).truncate(end_line);
+		let definitions = Arc::make_mut(&mut self.definitions);
+		definitions.truncate(definitions.partition_point(|definition| *definition < end_line));
+
+		self.text = document.text(side).clone();
+		self.line_count = document.line_count(side);
+		// The edited text no longer matches the key
+		self.key = None;
+	}
+}
+
+#[derive(Default)]
+pub struct LazyHighlights {
+	/// Left and right sides, while a file is open
+	sides: Option<(SideHighlights, SideHighlights)>,
+	/// Batch being highlighted in the background
+	task: Option<Task<()>>,
+	/// Bumped whenever the text or colours change, so batches of the previous ones are dropped
+	generation: usize,
+	/// Keys of the left and right texts of the document, when they can be cached
+	content_keys: (Option<ContentKey>, Option<ContentKey>),
+}
+
+impl LazyHighlights {
+	fn side_mut(&mut self, side: Side) -> Option<&mut SideHighlights> {
+		self.sides.as_mut().map(|(left, right)| match side {
+			Side::Left => left,
+			Side::Right => right,
+		})
+	}
+
+	/// Stops any batch in progress, whose results are about to be out of date
+	fn invalidate(&mut self) {
+		self.task = None;
+		self.generation += 1;
+	}
+}
+
+/// The highlights so far, cheap to clone for painting a frame
+#[derive(Clone, Default)]
+pub struct HighlightSnapshot {
+	left: (Arc<Vec<Vec<HighlightRun>>>, Arc<Vec<usize>>),
+	right: (Arc<Vec<Vec<HighlightRun>>>, Arc<Vec<usize>>),
+}
+
+impl HighlightSnapshot {
+	fn side(&self, side: Side) -> (&[Vec<HighlightRun>], &[usize]) {
+		let (lines, definitions) = match side {
+			Side::Left => &self.left,
+			Side::Right => &self.right,
+		};
+		(lines.as_slice(), definitions.as_slice())
+	}
+
+	/// Syntax highlight runs covering `document.line_text(row)`. Rows which aren't highlighted
+	/// yet have none, and are drawn in the fallback colour.
+	pub fn line_highlights(&self, document: &DiffDocument, row: usize) -> &[HighlightRun] {
+		let line = &document.lines()[row];
+		line.side_index()
+			.and_then(|index| self.side(line.side()).0.get(index))
+			.map(Vec::as_slice)
+			.unwrap_or(&[])
+	}
+
+	/// Row of the definition which `row` is part of. Highlighted lines go by their syntax, the
+	/// rest by git's default funcname rule like the document.
+	pub fn enclosing_definition(&self, document: &DiffDocument, row: usize) -> Option<usize> {
+		let line = document.lines()[row..]
+			.iter()
+			.find(|line| line.diff_type != DiffType::Header)?;
+		let side = line.side();
+		let index = line.side_index()?;
+		let (lines, definitions) = self.side(side);
+		// A syntax which names no definitions at all, like plain text, has nothing better
+		if index >= lines.len() || definitions.is_empty() {
+			return document.enclosing_definition(row);
+		}
+
+		let ix = definitions.partition_point(|line| *line <= index);
+		let definition = definitions[..ix].last()?;
+		document.row_for_line(side, *definition)
+	}
+}
+
+/// Lines of one side's file shown in the rows before `row_end`
+fn lines_through(document: &DiffDocument, side: Side, row_end: usize) -> usize {
+	document.lines()[..row_end.min(document.len())]
+		.iter()
+		.rev()
+		.find_map(|line| match side {
+			Side::Left => line.old_index,
+			Side::Right => line.new_index,
+		})
+		.map_or(0, |index| index + 1)
+}
+
+/// Rows showing the given lines of a side's file, along with any rows of the other side's lines
+/// between them
+fn rows_for_lines(document: &DiffDocument, side: Side, lines: Range<usize>) -> Range<usize> {
+	if lines.is_empty() {
+		return 0..0;
+	}
+	let first = document.row_for_line(side, lines.start);
+	let last = document.row_for_line(side, lines.end - 1);
+	match (first, last) {
+		(Some(first), Some(last)) => first..last + 1,
+		_ => 0..document.len(),
+	}
+}
+
+impl DiffPane {
+	/// Notes the texts of a newly opened file, before `restart_highlighting`, so highlights of
+	/// them can be reused
+	pub(super) fn set_content_keys(&mut self, left: Option<ContentKey>, right: Option<ContentKey>) {
+		self.highlights.content_keys = (left, right);
+	}
+
+	/// Starts highlighting the open file from the top, like after opening it or when the colours
+	/// or language changed. Whatever was highlighted before is cached, and highlighting picks up
+	/// from the cache when the same text was highlighted the same way before.
+	pub(super) fn restart_highlighting(&mut self, cx: &mut App) {
+		self.highlights.invalidate();
+		if let Some((left, right)) = self.highlights.sides.take() {
+			left.save(cx);
+			right.save(cx);
+		}
+
+		self.line_cache.borrow_mut().clear_rows();
+		let Some(path) = self.file_path.clone() else {
+			return;
+		};
+		let mut side = |side| {
+			let context = self
+				.syntax_highlighter
+				.context(self.document.text(side), &path);
+			let content_key = match side {
+				Side::Left => &self.highlights.content_keys.0,
+				Side::Right => &self.highlights.content_keys.1,
+			};
+			let key = content_key.clone().map(|content| HighlightKey {
+				content,
+				backend: context.backend_name(),
+				syntax: context.syntax_name().to_string(),
+				theme: self.syntax_highlighter.theme_id().clone(),
+			});
+			SideHighlights::new(context, key, &self.document, side, cx)
+		};
+		self.highlights.sides = Some((side(Side::Left), side(Side::Right)));
+		// Cached highlights may already reach past some of the hunks
+		self.name_hunks_by_syntax(cx);
+	}
+
+	/// Names the hunk headers after the definitions highlighted so far, rather than only by
+	/// git's funcname rule
+	pub(super) fn name_hunks_by_syntax(&mut self, cx: &App) {
+		let Some((left, right)) = self.highlights.sides.as_ref() else {
+			return;
+		};
+		let Some(document) = self
+			.document
+			.with_syntax_definitions(left.syntax_definitions(), right.syntax_definitions())
+		else {
+			return;
+		};
+
+		// Only header text changes, so the same row stays at the top and the same match active
+		let top_row = self
+			.display_map
+			.display_row(self.scroll_y as usize)
+			.map(|display_row| display_row.row);
+		let active_match = self
+			.search
+			.active_match
+			.and_then(|ix| self.search.matches.get(ix).cloned());
+
+		self.document = Arc::new(document);
+		self.update_display_map(cx);
+		self.line_cache.borrow_mut().clear_rows();
+		self.refresh_search_matches();
+
+		if let Some(top_row) = top_row {
+			self.scroll_y = self.display_map.display_row_for(top_row) as f32;
+		}
+		if let Some(ix) = active_match.and_then(|active_match| {
+			self.search
+				.matches
+				.iter()
+				.position(|found| *found == active_match)
+		}) {
+			self.search.active_match = Some(ix);
+		}
+	}
+
+	/// Keeps the highlights of the right file up to the checkpoint before the first line which
+	/// differs from `old_text`, the right file's text before an edit
+	pub(super) fn rehighlight_edited(&mut self, old_text: &str, cx: &App) {
+		self.highlights.invalidate();
+		let new_text = self.document.text(Side::Right).clone();
+		let common_len = old_text
+			.bytes()
+			.zip(new_text.bytes())
+			.take_while(|(old, new)| old == new)
+			.count();
+		let first_edited_line = new_text.as_bytes()[..common_len]
+			.iter()
+			.filter(|byte| **byte == b'\n')
+			.count();
+
+		self.highlights.content_keys.1 = None;
+		if let Some(right) = self.highlights.side_mut(Side::Right) {
+			right.edited_from(first_edited_line, &self.document, Side::Right);
+		}
+		// Rows past the edit lost their highlights until highlighting catches up again
+		self.line_cache.borrow_mut().clear_rows();
+		self.name_hunks_by_syntax(cx);
+	}
+
+	pub(super) fn highlight_snapshot(&self) -> HighlightSnapshot {
+		self.highlights
+			.sides
+			.as_ref()
+			.map_or_else(HighlightSnapshot::default, |(left, right)| {
+				HighlightSnapshot {
+					left: (left.lines.clone(), left.definitions.clone()),
+					right: (right.lines.clone(), right.definitions.clone()),
+				}
+			})
+	}
+
+	/// Highlights another batch in the background, when the rows before `row_end` or the
+	/// lookahead after them aren't highlighted yet
+	pub(super) fn highlight_through(&mut self, row_end: usize, cx: &mut Context<Self>) {
+		if self.highlights.task.is_some() {
+			return;
+		}
+		let Some((left, right)) = self.highlights.sides.as_ref() else {
+			return;
+		};
+
+		let wanted = |highlights: &SideHighlights, side| {
+			highlights
+				.wanted_lines(lines_through(&self.document, side, row_end))
+				.map(|wanted| (side, wanted))
+		};
+		let Some((side, wanted)) = wanted(left, Side::Left).or_else(|| wanted(right, Side::Right))
+		else {
+			return;
+		};
+		let highlights = match side {
+			Side::Left => left,
+			Side::Right => right,
+		};
+
+		let context = highlights.context.clone();
+		let text = highlights.text.clone();
+		let from = highlights.end.clone();
+		let line_count = (wanted - highlights.lines.len()).min(BATCH_LINES);
+		let generation = self.highlights.generation;
+
+		self.highlights.task = Some(cx.spawn(async move |this, cx| {
+			let batch = cx
+				.background_spawn(async move { context.highlight(&text, from, line_count) })
+				.await;
+			this.update(cx, |this, cx| {
+				this.apply_highlight_batch(side, generation, batch, cx)
+			})
+			.ok();
+		}));
+	}
+
+	fn apply_highlight_batch(
+		&mut self,
+		side: Side,
+		generation: usize,
+		batch: HighlightBatch,
+		cx: &mut Context<Self>,
+	) {
+		// A newer task has taken over since
+		if generation != self.highlights.generation {
+			return;
+		}
+
+		self.highlights.task = None;
+		let mut lines = 0..0;
+		if let Some(highlights) = self.highlights.side_mut(side) {
+			lines.start = highlights.lines.len();
+			highlights.apply(batch);
+			lines.end = highlights.lines.len();
+		}
+		// The rows of the batch's lines were shaped in the fallback colour, and have their
+		// highlights now
+		let rows = rows_for_lines(&self.document, side, lines);
+		self.line_cache.borrow_mut().clear_row_range(rows);
+		self.name_hunks_by_syntax(cx);
+		cx.notify();
+	}
+}
@@ -0,0 +1,48 @@
+// Cache of per-line layouts which survives across frames
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Stores a value per line id, valid only for one set of shaping parameters (font, font size,
+/// theme generation, ...). Whenever `validate` sees different parameters, everything is dropped.
+pub struct LineLayoutCache<K, P, V> {
+	params: Option<P>,
+	entries: HashMap<K, V>,
+}
+
+impl<K, P, V> Default for LineLayoutCache<K, P, V> {
+	fn default() -> Self {
+		Self {
+			params: None,
+			entries: HashMap::new(),
+		}
+	}
+}
+
+impl<K: Hash + Eq + Copy, P: PartialEq, V: Clone> LineLayoutCache<K, P, V> {
+	pub fn clear(&mut self) {
+		self.entries.clear();
+	}
+
+	/// Drops all entries if `params` differ from the ones the cache was filled with
+	pub fn validate(&mut self, params: P) {
+		if self.params.as_ref() != Some(&params) {
+			self.entries.clear();
+			self.params = Some(params);
+		}
+	}
+
+	pub fn get_or_insert_with(&mut self, key: K, build: impl FnOnce() -> V) -> V {
+		self.entries.entry(key).or_insert_with(build).clone()
+	}
+
+	pub fn remove_where(&mut self, mut remove: impl FnMut(&K) -> bool) {
+		self.entries.retain(|key, _| !remove(key));
+	}
+
+	/// Once more than `capacity` entries are stored, discards every entry rejected by `keep`
+	pub fn trim(&mut self, capacity: usize, mut keep: impl FnMut(&K) -> bool) {
+		if self.entries.len() > capacity {
+			self.entries.retain(|key, _| keep(key));
+		}
+	}
+}
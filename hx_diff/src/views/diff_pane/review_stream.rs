@@ -0,0 +1,423 @@
+// The "review all" mode, in which the pane shows every file of the workspace one after another,
+// each below a header which collapses it. Only the sections near the viewport keep a pane.
+use super::*;
+use std::ops::Range;
+use std::path::PathBuf;
+use theme::ActiveTheme;
+
+struct ReviewSection {
+	entry_id: ProjectEntryId,
+	path: PathBuf,
+	/// Diff of the file, built when the section scrolls into view and dropped once it's far
+	/// out of it
+	pane: Option<Entity<DiffPane>>,
+	/// Lines added and removed, kept from the last pane built for the header
+	change_counts: Option<(usize, usize)>,
+	is_collapsed: bool,
+}
+
+pub struct ReviewState {
+	pub is_open: bool,
+	sections: Vec<ReviewSection>,
+	list_state: ListState,
+	/// Section last reported at the top of the stream
+	top_section: Option<usize>,
+}
+
+impl Default for ReviewState {
+	fn default() -> Self {
+		Self {
+			is_open: false,
+			sections: Vec::new(),
+			list_state: ListState::new(0, ListAlignment::Top, px(ReviewState::OVERDRAW)),
+			top_section: None,
+		}
+	}
+}
+
+impl ReviewState {
+	/// How far past the viewport sections are built, so they are ready before scrolling in
+	const OVERDRAW: f32 = 400.;
+	/// Sections either side of those in view which keep their panes, so scrolling back a
+	/// little doesn't build them again
+	const KEPT_SECTIONS: usize = 4;
+}
+
+impl DiffPane {
+	pub fn is_reviewing(&self) -> bool {
+		self.review.is_open
+	}
+
+	pub(super) fn toggle_review_stream(
+		&mut self,
+		_: &ToggleReviewStream,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		self.set_reviewing(!self.review.is_open, cx);
+		cx.focus_self(window);
+	}
+
+	/// Shows every file one after another, or goes back to the diff of a single file
+	pub fn set_reviewing(&mut self, is_open: bool, cx: &mut Context<Self>) {
+		self.review.is_open = is_open;
+		if self.review.is_open {
+			self.refresh_review_sections(cx);
+		} else {
+			self.drop_section_panes(0..0, cx);
+		}
+		cx.notify();
+	}
+
+	/// Panes of sections whose files have unsaved edits, which are kept until they're saved
+	pub fn dirty_section_panes(&self, cx: &App) -> Vec<Entity<DiffPane>> {
+		self.review
+			.sections
+			.iter()
+			.filter_map(|section| section.pane.clone())
+			.filter(|pane| pane.read(cx).is_dirty())
+			.collect()
+	}
+
+	/// Drops the panes of the sections outside `kept`, except those with unsaved edits
+	fn drop_section_panes(&mut self, kept: Range<usize>, cx: &mut Context<Self>) {
+		for (ix, section) in self.review.sections.iter_mut().enumerate() {
+			let is_dirty = section
+				.pane
+				.as_ref()
+				.is_some_and(|pane| pane.read(cx).is_dirty());
+			if !kept.contains(&ix) && !is_dirty {
+				section.pane = None;
+			}
+		}
+	}
+
+	/// Lists the workspace's files, keeping the sections of files already listed
+	fn refresh_review_sections(&mut self, cx: &mut Context<Self>) {
+		let workspace = self.workspace.read(cx);
+		let files = workspace
+			.entries
+			.iter()
+			.filter(|entry| matches!(entry.kind, EntryKind::File(_)))
+			.map(|entry| (entry.id, entry.path.clone()))
+			.collect::<Vec<_>>();
+
+		let is_current = files.len() == self.review.sections.len()
+			&& files
+				.iter()
+				.zip(&self.review.sections)
+				.all(|((id, _), section)| *id == section.entry_id);
+		if is_current {
+			return;
+		}
+
+		let mut old_sections = std::mem::take(&mut self.review.sections);
+		self.review.sections = files
+			.into_iter()
+			.map(|(entry_id, path)| {
+				match old_sections
+					.iter()
+					.position(|section| section.entry_id == entry_id)
+				{
+					Some(ix) => old_sections.swap_remove(ix),
+					None => ReviewSection {
+						entry_id,
+						path,
+						pane: None,
+						change_counts: None,
+						is_collapsed: false,
+					},
+				}
+			})
+			.collect();
+		self.review.top_section = None;
+
+		self.review.list_state.reset(self.review.sections.len());
+		self.review.list_state.set_scroll_handler(cx.listener(
+			|this, event: &ListScrollEvent, _window, cx| {
+				this.review_scrolled(event.visible_range.clone(), cx);
+			},
+		));
+	}
+
+	fn review_scrolled(&mut self, visible_sections: Range<usize>, cx: &mut Context<Self>) {
+		let top_section = visible_sections.start;
+		if self.review.top_section == Some(top_section) {
+			return;
+		}
+
+		let kept = top_section.saturating_sub(ReviewState::KEPT_SECTIONS)
+			..visible_sections.end + ReviewState::KEPT_SECTIONS;
+		self.drop_section_panes(kept, cx);
+
+		self.review.top_section = Some(top_section);
+		self.report_top_section(cx);
+	}
+
+	/// Scrolls the stream so the file's header is at the top
+	pub fn reveal_in_review(&mut self, entry_id: ProjectEntryId, cx: &mut Context<Self>) {
+		let Some(ix) = self
+			.review
+			.sections
+			.iter()
+			.position(|section| section.entry_id == entry_id)
+		else {
+			return;
+		};
+
+		self.scroll_to_section(ix, cx);
+	}
+
+	fn scroll_to_section(&mut self, ix: usize, cx: &mut Context<Self>) {
+		self.review.list_state.scroll_to(ListOffset {
+			item_ix: ix,
+			offset_in_item: px(0.),
+		});
+		self.review.top_section = Some(ix);
+		cx.notify();
+	}
+
+	/// Steps to the file before or after the one at the top of the stream, which is how the
+	/// stream moves between differences
+	pub(super) fn step_review_section(&mut self, direction: MoveDirection, cx: &mut Context<Self>) {
+		let top = self.review.list_state.logical_scroll_top();
+		let ix = match direction {
+			MoveDirection::Up if top.offset_in_item > px(0.) => top.item_ix,
+			MoveDirection::Up => top.item_ix.saturating_sub(1),
+			MoveDirection::Down => top.item_ix + 1,
+		};
+		if ix < self.review.sections.len() {
+			self.scroll_to_section(ix, cx);
+			self.report_top_section(cx);
+		}
+	}
+
+	/// Lets the file list follow a scroll which didn't come from it
+	fn report_top_section(&mut self, cx: &mut Context<Self>) {
+		let section = self
+			.review
+			.top_section
+			.and_then(|ix| self.review.sections.get(ix));
+		if let Some(section) = section {
+			cx.emit(DiffPaneEvent::ScrolledToEntry {
+				entry_id: section.entry_id,
+			});
+		}
+	}
+
+	/// Scrolls the stream by a viewport's height, less one line so some of the page scrolled
+	/// away stays in view
+	pub(super) fn scroll_review_by_page(
+		&mut self,
+		direction: MoveDirection,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		let line_height = self.get_line_height(window, cx);
+		let page =
+			(self.review.list_state.viewport_bounds().size.height - line_height).max(line_height);
+		self.review.list_state.scroll_by(match direction {
+			MoveDirection::Up => -page,
+			MoveDirection::Down => page,
+		});
+		cx.notify();
+	}
+
+	/// Scrolls to the first or last file
+	pub(super) fn scroll_review_to_end(
+		&mut self,
+		direction: MoveDirection,
+		cx: &mut Context<Self>,
+	) {
+		let ix = match direction {
+			MoveDirection::Up => 0,
+			MoveDirection::Down => self.review.sections.len().saturating_sub(1),
+		};
+		self.scroll_to_section(ix, cx);
+		self.report_top_section(cx);
+	}
+
+	/// Gives the panes built so far the view options of this one, after one of them changed
+	pub(super) fn sync_section_options(&mut self, cx: &mut Context<Self>) {
+		let (line_number_columns, soft_wrap, show_whitespace) = (
+			self.line_number_columns,
+			self.soft_wrap,
+			self.show_whitespace,
+		);
+		for pane in self
+			.review
+			.sections
+			.iter()
+			.filter_map(|section| section.pane.as_ref())
+		{
+			pane.update(cx, |pane, cx| {
+				pane.line_number_columns = line_number_columns;
+				pane.show_whitespace = show_whitespace;
+				if pane.soft_wrap != soft_wrap {
+					pane.soft_wrap = soft_wrap;
+					pane.scroll_x = px(0.);
+					pane.update_display_map(cx);
+				}
+				cx.notify();
+			});
+		}
+	}
+
+	fn toggle_section(&mut self, ix: usize, cx: &mut Context<Self>) {
+		let Some(section) = self.review.sections.get_mut(ix) else {
+			return;
+		};
+
+		section.is_collapsed = !section.is_collapsed;
+		self.review.list_state.splice(ix..ix + 1, 1);
+		// Collapsing the section pinned to the top brings its header back into place
+		if self.review.list_state.logical_scroll_top().item_ix == ix {
+			self.review.list_state.scroll_to(ListOffset {
+				item_ix: ix,
+				offset_in_item: px(0.),
+			});
+		}
+		cx.notify();
+	}
+
+	/// Builds the pane of a section, with the view options of this one
+	fn build_section_pane(
+		&self,
+		entry_id: ProjectEntryId,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) -> Entity<DiffPane> {
+		let pane = DiffPane::new(
+			WeakEntity::new_invalid(),
+			self.workspace.clone(),
+			window,
+			cx,
+		);
+		pane.update(cx, |pane, cx| {
+			pane.fit_to_rows = true;
+			pane.line_number_columns = self.line_number_columns;
+			pane.soft_wrap = self.soft_wrap;
+			pane.show_whitespace = self.show_whitespace;
+			pane.open_diff(entry_id, window, cx);
+		});
+		pane
+	}
+
+	fn render_review_section(
+		&mut self,
+		ix: usize,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) -> AnyElement {
+		let Some(section) = self.review.sections.get(ix) else {
+			return div().into_any_element();
+		};
+
+		if !section.is_collapsed && section.pane.is_none() {
+			let pane = self.build_section_pane(section.entry_id, window, cx);
+			let section = &mut self.review.sections[ix];
+			section.change_counts = Some(pane.read(cx).document.change_counts());
+			section.pane = Some(pane);
+		}
+
+		let section = &self.review.sections[ix];
+		div()
+			.w_full()
+			.flex()
+			.flex_col()
+			.child(self.render_section_header(ix, "review-section-header", cx))
+			.when_some(
+				section.pane.clone().filter(|_| !section.is_collapsed),
+				|div, pane| div.child(pane),
+			)
+			.into_any_element()
+	}
+
+	fn render_section_header(
+		&self,
+		ix: usize,
+		id: &'static str,
+		cx: &Context<Self>,
+	) -> impl IntoElement {
+		let section = &self.review.sections[ix];
+		let colors = cx.theme().colors();
+		let status = cx.theme().status();
+		// A pane being edited has its counts change with the edits
+		let change_counts = section
+			.pane
+			.as_ref()
+			.map(|pane| pane.read(cx).document.change_counts())
+			.or(section.change_counts);
+
+		div()
+			.id((id, ix))
+			.flex()
+			.flex_row()
+			.items_center()
+			.gap_2()
+			.px_2()
+			.py_1()
+			.border_b_1()
+			.border_color(colors.border)
+			.bg(colors.editor_subheader_background)
+			.text_sm()
+			.cursor_pointer()
+			.on_click(cx.listener(move |this, _: &ClickEvent, _window, cx| {
+				this.toggle_section(ix, cx);
+			}))
+			.child(
+				div()
+					.text_color(colors.text_muted)
+					.child(if section.is_collapsed { "▸" } else { "▾" }),
+			)
+			.child(
+				div()
+					.flex_grow()
+					.text_color(colors.text)
+					.child(crate::common::clean_path_display(&section.path)),
+			)
+			.children(change_counts.map(|(added, removed)| {
+				div()
+					.flex()
+					.flex_row()
+					.gap_1()
+					.child(
+						div()
+							.text_color(status.created)
+							.child(format!("+{}", added)),
+					)
+					.child(
+						div()
+							.text_color(status.deleted)
+							.child(format!("−{}", removed)),
+					)
+			}))
+	}
+
+	pub(super) fn render_review_stream(&self, cx: &mut Context<Self>) -> impl IntoElement {
+		// Pin the header of the file at the top once its own header has scrolled away
+		let top = self.review.list_state.logical_scroll_top();
+		let sticky_header = (top.offset_in_item > px(0.)
+			&& top.item_ix < self.review.sections.len())
+		.then(|| self.render_section_header(top.item_ix, "review-sticky-header", cx));
+
+		div()
+			.track_focus(&self.focus_handle)
+			.key_context("ReviewStream")
+			.relative()
+			.flex_grow()
+			.min_h_0()
+			.bg(cx.theme().colors().editor_background)
+			.child(
+				list(
+					self.review.list_state.clone(),
+					cx.processor(|this, ix, window, cx| this.render_review_section(ix, window, cx)),
+				)
+				.size_full(),
+			)
+			.children(
+				sticky_header
+					.map(|header| div().absolute().top_0().left_0().right_0().child(header)),
+			)
+	}
+}
@@ -0,0 +1,163 @@
+// Find-in-diff: query compilation and matching against the rows of a DiffDocument
+use super::document::{DiffDocument, DiffType};
+use regex::{Regex, RegexBuilder};
+use std::ops::Range;
+
+/// Which kind of rows a search looks at
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SearchScope {
+	#[default]
+	All,
+	Added,
+	Removed,
+	Context,
+}
+
+impl SearchScope {
+	fn includes(self, diff_type: DiffType) -> bool {
+		match (self, diff_type) {
+			(_, DiffType::Header) => false,
+			(Self::All, _) => true,
+			(Self::Added, DiffType::Added) => true,
+			(Self::Removed, DiffType::Removed) => true,
+			(Self::Context, DiffType::Normal) => true,
+			_ => false,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SearchOptions {
+	pub case_sensitive: bool,
+	pub whole_word: bool,
+	pub regex: bool,
+	pub scope: SearchScope,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchMatch {
+	pub row: usize,
+	/// Byte range of the match within the row's text
+	pub columns: Range<usize>,
+}
+
+/// Compiles the query text into a regex honoring the options. Plain-text queries are escaped.
+pub fn build_query(query: &str, options: &SearchOptions) -> Result<Regex, regex::Error> {
+	let mut pattern = if options.regex {
+		query.to_string()
+	} else {
+		regex::escape(query)
+	};
+
+	if options.whole_word {
+		pattern = format!(r"\b(?:{})\b", pattern);
+	}
+
+	RegexBuilder::new(&pattern)
+		.case_insensitive(!options.case_sensitive)
+		.build()
+}
+
+/// Finds all non-empty matches of `query` in the rows included by `scope`, in row order
+pub fn find_matches(
+	document: &DiffDocument,
+	query: &Regex,
+	scope: SearchScope,
+) -> Vec<SearchMatch> {
+	let mut matches = Vec::new();
+	for (row, line) in document.lines().iter().enumerate() {
+		if !scope.includes(line.diff_type) {
+			continue;
+		}
+
+		for found in query.find_iter(document.line_text(row)) {
+			if !found.is_empty() {
+				matches.push(SearchMatch {
+					row,
+					columns: found.range(),
+				});
+			}
+		}
+	}
+	matches
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn options(case_sensitive: bool, whole_word: bool, regex: bool) -> SearchOptions {
+		SearchOptions {
+			case_sensitive,
+			whole_word,
+			regex,
+			scope: SearchScope::All,
+		}
+	}
+
+	fn columns(query: &str, options: SearchOptions, text: &str) -> Vec<Range<usize>> {
+		let query = build_query(query, &options).unwrap();
+		query.find_iter(text).map(|found| found.range()).collect()
+	}
+
+	#[test]
+	fn plain_queries_are_escaped() {
+		let plain = options(true, false, false);
+		assert_eq!(columns("a.b", plain, "a.b axb"), vec![0..3]);
+		assert_eq!(columns("(x", plain, "f(x)"), vec![1..3]);
+		assert_eq!(
+			columns("a.b", options(true, false, true), "a.b axb"),
+			vec![0..3, 4..7]
+		);
+		assert!(build_query("(x", &options(true, false, true)).is_err());
+	}
+
+	#[test]
+	fn case_and_whole_word_options() {
+		assert_eq!(
+			columns("foo", options(false, false, false), "Foo foo FOObar"),
+			vec![0..3, 4..7, 8..11]
+		);
+		assert_eq!(
+			columns("foo", options(true, false, false), "Foo foo FOObar"),
+			vec![4..7]
+		);
+		assert_eq!(
+			columns("foo", options(false, true, false), "Foo foo FOObar foo_x"),
+			vec![0..3, 4..7]
+		);
+		// Whole word applies to the whole of a regex's alternatives
+		assert_eq!(
+			columns("a|b", options(true, true, true), "a ab b"),
+			vec![0..1, 5..6]
+		);
+	}
+
+	#[test]
+	fn matches_are_found_in_the_rows_of_the_scope() {
+		// A header row, then " x1", "-x2", "+x3" and " x4"
+		let document = DiffDocument::new("x1\nx2\nx4\n", "x1\nx3\nx4\n", 3);
+		let query = build_query("x", &SearchOptions::default()).unwrap();
+		let rows = |scope| {
+			find_matches(&document, &query, scope)
+				.into_iter()
+				.map(|found| found.row)
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(rows(SearchScope::All), vec![1, 2, 3, 4]);
+		assert_eq!(rows(SearchScope::Added), vec![3]);
+		assert_eq!(rows(SearchScope::Removed), vec![2]);
+		assert_eq!(rows(SearchScope::Context), vec![1, 4]);
+
+		// Empty matches, like those of `x*` between other characters, are left out
+		let query = build_query("x*", &options(true, false, true)).unwrap();
+		let found = find_matches(&document, &query, SearchScope::Added);
+		assert_eq!(
+			found,
+			vec![SearchMatch {
+				row: 3,
+				columns: 0..1
+			}]
+		);
+	}
+}
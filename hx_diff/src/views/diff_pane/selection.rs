@@ -0,0 +1,356 @@
+// Text selection within the rows of a DiffDocument, and conversion of a selection to text
+use super::document::{DiffDocument, DiffType};
+use std::fmt::Write;
+use std::ops::Range;
+
+/// A position within the diff, as a row and a byte offset into that row's text
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TextPosition {
+	pub row: usize,
+	pub column: usize,
+}
+
+impl TextPosition {
+	pub fn new(row: usize, column: usize) -> Self {
+		Self { row, column }
+	}
+}
+
+/// The unit a selection grows by while dragging: single, double or triple click
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Granularity {
+	Character,
+	Word,
+	Line,
+}
+
+impl Granularity {
+	pub fn from_click_count(click_count: usize) -> Self {
+		match click_count {
+			0 | 1 => Self::Character,
+			2 => Self::Word,
+			_ => Self::Line,
+		}
+	}
+}
+
+/// What to put on the clipboard when copying a selection
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CopyMode {
+	/// The selected rows exactly as displayed, removed and added lines interleaved
+	Displayed,
+	/// Only rows that exist in the new file
+	NewSide,
+	/// Only rows that exist in the old file
+	OldSide,
+	/// Whole selected rows as a unified diff hunk
+	Patch,
+}
+
+#[derive(Clone, Debug)]
+pub struct TextSelection {
+	/// The unit (character, word or line) under the initial click, which always stays selected
+	origin: Range<TextPosition>,
+	pub start: TextPosition,
+	pub end: TextPosition,
+	granularity: Granularity,
+}
+
+impl TextSelection {
+	pub fn new(document: &DiffDocument, position: TextPosition, granularity: Granularity) -> Self {
+		let unit = unit_at(document, position, granularity);
+		Self {
+			start: unit.start,
+			end: unit.end,
+			origin: unit,
+			granularity,
+		}
+	}
+
+	/// A character-wise selection of exactly `start..end`
+	pub fn from_range(start: TextPosition, end: TextPosition) -> Self {
+		Self {
+			origin: start..end,
+			start,
+			end,
+			granularity: Granularity::Character,
+		}
+	}
+
+	/// Moves the end of the selection opposite the origin to cover `position`
+	pub fn extend_to(&mut self, document: &DiffDocument, position: TextPosition) {
+		let unit = unit_at(document, position, self.granularity);
+		if unit.start < self.origin.start {
+			self.start = unit.start;
+			self.end = self.origin.end;
+		} else {
+			self.start = self.origin.start;
+			self.end = unit.end.max(self.origin.end);
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.start == self.end
+	}
+
+	/// Byte range of `row` covered by the selection, or `None` if the row isn't selected. The
+	/// flag is true when the selection continues past the end of the row.
+	pub fn columns_for_row(
+		&self,
+		document: &DiffDocument,
+		row: usize,
+	) -> Option<(Range<usize>, bool)> {
+		if self.is_empty() || row < self.start.row || row > self.end.row {
+			return None;
+		}
+
+		let line_len = document.line_text(row).len();
+		let start = if row == self.start.row {
+			self.start.column
+		} else {
+			0
+		};
+		let (end, continues) = if row == self.end.row {
+			(self.end.column, false)
+		} else {
+			(line_len, true)
+		};
+
+		if start >= end && !continues {
+			return None;
+		}
+
+		Some((start.min(line_len)..end.min(line_len), continues))
+	}
+
+	/// Builds the clipboard contents for this selection
+	pub fn text(&self, document: &DiffDocument, mode: CopyMode) -> String {
+		if document.is_empty() {
+			return String::new();
+		}
+
+		if mode == CopyMode::Patch {
+			return self.patch(document);
+		}
+
+		let mut text = String::new();
+		for row in self.start.row..=self.end.row.min(document.len().saturating_sub(1)) {
+			let diff_type = document.lines()[row].diff_type;
+			let excluded = matches!(
+				(mode, diff_type),
+				(_, DiffType::Header)
+					| (CopyMode::NewSide, DiffType::Removed)
+					| (CopyMode::OldSide, DiffType::Added)
+			);
+
+			if excluded {
+				continue;
+			}
+
+			if let Some((columns, continues)) = self.columns_for_row(document, row) {
+				text.push_str(&document.line_text(row)[columns]);
+				if continues {
+					text.push('\n');
+				}
+			}
+		}
+		text
+	}
+
+	/// Formats the selected rows as a unified diff hunk, with a `@@ -a,b +c,d @@` header
+	fn patch(&self, document: &DiffDocument) -> String {
+		let lines = document.lines();
+		let mut last_row = self.end.row.min(lines.len().saturating_sub(1));
+		if self.end.column == 0 && self.end.row > self.start.row {
+			last_row = self.end.row - 1;
+		}
+		let rows = self.start.row..last_row + 1;
+
+		// Lines of each file which come before the hunk, used for the header start positions
+		let old_before = lines[..rows.start]
+			.iter()
+			.filter(|line| line.old_index.is_some())
+			.count();
+		let new_before = lines[..rows.start]
+			.iter()
+			.filter(|line| line.new_index.is_some())
+			.count();
+
+		let mut body = String::new();
+		let mut old_count = 0;
+		let mut new_count = 0;
+
+		for row in rows {
+			let prefix = match lines[row].diff_type {
+				DiffType::Header => continue,
+				DiffType::Normal => ' ',
+				DiffType::Added => '+',
+				DiffType::Removed => '-',
+			};
+
+			if lines[row].old_index.is_some() {
+				old_count += 1;
+			}
+			if lines[row].new_index.is_some() {
+				new_count += 1;
+			}
+
+			body.push(prefix);
+			body.push_str(document.line_text(row));
+			body.push('\n');
+		}
+
+		// An empty range starts at the line before it, like git does
+		let old_start = old_before + (old_count > 0) as usize;
+		let new_start = new_before + (new_count > 0) as usize;
+
+		let mut patch = String::new();
+		writeln!(
+			patch,
+			"@@ -{},{} +{},{} @@",
+			old_start, old_count, new_start, new_count
+		)
+		.unwrap();
+		patch.push_str(&body);
+		patch
+	}
+}
+
+fn is_word_char(ch: char) -> bool {
+	ch.is_alphanumeric() || ch == '_'
+}
+
+/// Byte range of the word (or run of other non-whitespace characters) at `column`
+pub fn word_range(text: &str, column: usize) -> Range<usize> {
+	let column = column.min(text.len());
+	let Some(ch) = text[column..].chars().next() else {
+		return column..column;
+	};
+
+	let class = |c: char| (is_word_char(c), c.is_whitespace());
+	let target = class(ch);
+
+	let start = text[..column]
+		.char_indices()
+		.rev()
+		.take_while(|(_, c)| class(*c) == target)
+		.last()
+		.map_or(column, |(ix, _)| ix);
+	let end = text[column..]
+		.char_indices()
+		.find(|(_, c)| class(*c) != target)
+		.map_or(text.len(), |(ix, _)| column + ix);
+
+	start..end
+}
+
+fn unit_at(
+	document: &DiffDocument,
+	position: TextPosition,
+	granularity: Granularity,
+) -> Range<TextPosition> {
+	let text = document.line_text(position.row);
+	match granularity {
+		Granularity::Character => position..position,
+		Granularity::Word => {
+			let words = word_range(text, position.column);
+			TextPosition::new(position.row, words.start)..TextPosition::new(position.row, words.end)
+		}
+		Granularity::Line => {
+			let end = if position.row + 1 < document.len() {
+				TextPosition::new(position.row + 1, 0)
+			} else {
+				TextPosition::new(position.row, text.len())
+			};
+			TextPosition::new(position.row, 0)..end
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A header row, then " a", "-b", "+B" and " c"
+	fn document() -> DiffDocument {
+		DiffDocument::new("a\nb\nc\n", "a\nB\nc\n", 3)
+	}
+
+	fn rows(start: usize, end: usize) -> TextSelection {
+		TextSelection::from_range(TextPosition::new(start, 0), TextPosition::new(end, 0))
+	}
+
+	#[test]
+	fn patch_header_counts_the_selected_lines_of_each_side() {
+		let document = document();
+		assert_eq!(
+			rows(2, 4).text(&document, CopyMode::Patch),
+			"@@ -2,1 +2,1 @@\n-b\n+B\n"
+		);
+		// The header row is left out, and the context line counts on both sides
+		assert_eq!(
+			rows(0, 3).text(&document, CopyMode::Patch),
+			"@@ -1,2 +1,1 @@\n a\n-b\n"
+		);
+	}
+
+	#[test]
+	fn patch_of_a_side_without_lines_starts_at_the_line_before() {
+		let document = document();
+		assert_eq!(
+			rows(3, 4).text(&document, CopyMode::Patch),
+			"@@ -2,0 +2,1 @@\n+B\n"
+		);
+		assert_eq!(
+			rows(2, 3).text(&document, CopyMode::Patch),
+			"@@ -2,1 +1,0 @@\n-b\n"
+		);
+	}
+
+	#[test]
+	fn copy_modes_keep_the_rows_of_their_side() {
+		let document = document();
+		let selection = TextSelection::from_range(TextPosition::new(0, 0), TextPosition::new(4, 1));
+		assert_eq!(selection.text(&document, CopyMode::Displayed), "a\nb\nB\nc");
+		assert_eq!(selection.text(&document, CopyMode::NewSide), "a\nB\nc");
+		assert_eq!(selection.text(&document, CopyMode::OldSide), "a\nb\nc");
+
+		// Partial first and last rows keep only their selected columns
+		let document = DiffDocument::new("", "one two\nthree four\n", 3);
+		let selection = TextSelection::from_range(TextPosition::new(1, 4), TextPosition::new(2, 5));
+		assert_eq!(selection.text(&document, CopyMode::Displayed), "two\nthree");
+	}
+
+	#[test]
+	fn word_range_covers_runs_of_the_same_class() {
+		let text = "foo_bar  += wörld";
+		assert_eq!(word_range(text, 2), 0..7);
+		assert_eq!(word_range(text, 7), 7..9);
+		assert_eq!(word_range(text, 9), 9..11);
+		assert_eq!(word_range(text, 13), 12..18);
+		assert_eq!(word_range(text, text.len()), 18..18);
+		assert_eq!(word_range(text, 100), 18..18);
+	}
+
+	#[test]
+	fn extending_keeps_the_unit_first_clicked() {
+		let document = DiffDocument::new("", "one two three\n", 3);
+		let mut selection =
+			TextSelection::new(&document, TextPosition::new(1, 5), Granularity::Word);
+		assert_eq!((selection.start.column, selection.end.column), (4, 7));
+
+		selection.extend_to(&document, TextPosition::new(1, 9));
+		assert_eq!((selection.start.column, selection.end.column), (4, 13));
+
+		// Going back before the origin selects from there to the end of the origin
+		selection.extend_to(&document, TextPosition::new(1, 1));
+		assert_eq!((selection.start.column, selection.end.column), (0, 7));
+
+		let mut selection =
+			TextSelection::new(&document, TextPosition::new(1, 5), Granularity::Line);
+		assert_eq!(selection.end, TextPosition::new(1, 13));
+		selection.extend_to(&document, TextPosition::new(0, 0));
+		assert_eq!(selection.start, TextPosition::new(0, 0));
+		assert_eq!(selection.end, TextPosition::new(1, 13));
+	}
+}
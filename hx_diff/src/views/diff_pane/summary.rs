@@ -0,0 +1,351 @@
+// The summary page of a category or directory, listing its files with their line counts, which
+// are counted in the background
+use super::*;
+use similar::{ChangeTag, TextDiff};
+use std::path::PathBuf;
+use theme::ActiveTheme;
+
+/// Bytes git looks through for a NUL when telling whether a file is binary
+const BINARY_CHECK_LEN: usize = 8000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FileChange {
+	Added,
+	Deleted,
+	Modified,
+}
+
+#[derive(Clone, Copy)]
+enum LineCounts {
+	/// Not counted yet
+	Pending,
+	Lines {
+		added: usize,
+		removed: usize,
+	},
+	/// Binary files, and others which aren't UTF-8, have no lines to count
+	Binary,
+}
+
+struct FileSummary {
+	entry_id: ProjectEntryId,
+	path: PathBuf,
+	change: FileChange,
+	counts: LineCounts,
+}
+
+pub struct Summary {
+	title: SharedString,
+	files: Vec<FileSummary>,
+	/// Counts the files' lines, until it's dropped with the page
+	_counting: Option<Task<()>>,
+}
+
+/// Whether the source has no file, like the old side of an added one
+fn is_missing(file_entry: &FileEntry, source: &FileSource) -> bool {
+	match source {
+		FileSource::Empty => true,
+		FileSource::Working => !file_entry.path.exists(),
+		FileSource::Index(sha1) | FileSource::Head(sha1) | FileSource::Commit(sha1) => {
+			sha1.is_zero()
+		}
+	}
+}
+
+/// Text of one side of a file, or `None` when it's binary or can't be read
+fn read_text(file_entry: &FileEntry, source: &FileSource) -> Option<String> {
+	if is_missing(file_entry, source) {
+		return Some(String::new());
+	}
+	let bytes = match source {
+		FileSource::Empty => Vec::new(),
+		FileSource::Working => std::fs::read(&file_entry.path).ok()?,
+		FileSource::Index(sha1) | FileSource::Head(sha1) | FileSource::Commit(sha1) => {
+			git::get_file_bytes(&file_entry.path, sha1).ok()?
+		}
+	};
+	if bytes[..bytes.len().min(BINARY_CHECK_LEN)].contains(&0) {
+		return None;
+	}
+	String::from_utf8(bytes).ok()
+}
+
+fn count_lines(file_entry: &FileEntry) -> LineCounts {
+	let (Some(old_text), Some(new_text)) = (
+		read_text(file_entry, &file_entry.left_source),
+		read_text(file_entry, &file_entry.right_source),
+	) else {
+		return LineCounts::Binary;
+	};
+
+	let (mut added, mut removed) = (0, 0);
+	for change in TextDiff::from_lines(&old_text, &new_text).iter_all_changes() {
+		match change.tag() {
+			ChangeTag::Insert => added += 1,
+			ChangeTag::Delete => removed += 1,
+			ChangeTag::Equal => {}
+		}
+	}
+	LineCounts::Lines { added, removed }
+}
+
+impl FileSummary {
+	fn new(entry_id: ProjectEntryId, file_entry: &FileEntry) -> Self {
+		let change = if is_missing(file_entry, &file_entry.left_source) {
+			FileChange::Added
+		} else if is_missing(file_entry, &file_entry.right_source) {
+			FileChange::Deleted
+		} else {
+			FileChange::Modified
+		};
+
+		Self {
+			entry_id,
+			path: file_entry.path.clone(),
+			change,
+			counts: LineCounts::Pending,
+		}
+	}
+
+	/// Lines added and removed, none while they're being counted or for binary files
+	fn added_removed(&self) -> (usize, usize) {
+		match self.counts {
+			LineCounts::Lines { added, removed } => (added, removed),
+			LineCounts::Pending | LineCounts::Binary => (0, 0),
+		}
+	}
+}
+
+impl Summary {
+	/// Summarizes the files listed under a category or directory entry: those after it, up to the
+	/// next category, or the next directory for a directory. Returns the files too, for counting
+	/// their lines.
+	fn new(workspace: &Workspace, id: ProjectEntryId) -> (Self, Vec<FileEntry>) {
+		let Some(ix) = workspace.entries.iter().position(|entry| entry.id == id) else {
+			let summary = Self {
+				title: SharedString::default(),
+				files: Vec::new(),
+				_counting: None,
+			};
+			return (summary, Vec::new());
+		};
+
+		let entry = &workspace.entries[ix];
+		let is_directory = matches!(entry.kind, EntryKind::Directory(_));
+		let title = match entry.kind {
+			EntryKind::Category(CategoryKind::Staged) => "Staged changes".into(),
+			EntryKind::Category(CategoryKind::Working) => "Unstaged changes".into(),
+			EntryKind::Category(CategoryKind::Commit) => "Changes".into(),
+			_ => crate::common::clean_path_display(&entry.path).into(),
+		};
+
+		let (files, file_entries) = workspace.entries[ix + 1..]
+			.iter()
+			.take_while(|entry| match entry.kind {
+				EntryKind::Category(_) => false,
+				EntryKind::Directory(_) => !is_directory,
+				EntryKind::File(_) => true,
+			})
+			.filter_map(|entry| match entry.kind {
+				EntryKind::File(ref file_entry) => {
+					Some((FileSummary::new(entry.id, file_entry), file_entry.clone()))
+				}
+				_ => None,
+			})
+			.unzip();
+
+		let summary = Self {
+			title,
+			files,
+			_counting: None,
+		};
+		(summary, file_entries)
+	}
+}
+
+impl DiffPane {
+	/// Shows the summary page of a category or directory, and counts its files' lines in the
+	/// background
+	pub(super) fn open_summary(&mut self, id: ProjectEntryId, cx: &mut Context<Self>) {
+		let (mut summary, file_entries) = Summary::new(self.workspace.read(cx), id);
+		summary._counting = Some(cx.spawn(async move |this, cx| {
+			let counts = cx
+				.background_spawn(async move {
+					file_entries.iter().map(count_lines).collect::<Vec<_>>()
+				})
+				.await;
+			this.update(cx, |this, cx| {
+				if let Page::Summary(summary) = &mut this.page {
+					for (file, counts) in summary.files.iter_mut().zip(counts) {
+						file.counts = counts;
+					}
+					cx.notify();
+				}
+			})
+			.ok();
+		}));
+
+		self.clear_document(cx);
+		self.page = Page::Summary(summary);
+		cx.notify();
+	}
+
+	pub(super) fn render_summary(&self, summary: &Summary, cx: &Context<Self>) -> impl IntoElement {
+		let colors = cx.theme().colors();
+		let status = cx.theme().status();
+
+		let (total_added, total_removed) = summary
+			.files
+			.iter()
+			.map(FileSummary::added_removed)
+			.fold((0, 0), |(total_added, total_removed), (added, removed)| {
+				(total_added + added, total_removed + removed)
+			});
+		let is_counting = summary
+			.files
+			.iter()
+			.any(|file| matches!(file.counts, LineCounts::Pending));
+		// Churn bars are scaled to the file with the most changed lines
+		let max_churn = summary
+			.files
+			.iter()
+			.map(|file| {
+				let (added, removed) = file.added_removed();
+				added + removed
+			})
+			.max()
+			.unwrap_or(0)
+			.max(1) as f32;
+
+		// Paths are shown relative to the working directory where possible
+		let current_dir = std::env::current_dir().ok();
+		let display_path = |path: &std::path::Path| {
+			current_dir
+				.as_ref()
+				.and_then(|dir| path.strip_prefix(dir).ok())
+				.map_or_else(
+					|| crate::common::clean_path_display(path),
+					|path| path.to_string_lossy().into_owned(),
+				)
+		};
+
+		let rows = summary.files.iter().enumerate().map(|(ix, file)| {
+			let entry_id = file.entry_id;
+			let (added, removed) = file.added_removed();
+			let (change, change_color) = match file.change {
+				FileChange::Added => ("Added", status.created),
+				FileChange::Deleted => ("Deleted", status.deleted),
+				FileChange::Modified => ("Modified", status.modified),
+			};
+
+			div()
+				.id(("summary-file", ix))
+				.flex()
+				.flex_row()
+				.items_center()
+				.gap_3()
+				.px_2()
+				.py_0p5()
+				.rounded_sm()
+				.cursor_pointer()
+				.hover(|style| style.bg(colors.element_hover))
+				.on_click(cx.listener(move |_, _: &ClickEvent, _window, cx| {
+					cx.emit(DiffPaneEvent::OpenedEntry { entry_id });
+				}))
+				.child(
+					div()
+						.w(px(72.))
+						.flex_none()
+						.text_color(change_color)
+						.child(change),
+				)
+				.child(
+					div()
+						.flex_grow()
+						.overflow_hidden()
+						.text_color(colors.text)
+						.child(display_path(&file.path)),
+				)
+				.child(
+					div()
+						.flex()
+						.flex_row()
+						.justify_end()
+						.gap_1()
+						.w(px(96.))
+						.flex_none()
+						.map(|counts| match file.counts {
+							LineCounts::Lines { added, removed } => counts
+								.child(
+									div()
+										.text_color(status.created)
+										.child(format!("+{}", added)),
+								)
+								.child(
+									div()
+										.text_color(status.deleted)
+										.child(format!("−{}", removed)),
+								),
+							LineCounts::Pending => counts.text_color(colors.text_muted).child("…"),
+							LineCounts::Binary => {
+								counts.text_color(colors.text_muted).child("Binary")
+							}
+						}),
+				)
+				.child(
+					div()
+						.flex()
+						.flex_row()
+						.w(px(120.))
+						.h(px(8.))
+						.flex_none()
+						.rounded_sm()
+						.overflow_hidden()
+						.bg(colors.element_background)
+						.child(
+							div()
+								.h_full()
+								.w(relative(added as f32 / max_churn))
+								.bg(status.created),
+						)
+						.child(
+							div()
+								.h_full()
+								.w(relative(removed as f32 / max_churn))
+								.bg(status.deleted),
+						),
+				)
+		});
+
+		div()
+			.id("summary")
+			.size_full()
+			.overflow_y_scroll()
+			.p_4()
+			.flex()
+			.flex_col()
+			.gap_1()
+			.bg(colors.editor_background)
+			.text_sm()
+			.child(
+				div()
+					.text_lg()
+					.font_weight(FontWeight::BOLD)
+					.text_color(colors.text)
+					.child(summary.title.clone()),
+			)
+			.child(div().pb_2().text_color(colors.text_muted).child(format!(
+				"{} {}, +{} −{}{}",
+				summary.files.len(),
+				if summary.files.len() == 1 {
+					"file"
+				} else {
+					"files"
+				},
+				total_added,
+				total_removed,
+				if is_counting { " so far" } else { "" }
+			)))
+			.children(rows)
+	}
+}
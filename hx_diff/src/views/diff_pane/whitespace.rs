@@ -0,0 +1,46 @@
+// Locating the whitespace drawn by the "show whitespace" mode
+use crate::editorconfig::IndentStyle;
+use std::ops::Range;
+
+/// Byte offsets of the whitespace in one line
+#[derive(Debug, Default, PartialEq)]
+pub struct Whitespace {
+	pub tabs: Vec<usize>,
+	pub spaces: Vec<usize>,
+	pub trailing: Option<Range<usize>>,
+	/// Indentation which doesn't follow the file's indent style
+	pub wrong_indent: Option<Range<usize>>,
+}
+
+pub fn find_whitespace(text: &str, indent_style: Option<IndentStyle>) -> Whitespace {
+	let mut whitespace = Whitespace::default();
+	for (ix, ch) in text.char_indices() {
+		match ch {
+			'\t' => whitespace.tabs.push(ix),
+			' ' => whitespace.spaces.push(ix),
+			_ => {}
+		}
+	}
+
+	let content_end = text.trim_end_matches([' ', '\t']).len();
+	if content_end < text.len() {
+		whitespace.trailing = Some(content_end..text.len());
+	}
+
+	// Blank lines are already covered by the trailing whitespace
+	let indent_end = text.len() - text.trim_start_matches([' ', '\t']).len();
+	if indent_end < text.len() {
+		let indent = &text[..indent_end];
+		let is_wrong = match indent_style {
+			// Spaces after tabs are fine for alignment, but not in place of them
+			Some(IndentStyle::Tab) => indent.starts_with(' '),
+			Some(IndentStyle::Space) => indent.contains('\t'),
+			None => false,
+		};
+		if is_wrong {
+			whitespace.wrong_indent = Some(0..indent_end);
+		}
+	}
+
+	whitespace
+}
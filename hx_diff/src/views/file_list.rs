@@ -1,5 +1,8 @@
+use crate::external_editor::{self, EditorLocation};
+use crate::user_settings::HxDiffSettings;
 use crate::*;
 use git_cli_wrap as git;
+use gpui::prelude::*;
 use gpui::*;
 use hx_diff::{DraggedPanel, PanelPosition};
 use std::ops::Range;
@@ -46,9 +49,10 @@ pub struct FileList {
 	context_menu: Option<(Entity<ui::ContextMenu>, gpui::Point<Pixels>, Subscription)>,
 	focus_handle: FocusHandle,
 	selection: Option<Selection>,
+	scroll_handle: UniformListScrollHandle,
 }
 
-actions!(file_list, [CopyPath, StageFile, UnstageFile,]);
+actions!(file_list, [CopyPath, StageFile, UnstageFile, OpenInEditor,]);
 
 impl FileList {
 	fn refresh_from_workspace(&mut self, workspace: &Workspace) {
@@ -71,7 +75,10 @@ impl FileList {
 						"UNSTAGED - Changes not staged for commit".into()
 					}
 					EntryKind::Category(workspace::CategoryKind::Commit) => {
-						"Commit Details Here".into()
+						match &workspace.commit {
+							Some(commit) => format!("Commit - {}", commit.subject()).into(),
+							None => "Commit Details Here".into(),
+						}
 					}
 					EntryKind::Directory(_) => {
 						crate::common::clean_path_display(&entry.path).into()
@@ -134,6 +141,7 @@ impl FileList {
 				model,
 				workspace,
 				selection: None,
+				scroll_handle: UniformListScrollHandle::new(),
 			};
 
 			file_list.refresh_from_workspace(file_list.workspace.read(cx));
@@ -183,6 +191,9 @@ impl FileList {
 				}
 			}
 
+			if matches!(entry.kind, EntryKind::File(_)) {
+				menu = menu.action("Open in Editor", Box::new(OpenInEditor));
+			}
 			menu = menu.action("Copy Path", Box::new(CopyPath));
 			menu
 		});
@@ -206,6 +217,16 @@ impl FileList {
 		self.context_menu = Some((context_menu, position, subscription));
 	}
 
+	/// Selects the entry shown in the diff pane and scrolls it into view
+	pub fn select_entry(&mut self, entry_id: ProjectEntryId, cx: &mut Context<Self>) {
+		self.selection = Some(Selection { entry_id });
+		if let Some(ix) = self.items.iter().position(|item| item.entry_id == entry_id) {
+			self.scroll_handle
+				.scroll_to_item(ix, ScrollStrategy::Center);
+		}
+		cx.notify();
+	}
+
 	fn selected_entry_handle<'a>(&self, cx: &'a App) -> Option<&'a Entry> {
 		let selection = self.selection?;
 		let entry = self.workspace.read(cx).get_entry(selection.entry_id)?;
@@ -217,7 +238,7 @@ impl FileList {
 		Some(entry)
 	}
 
-	fn copy_path(&mut self, _: &CopyPath, _window: &mut Window, cx: &mut Context<Self>) {
+	pub(crate) fn copy_path(&mut self, _: &CopyPath, _window: &mut Window, cx: &mut Context<Self>) {
 		if let Some(entry) = self.selected_entry(cx) {
 			cx.write_to_clipboard(ClipboardItem::new_string(
 				entry.path.to_string_lossy().to_string(),
@@ -225,14 +246,49 @@ impl FileList {
 		}
 	}
 
-	fn stage_file(&mut self, _: &StageFile, _window: &mut Window, cx: &mut Context<Self>) {
+	pub(crate) fn open_in_editor(
+		&mut self,
+		_: &OpenInEditor,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		let Some(entry) = self
+			.selected_entry(cx)
+			.filter(|entry| matches!(entry.kind, EntryKind::File(_)))
+		else {
+			return;
+		};
+
+		let location = EditorLocation::new(&entry.path, 1, 1);
+		if let Err(error) = external_editor::open_in_editor(&location) {
+			let _ = window.prompt(
+				PromptLevel::Critical,
+				"Failed to open the editor",
+				Some(&error.to_string()),
+				&["OK"],
+				cx,
+			);
+		}
+	}
+
+	pub(crate) fn stage_file(
+		&mut self,
+		_: &StageFile,
+		_window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
 		if let Some(entry) = self.selected_entry(cx) {
 			git::stage_file(&entry.path.to_string_lossy()).expect("Failed to stage file");
 			// TODO: Trigger reload/invalidate workspace
 		}
 	}
 
-	fn unstage_file(&mut self, _: &UnstageFile, _window: &mut Window, cx: &mut Context<Self>) {
+	pub(crate) fn unstage_file(
+		&mut self,
+		_: &UnstageFile,
+		_window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
 		if let Some(entry) = self.selected_entry(cx) {
 			git::unstage_file(&entry.path.to_string_lossy()).expect("Failed to unstage file");
 			// TODO: Trigger reload/invalidate workspace
@@ -259,6 +315,9 @@ impl FileList {
 		};
 
 		let id = item.entry_id;
+		let is_selected = self
+			.selection
+			.is_some_and(|selection| selection.entry_id == id);
 
 		ui::ListItem::new(index)
 			.child(
@@ -268,12 +327,16 @@ impl FileList {
 					.w_full()
 					.px_2()
 					.text_color(text_color)
+					.when(is_selected, |div| {
+						div.bg(cx.theme().colors().element_selected)
+					})
 					.id(id.to_usize())
 					.on_click(
-						cx.listener(move |_this, _event: &gpui::ClickEvent, _window, cx| {
-							if item_type == ListItemType::File {
-								cx.emit(FileListEvent::OpenedEntry { entry_id: id });
-							}
+						cx.listener(move |this, _event: &gpui::ClickEvent, _window, cx| {
+							// Categories and directories open a summary of their files
+							this.selection = Some(Selection { entry_id: id });
+							cx.emit(FileListEvent::OpenedEntry { entry_id: id });
+							cx.notify();
 						}),
 					)
 					.child(
@@ -327,15 +390,17 @@ impl Render for FileList {
 			.occlude();
 
 		div()
+			.key_context("FileList")
 			.flex()
 			.flex_col()
-			.w(width.unwrap_or(px(300.)))
+			.w(width.unwrap_or(px(HxDiffSettings::get(cx).file_list_width)))
 			.gap(px(1.))
 			.border_r_1()
 			.border_color(cx.theme().colors().border)
 			.bg(cx.theme().colors().panel_background)
 			.gap(rems(0.3))
 			.on_action(cx.listener(Self::copy_path))
+			.on_action(cx.listener(Self::open_in_editor))
 			.on_action(cx.listener(Self::stage_file))
 			.on_action(cx.listener(Self::unstage_file))
 			.on_action(cx.listener(Self::refresh_list))
@@ -357,6 +422,7 @@ impl Render for FileList {
 							.collect()
 					})
 				})
+				.track_scroll(&self.scroll_handle)
 				.size_full(),
 			)
 			.child(handle)
@@ -3,7 +3,13 @@ use gpui::prelude::*;
 use gpui::*;
 use theme::ActiveTheme;
 
+use crate::command_palette::{self, CommandPaletteEvent};
+use crate::common::watch_file;
+use crate::file_list::{CopyPath, OpenInEditor, StageFile, UnstageFile};
+use crate::user_settings::{self, HxDiffSettings, ViewMode};
 use crate::workspace::*;
+use crate::{keymap, paths};
+use std::path::Path;
 
 #[derive(Clone)]
 pub enum PanelPosition {
@@ -14,11 +20,26 @@ pub enum PanelPosition {
 #[derive(Clone, Render)]
 pub struct DraggedPanel(pub PanelPosition);
 
+/// Where the selection goes in a newly opened file
+#[derive(Clone, Copy, PartialEq)]
+enum StartAt {
+	FirstDifference,
+	/// Used when stepping back into a file from the one after it
+	LastDifference,
+}
+
 pub struct HxDiff {
 	_weak_self: WeakEntity<Self>,
 	file_pane: Entity<FileList>,
 	diff_pane: Entity<DiffPane>,
-	_workspace: Entity<Workspace>,
+	workspace: Entity<Workspace>,
+	/// Entry shown in the diff pane
+	active_entry: Option<ProjectEntryId>,
+	/// Open palette, with the focus to return to once it closes
+	command_palette: Option<(Entity<CommandPalette>, FocusHandle, Subscription)>,
+	/// Poll the settings and keymap files for changes
+	_settings_watcher: Task<()>,
+	_keymap_watcher: Task<()>,
 }
 
 impl HxDiff {
@@ -32,7 +53,28 @@ impl HxDiff {
 			cx.subscribe_in(&file_pane, window, {
 				move |hx_diff, _, event, window, cx| match event {
 					&FileListEvent::OpenedEntry { entry_id } => {
-						hx_diff.open_file(entry_id, window, cx);
+						hx_diff.open_file(entry_id, StartAt::FirstDifference, window, cx);
+					}
+				}
+			})
+			.detach();
+
+			// Stepping past the first or last change continues in the neighbouring file
+			cx.subscribe_in(&diff_pane, window, {
+				move |hx_diff, _, event, window, cx| match event {
+					DiffPaneEvent::PassedLastDifference => {
+						hx_diff.open_adjacent_file(true, window, cx);
+					}
+					DiffPaneEvent::PassedFirstDifference => {
+						hx_diff.open_adjacent_file(false, window, cx);
+					}
+					&DiffPaneEvent::OpenedEntry { entry_id } => {
+						hx_diff.open_file(entry_id, StartAt::FirstDifference, window, cx);
+					}
+					&DiffPaneEvent::ScrolledToEntry { entry_id } => {
+						hx_diff.file_pane.update(cx, |file_pane, cx| {
+							file_pane.select_entry(entry_id, cx);
+						});
 					}
 				}
 			})
@@ -42,11 +84,29 @@ impl HxDiff {
 
 			cx.focus_view(&diff_pane, window);
 
+			// Closing the window asks about unsaved edits first
+			window.on_window_should_close(cx, {
+				let weak_handle = weak_handle.clone();
+				move |window, cx| {
+					weak_handle
+						.update(cx, |hx_diff, cx| hx_diff.should_close(window, cx))
+						.unwrap_or(true)
+				}
+			});
+
+			if HxDiffSettings::get(cx).view_mode == ViewMode::Review {
+				diff_pane.update(cx, |diff_pane, cx| diff_pane.set_reviewing(true, cx));
+			}
+
 			HxDiff {
 				_weak_self: weak_handle,
 				file_pane,
 				diff_pane,
-				_workspace: workspace.clone(),
+				workspace: workspace.clone(),
+				active_entry: None,
+				command_palette: None,
+				_settings_watcher: watch_file(paths::settings_file(), user_settings::reload, cx),
+				_keymap_watcher: watch_file(paths::keymap_file(), keymap::load, cx),
 			}
 		});
 		hxdiff_view
@@ -56,25 +116,303 @@ impl HxDiff {
 	// 	self._weak_self.clone()
 	// }
 
-	fn open_file(&mut self, id: ProjectEntryId, window: &mut Window, cx: &mut Context<Self>) {
+	fn open_file(
+		&mut self,
+		id: ProjectEntryId,
+		start_at: StartAt,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		// The review stream already has every file, so it only needs to scroll
+		if self.diff_pane.read(cx).is_reviewing() {
+			self.diff_pane
+				.update(cx, |diff_pane, cx| diff_pane.reveal_in_review(id, cx));
+			return;
+		}
+
+		// The panes of files edited in the review stream stay as they are
+		let dirty_panes = self
+			.diff_pane
+			.read(cx)
+			.is_dirty()
+			.then(|| self.diff_pane.clone())
+			.into_iter()
+			.collect();
+		self.confirm_saving(dirty_panes, window, cx, move |this, window, cx| {
+			this.show_file(id, start_at, window, cx);
+		});
+	}
+
+	/// Panes with unsaved edits: the open file's, then those of files edited in the review stream
+	fn dirty_panes(&self, cx: &App) -> Vec<Entity<DiffPane>> {
+		let diff_pane = self.diff_pane.read(cx);
+		diff_pane
+			.is_dirty()
+			.then(|| self.diff_pane.clone())
+			.into_iter()
+			.chain(diff_pane.dirty_section_panes(cx))
+			.collect()
+	}
+
+	/// Whether any file has edits which haven't been saved
+	pub fn is_dirty(&self, cx: &App) -> bool {
+		!self.dirty_panes(cx).is_empty()
+	}
+
+	/// Lets the window close straight away unless files have unsaved edits, which are asked
+	/// about first
+	fn should_close(&mut self, window: &mut Window, cx: &mut Context<Self>) -> bool {
+		if !self.is_dirty(cx) {
+			return true;
+		}
+		self.confirm_leaving_edits(window, cx, |_, window, _| window.remove_window());
+		false
+	}
+
+	/// Runs `then` once the unsaved edits of every file are saved, or the user chose to drop
+	/// them. Cancelling, or a save which fails, keeps the files open.
+	pub fn confirm_leaving_edits(
+		&mut self,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+		then: impl FnOnce(&mut Self, &mut Window, &mut Context<Self>) + 'static,
+	) {
+		let dirty_panes = self.dirty_panes(cx);
+		self.confirm_saving(dirty_panes, window, cx, then);
+	}
+
+	/// Asks whether to save the edits of each of `panes` in turn, then runs `then`. Cancelling,
+	/// or a save which fails, stops there.
+	fn confirm_saving(
+		&mut self,
+		mut panes: Vec<Entity<DiffPane>>,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+		then: impl FnOnce(&mut Self, &mut Window, &mut Context<Self>) + 'static,
+	) {
+		if panes.is_empty() {
+			then(self, window, cx);
+			return;
+		}
+		let pane = panes.remove(0);
+		let Some(dirty_path) = pane.read(cx).editing_path().map(Path::to_path_buf) else {
+			self.confirm_saving(panes, window, cx, then);
+			return;
+		};
+
+		let message = format!(
+			"Save changes to {}?",
+			crate::common::clean_path_display(&dirty_path)
+		);
+		let answer = window.prompt(
+			PromptLevel::Warning,
+			&message,
+			Some("Your changes will be lost if you don't save them."),
+			&["Save", "Don't Save", "Cancel"],
+			cx,
+		);
+
+		cx.spawn_in(window, async move |this, cx| {
+			let answer = answer.await.ok();
+			this.update_in(cx, |this, window, cx| {
+				match answer {
+					Some(0) => {
+						let saved = pane.update(cx, |diff_pane, cx| diff_pane.save_file(cx));
+						if let Err(error) = saved {
+							let _ = window.prompt(
+								PromptLevel::Critical,
+								"Failed to save the file",
+								Some(&error.to_string()),
+								&["OK"],
+								cx,
+							);
+							this.sync_file_list(cx);
+							return;
+						}
+					}
+					Some(1) => {}
+					// The file list may have moved on, so point it back at the open file
+					_ => {
+						this.sync_file_list(cx);
+						return;
+					}
+				}
+				this.confirm_saving(panes, window, cx, then);
+			})
+			.ok();
+		})
+		.detach();
+	}
+
+	fn show_file(
+		&mut self,
+		id: ProjectEntryId,
+		start_at: StartAt,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		self.active_entry = Some(id);
 		self.diff_pane.update(cx, |diff_pane, cx| {
 			diff_pane.open_diff(id, window, cx);
+			if start_at == StartAt::LastDifference {
+				diff_pane.select_last_difference(window, cx);
+			}
 		});
+		self.sync_file_list(cx);
+	}
+
+	fn sync_file_list(&mut self, cx: &mut Context<Self>) {
+		if let Some(id) = self.active_entry {
+			self.file_pane
+				.update(cx, |file_pane, cx| file_pane.select_entry(id, cx));
+		}
+	}
+
+	/// The file entry before or after the open one, in workspace order. From a category or
+	/// directory this is the file listed next to it. With nothing open yet, stepping forward
+	/// starts at the first file.
+	fn adjacent_file(&self, forward: bool, cx: &App) -> Option<ProjectEntryId> {
+		let entries = &self.workspace.read(cx).entries;
+		let is_file = |entry: &&Entry| matches!(entry.kind, EntryKind::File(_));
+
+		let current = self
+			.active_entry
+			.and_then(|active| entries.iter().position(|entry| entry.id == active));
+		let file = match (current, forward) {
+			(Some(ix), true) => entries[ix + 1..].iter().find(is_file),
+			(Some(ix), false) => entries[..ix].iter().rev().find(is_file),
+			(None, true) => entries.iter().find(is_file),
+			(None, false) => None,
+		};
+		file.map(|entry| entry.id)
+	}
+
+	fn open_adjacent_file(&mut self, forward: bool, window: &mut Window, cx: &mut Context<Self>) {
+		let start_at = if forward {
+			StartAt::FirstDifference
+		} else {
+			StartAt::LastDifference
+		};
+		if let Some(id) = self.adjacent_file(forward, cx) {
+			self.open_file(id, start_at, window, cx);
+		}
 	}
 
 	fn refresh_list(&mut self, _: &RefreshFileList, _window: &mut Window, _cx: &mut Context<Self>) {
 		println!("HxDiff: Refresh File List!");
 	}
+
+	fn toggle_command_palette(
+		&mut self,
+		_: &command_palette::Toggle,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		if self.command_palette.is_some() {
+			self.dismiss_command_palette(window, cx);
+			return;
+		}
+		let palette = CommandPalette::commands(window, cx);
+		self.show_command_palette(palette, window, cx);
+	}
+
+	fn deploy_go_to_file(
+		&mut self,
+		_: &command_palette::GoToFile,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		let palette = CommandPalette::files(self.workspace.read(cx), cx);
+		self.show_command_palette(palette, window, cx);
+	}
+
+	fn show_command_palette(
+		&mut self,
+		palette: Entity<CommandPalette>,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		// Going to a file from the commands replaces them, but still returns to where they
+		// were opened from
+		let previous_focus = match self.command_palette.take() {
+			Some((_, previous_focus, _)) => previous_focus,
+			None => window
+				.focused(cx)
+				.unwrap_or_else(|| self.diff_pane.focus_handle(cx)),
+		};
+
+		let subscription =
+			cx.subscribe_in(&palette, window, |this, _, event, window, cx| match event {
+				CommandPaletteEvent::Dismissed => this.dismiss_command_palette(window, cx),
+				CommandPaletteEvent::RunAction(action) => {
+					let action = action.boxed_clone();
+					this.dismiss_command_palette(window, cx);
+					window.dispatch_action(action, cx);
+				}
+				&CommandPaletteEvent::OpenedEntry { entry_id } => {
+					this.dismiss_command_palette(window, cx);
+					this.open_file(entry_id, StartAt::FirstDifference, window, cx);
+				}
+			});
+
+		window.focus(&palette.focus_handle(cx));
+		self.command_palette = Some((palette, previous_focus, subscription));
+		cx.notify();
+	}
+
+	fn dismiss_command_palette(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+		if let Some((_, previous_focus, _)) = self.command_palette.take() {
+			window.focus(&previous_focus);
+		}
+		cx.notify();
+	}
+
+	// The file list doesn't take the focus yet, so its actions are handled here on behalf of its
+	// selection, which follows the open file
+
+	fn copy_path(&mut self, action: &CopyPath, window: &mut Window, cx: &mut Context<Self>) {
+		self.file_pane
+			.update(cx, |file_pane, cx| file_pane.copy_path(action, window, cx));
+	}
+
+	fn open_selected_in_editor(
+		&mut self,
+		action: &OpenInEditor,
+		window: &mut Window,
+		cx: &mut Context<Self>,
+	) {
+		self.file_pane.update(cx, |file_pane, cx| {
+			file_pane.open_in_editor(action, window, cx)
+		});
+	}
+
+	fn stage_file(&mut self, action: &StageFile, window: &mut Window, cx: &mut Context<Self>) {
+		self.file_pane
+			.update(cx, |file_pane, cx| file_pane.stage_file(action, window, cx));
+	}
+
+	fn unstage_file(&mut self, action: &UnstageFile, window: &mut Window, cx: &mut Context<Self>) {
+		self.file_pane.update(cx, |file_pane, cx| {
+			file_pane.unstage_file(action, window, cx)
+		});
+	}
 }
 
 impl Render for HxDiff {
 	fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
 		div()
+			.key_context("HxDiff")
 			.size_full()
 			.flex()
 			.flex_col()
 			.text_color(cx.theme().colors().text)
 			.on_action(cx.listener(Self::refresh_list))
+			.on_action(cx.listener(Self::toggle_command_palette))
+			.on_action(cx.listener(Self::deploy_go_to_file))
+			.on_action(cx.listener(Self::copy_path))
+			.on_action(cx.listener(Self::open_selected_in_editor))
+			.on_action(cx.listener(Self::stage_file))
+			.on_action(cx.listener(Self::unstage_file))
 			.child(
 				div() // main status bar
 					.flex()
@@ -97,6 +435,18 @@ impl Render for HxDiff {
 					.child(self.file_pane.clone())
 					.child(div().flex_grow().child(self.diff_pane.clone())),
 			)
+			.children(self.command_palette.as_ref().map(|(palette, _, _)| {
+				// Floats over the top of the window, above the panes
+				deferred(
+					div()
+						.absolute()
+						.top(px(48.))
+						.w_full()
+						.flex()
+						.justify_center()
+						.child(palette.clone()),
+				)
+			}))
 		// .child(
 		// 	div() // Status bar - Nothing useful here yet
 		// 		.h(px(30.0))
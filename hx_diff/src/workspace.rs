@@ -1,6 +1,6 @@
 // Functions for managing the main state of the application
 // Including all scanned files, app query parameters, etc.
-use crate::Args;
+use crate::cli::{Args, Command};
 use core::sync::atomic::AtomicUsize;
 use core::sync::atomic::Ordering::SeqCst;
 use git_cli_wrap as git;
@@ -21,6 +21,7 @@ impl ProjectEntryId {
 	}
 }
 
+#[derive(Clone)]
 pub enum FileSource {
 	// TODO, name
 	Empty,
@@ -64,6 +65,7 @@ pub enum CategoryKind {
 	Commit,
 }
 
+#[derive(Clone)]
 pub struct FileEntry {
 	pub path: PathBuf,
 	pub left_source: FileSource,
@@ -100,6 +102,8 @@ pub struct Workspace {
 	// entries: HashMap<ProjectEntryId, Entry>,
 	pub mode: WorkspaceMode,
 	pub entries: Vec<Entry>,
+	/// The commit being shown in `show` mode
+	pub commit: Option<git::CommitDetails>,
 }
 
 impl Workspace {
@@ -108,28 +112,17 @@ impl Workspace {
 		self.entries.iter().find(|entry| entry.id == id)
 	}
 
-	pub fn from_args(args: &Args) -> Self {
-		match args.mode.as_deref() {
-			None | Some("status") => Self::for_git_status(),
-			Some("diff") => Self::for_git_diff(args),
-			Some("show") => Self::for_git_show(
-				&args
-					.arg
-					.as_ref()
-					.expect("Missing commit argument for 'show'"),
-			),
-			_ => panic!("Invalid mode"),
+	/// Fails when git does, or its output can't be read
+	pub fn from_args(args: &Args) -> Result<Self, git::GitError> {
+		match &args.command {
+			None | Some(Command::Status) => Self::for_git_status(),
+			Some(Command::Diff(diff)) => Self::for_git_diff(diff.options()),
+			Some(Command::Show(show)) => Self::for_git_show(&show.rev),
 		}
 	}
 
-	pub fn for_git_diff(args: &Args) -> Self {
-		let diff_options = git::DiffOptions {
-			merge_base: args.merge_base,
-			cached: args.cached || args.staged,
-			commit: args.arg.clone(),
-		};
-
-		let git_diff = git::get_diff(&diff_options).expect("Failed to get git diff");
+	pub fn for_git_diff(diff_options: git::DiffOptions) -> Result<Self, git::GitError> {
+		let git_diff = git::get_diff(&diff_options)?;
 
 		let counter = AtomicUsize::new(0);
 		let mut entries = Vec::new();
@@ -156,14 +149,15 @@ impl Workspace {
 			});
 		}
 
-		return Workspace {
+		Ok(Workspace {
 			mode: WorkspaceMode::GitDiff(diff_options),
 			entries,
-		};
+			commit: None,
+		})
 	}
 
-	pub fn for_git_show(commit: &str) -> Self {
-		let git_show = git::show(commit).expect("Failed to get git show");
+	pub fn for_git_show(commit: &str) -> Result<Self, git::GitError> {
+		let git_show = git::show(commit)?;
 
 		let counter = AtomicUsize::new(0);
 		let mut entries = Vec::new();
@@ -190,14 +184,15 @@ impl Workspace {
 			});
 		}
 
-		return Workspace {
+		Ok(Workspace {
 			mode: WorkspaceMode::GitShow(commit.to_owned()),
 			entries,
-		};
+			commit: Some(git_show.commit),
+		})
 	}
 
-	pub fn for_git_status() -> Self {
-		let git_status = git::get_status().expect("Failed to get git status");
+	pub fn for_git_status() -> Result<Self, git::GitError> {
+		let git_status = git::get_status()?;
 
 		let counter = AtomicUsize::new(0);
 		let mut entries = Vec::new();
@@ -274,9 +269,10 @@ impl Workspace {
 			"WORKING - Changes not staged for commit",
 		);
 
-		return Workspace {
+		Ok(Workspace {
 			mode: WorkspaceMode::GitStatus,
 			entries,
-		};
+			commit: None,
+		})
 	}
 }